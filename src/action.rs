@@ -0,0 +1,349 @@
+//! Actions applied to the files matched by a scan: copy, move, delete, change permissions or run a command
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use color_print::cformat;
+use crate::criteria::{ActionMode, Criteria};
+use crate::errors::{FileError, FileOperation};
+use crate::resource_row::{ResourceRow, ResourceTree};
+use crate::manage::{PermissionChange, target_file_path, transfer_file};
+use crate::exec::ExecCommand;
+use crate::transfer::{Throttle, TransferOptions, start_job};
+use crate::job::{Job, JobEntry};
+use crate::progress::Progress;
+use crate::interrupt::is_interrupted;
+use crate::file_system::{FileEntry, FileSystem};
+use crate::utils::{pluralize_64, smart_size};
+
+/// An action on matched files. Copy and move keep the path of each file relative to the scanned root.
+/// Commands and permission changes always apply to files on disk.
+#[derive(Debug, Clone)]
+pub enum Action {
+  Copy(PathBuf),
+  Move(PathBuf),
+  Delete,
+  Permissions(PermissionChange),
+  Exec(ExecCommand),
+}
+
+impl Action {
+  /// The action selected by the criteria, or None when only listing
+  pub fn from_criteria(criteria: &Criteria) -> Option<Action> {
+    let target = criteria.target.as_ref().map(PathBuf::from).unwrap_or_default();
+    match criteria.action {
+      ActionMode::Copy => Some(Action::Copy(target)),
+      ActionMode::Move => Some(Action::Move(target)),
+      ActionMode::Delete | ActionMode::DirectDelete => Some(Action::Delete),
+      ActionMode::Permissions | ActionMode::DirectPermissions => criteria.permission_change.clone().map(Action::Permissions),
+      ActionMode::Exec => criteria.exec.clone().map(Action::Exec),
+      ActionMode::List => None,
+    }
+  }
+
+  /// Copy or move into a subfolder of the target, e.g. one per scanned root
+  pub fn in_folder(&self, folder: &str) -> Action {
+    match self {
+      Action::Copy(target) => Action::Copy(target.join(folder)),
+      Action::Move(target) => Action::Move(target.join(folder)),
+      _ => self.clone(),
+    }
+  }
+
+  pub fn mode(&self) -> ActionMode {
+    match self {
+      Action::Copy(_) => ActionMode::Copy,
+      Action::Move(_) => ActionMode::Move,
+      Action::Delete => ActionMode::Delete,
+      Action::Permissions(_) => ActionMode::Permissions,
+      Action::Exec(_) => ActionMode::Exec,
+    }
+  }
+
+  pub fn operation(&self) -> FileOperation {
+    match self {
+      Action::Copy(_) => FileOperation::Copy,
+      Action::Move(_) => FileOperation::Move,
+      Action::Delete => FileOperation::Delete,
+      Action::Permissions(_) => FileOperation::Permissions,
+      Action::Exec(_) => FileOperation::Exec,
+    }
+  }
+
+  pub fn target(&self) -> Option<&Path> {
+    match self {
+      Action::Copy(target) | Action::Move(target) => Some(target),
+      _ => None,
+    }
+  }
+
+  /// Apply the action to one file, e.g. as it is found while streaming
+  pub fn apply_file(&self, fs: &dyn FileSystem, resource: &ResourceRow, root_ref: &Option<FileEntry>, throttle: Option<&Throttle>) -> io::Result<()> {
+    match self {
+      Action::Copy(target) | Action::Move(target) => {
+        let new_path = target_file_path(resource, target, root_ref);
+        transfer_file(fs, resource.path_ref(), &new_path, self.mode().is_move(), throttle)
+      },
+      Action::Delete => fs.remove_file(resource.path_ref()),
+      Action::Permissions(change) => change.apply(resource),
+      Action::Exec(command) => command.run_file(resource, root_ref),
+    }
+  }
+
+  /// Apply the action to the files shown in the tree overview, on the file system that was scanned.
+  /// Failures are added to the tree errors.
+  /// Copies and moves run on a pool of workers and, if resumable, are saved as a job. The state file
+  /// of an incomplete job is kept in the tree for resuming it.
+  /// Commands also run on the pool, once per file or once per batch of files.
+  /// When several roots are scanned, each tree is copied or moved to its own subfolder of the target.
+  pub fn apply(&self, resource_tree: &mut ResourceTree, transfer_options: &TransferOptions) -> ActionTally {
+    if let Some(folder) = resource_tree.target_folder.clone() {
+      let action = self.in_folder(&folder);
+      if let Action::Copy(target) | Action::Move(target) = &action {
+        // no subfolder for a root without matches, otherwise it is needed for the job file
+        if resource_tree.num_files() < 1 {
+          resource_tree.tally = Some(ActionTally::default());
+          return ActionTally::default();
+        }
+        // without its subfolder, none of the files of this root can be copied or moved
+        if let Err(error) = resource_tree.fs.create_dir_all(target) {
+          resource_tree.errors.push(FileError::from_io(target, action.operation(), &error));
+          let tally = ActionTally { num_failed: resource_tree.action_rows().count() as u64, ..ActionTally::default() };
+          resource_tree.tally = Some(tally);
+          return tally;
+        }
+      }
+      return action.apply_here(resource_tree, &transfer_options.in_folder(&folder));
+    }
+    self.apply_here(resource_tree, transfer_options)
+  }
+
+  fn apply_here(&self, resource_tree: &mut ResourceTree, transfer_options: &TransferOptions) -> ActionTally {
+    let start = Instant::now();
+    let fs = resource_tree.fs.clone();
+    let root_ref = resource_tree.root().clone();
+    let rows: Vec<&ResourceRow> = resource_tree.action_rows().collect();
+    let total_bytes = rows.iter().map(|row| row.size()).sum();
+    let progress = Progress::action(resource_tree.show_progress, self.mode().past_word(), rows.len() as u64, total_bytes);
+    let mut tally = ActionTally::default();
+    let mut errors: Vec<FileError> = vec![];
+    let mut deleted: Vec<PathBuf> = vec![];
+    if let Action::Copy(target) | Action::Move(target) = self {
+      let root_path = root_ref.as_ref().map(|root| root.path().to_path_buf()).unwrap_or_default();
+      let entries = rows.iter().map(|row| JobEntry::new(row.path_ref().to_path_buf(), target_file_path(row, target, &root_ref), row.size())).collect();
+      let summary = start_job(fs.as_ref(), Job::new(self.mode().is_move(), &root_path, target, entries), transfer_options, &progress);
+      tally = summary.tally();
+      errors = summary.failures;
+      errors.extend(summary.job_errors);
+      resource_tree.job_file = summary.job_file;
+    } else if let Action::Exec(command) = self {
+      for (batch, result) in command.run(&rows, &root_ref, transfer_options.jobs, &progress) {
+        for resource in batch {
+          if let Err(error) = &result {
+            errors.push(FileError::from_io(resource.path_ref(), FileOperation::Exec, error));
+          }
+          tally.add(result.is_ok(), resource.size());
+        }
+      }
+      progress.finish();
+      tally.elapsed = start.elapsed();
+    } else {
+      for resource in rows {
+        if is_interrupted() {
+          break;
+        }
+        let result = self.apply_file(fs.as_ref(), resource, &root_ref, None);
+        match &result {
+          Err(error) => errors.push(FileError::from_io(resource.path_ref(), self.operation(), error)),
+          Ok(()) if matches!(self, Action::Delete) => deleted.push(resource.path_ref().to_path_buf()),
+          Ok(()) => {}
+        }
+        tally.add(result.is_ok(), resource.size());
+        progress.add_matched(resource.size());
+      }
+      progress.finish();
+      tally.elapsed = start.elapsed();
+    }
+    resource_tree.set_deleted(&deleted);
+    resource_tree.tally = Some(tally);
+    resource_tree.errors.extend(errors);
+    tally
+  }
+}
+
+/// Number and size of files changed by an action, how many failed and how long it took
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ActionTally {
+  pub num: u64,
+  pub size: u64,
+  pub num_failed: u64,
+  pub elapsed: Duration,
+}
+
+impl ActionTally {
+  /// Add the results of the same action on another root
+  pub fn merge(&mut self, other: &ActionTally) {
+    self.num += other.num;
+    self.size += other.size;
+    self.num_failed += other.num_failed;
+    self.elapsed += other.elapsed;
+  }
+
+  pub fn add(&mut self, success: bool, size: u64) {
+    if success {
+      self.num += 1;
+      self.size += size;
+    } else {
+      self.num_failed += 1;
+    }
+  }
+
+  /// Average throughput, e.g. 45.2 MiB/s
+  pub fn rate_display(&self) -> String {
+    let secs = self.elapsed.as_secs_f64();
+    if secs > 0f64 {
+      format!("{}/s", smart_size((self.size as f64 / secs) as u64))
+    } else {
+      "-".to_owned()
+    }
+  }
+
+  /// One-line summary of the applied action
+  pub fn to_text(&self, action: &Action) -> String {
+    let num = self.num;
+    let mode = action.mode();
+    let failed_text = if self.num_failed > 0 { cformat!(", <red>{} failed</red>", self.num_failed) } else { "".to_owned() };
+    match action {
+      Action::Copy(target) | Action::Move(target) => {
+        cformat!("{} {} {} ({}) to {} in {:.1}s, <cyan>{}</cyan>{}", mode.to_past(), num, pluralize_64("file", "s", num), smart_size(self.size), target.to_string_lossy(), self.elapsed.as_secs_f64(), self.rate_display(), failed_text)
+      },
+      Action::Permissions(change) => {
+        cformat!("{} {} {} ({}){}", mode.to_past(), num, pluralize_64("file", "s", num), change.to_text(), failed_text)
+      },
+      Action::Delete => {
+        cformat!("{} {} {} ({}){}", mode.to_past(), num, pluralize_64("file", "s", num), smart_size(self.size), failed_text)
+      },
+      Action::Exec(command) => {
+        cformat!("{} {} {} with <cyan>{}</cyan> in {:.1}s{}", mode.to_past(), num, pluralize_64("file", "s", num), command.to_text(), self.elapsed.as_secs_f64(), failed_text)
+      },
+    }
+  }
+
+  pub fn show(&self, action: &Action) {
+    println!("{}", self.to_text(action));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::ErrorKind;
+  use std::sync::Arc;
+  use crate::criteria::CriteriaBuilder;
+  use crate::errors::FileErrorKind;
+  use crate::file_system::{FsOperation, MemoryFileSystem};
+  use crate::run::Scanner;
+
+  fn sample_tree(fs: &Arc<MemoryFileSystem>) -> ResourceTree {
+    fs.add_file("/data/a.txt", b"a", 100);
+    fs.add_file("/data/docs/b.txt", b"bb", 100);
+    fs.add_dir("/backup");
+    Scanner::with_file_system(CriteriaBuilder::new().build().unwrap(), fs.clone()).scan("/data")
+  }
+
+  fn paths(list: &[&str]) -> Vec<PathBuf> {
+    list.iter().map(PathBuf::from).collect()
+  }
+
+  #[test]
+  fn test_move_keeps_relative_paths() {
+    let fs = Arc::new(MemoryFileSystem::new());
+    let mut tree = sample_tree(&fs);
+    let tally = Action::Move("/backup".into()).apply(&mut tree, &TransferOptions::default());
+    assert_eq!((tally.num, tally.size, tally.num_failed), (2, 3, 0));
+    assert_eq!(fs.files(), paths(&["/backup/a.txt", "/backup/docs/b.txt"]));
+  }
+
+  #[test]
+  fn test_delete_marks_deleted_rows() {
+    let fs = Arc::new(MemoryFileSystem::new());
+    fs.fail(FsOperation::Remove, "/data/docs", ErrorKind::PermissionDenied);
+    let mut tree = sample_tree(&fs);
+    Action::Delete.apply(&mut tree, &TransferOptions::default());
+    let deleted: Vec<(String, bool)> = tree.action_rows().map(|row| (row.relative_path(tree.root()), row.deleted)).collect();
+    assert_eq!(deleted, vec![("a.txt".to_owned(), true), ("docs/b.txt".to_owned(), false)]);
+  }
+
+  #[test]
+  fn test_roots_move_to_subfolders() {
+    let fs = Arc::new(MemoryFileSystem::new());
+    fs.add_file("/srv/cache/c.tmp", b"c", 100);
+    fs.add_file("/var/log/app/x/d.log", b"dd", 100);
+    fs.add_dir("/srv/empty");
+    fs.add_dir("/backup");
+    let criteria = CriteriaBuilder::new().move_to("/backup").build().unwrap();
+    let mut tally = ActionTally::default();
+    for (root, folder) in [("/srv/cache", "cache"), ("/var/log/app", "app"), ("/srv/empty", "empty")] {
+      let mut tree = Scanner::with_file_system(criteria.clone(), fs.clone()).with_target_folder(folder).scan(root);
+      tally.merge(&Action::Move("/backup".into()).apply(&mut tree, &TransferOptions::default()));
+    }
+    assert_eq!((tally.num, tally.size), (2, 3));
+    assert_eq!(fs.files(), paths(&["/backup/app/x/d.log", "/backup/cache/c.tmp"]));
+    assert!(!fs.exists(Path::new("/backup/empty")));
+  }
+
+  #[test]
+  fn test_unwritable_subfolder_fails_its_root() {
+    let fs = Arc::new(MemoryFileSystem::new());
+    fs.add_file("/srv/cache/c.tmp", b"c", 100);
+    fs.add_file("/srv/cache/d.tmp", b"dd", 100);
+    fs.add_dir("/backup");
+    fs.fail(FsOperation::CreateDir, "/backup/cache", ErrorKind::PermissionDenied);
+    let criteria = CriteriaBuilder::new().copy_to("/backup").build().unwrap();
+    let mut tree = Scanner::with_file_system(criteria, fs.clone()).with_target_folder("cache").scan("/srv/cache");
+    let tally = Action::Copy("/backup".into()).apply(&mut tree, &TransferOptions::default());
+    assert_eq!((tally.num, tally.num_failed), (0, 2));
+    assert_eq!(tree.tally.map(|tally| tally.num_failed), Some(2));
+    let errors: Vec<(&Path, FileOperation, FileErrorKind)> = tree.errors.iter().map(|error| (error.path.as_path(), error.operation, error.kind)).collect();
+    assert_eq!(errors, vec![(Path::new("/backup/cache"), FileOperation::Copy, FileErrorKind::PermissionDenied)]);
+    assert_eq!(fs.files(), paths(&["/srv/cache/c.tmp", "/srv/cache/d.tmp"]));
+  }
+
+  #[test]
+  fn test_move_across_devices() {
+    let fs = Arc::new(MemoryFileSystem::new());
+    fs.mount("/backup", 2);
+    let mut tree = sample_tree(&fs);
+    let tally = Action::Move("/backup".into()).apply(&mut tree, &TransferOptions::default());
+    assert_eq!(tally.num, 2);
+    assert_eq!(fs.files(), paths(&["/backup/a.txt", "/backup/docs/b.txt"]));
+    assert_eq!(fs.contents("/backup/docs/b.txt"), Some(b"bb".to_vec()));
+  }
+
+  #[test]
+  fn test_failures_are_recorded() {
+    let fs = Arc::new(MemoryFileSystem::new());
+    fs.fail(FsOperation::Copy, "/backup/docs", ErrorKind::StorageFull);
+    let mut tree = sample_tree(&fs);
+    let tally = Action::Copy("/backup".into()).apply(&mut tree, &TransferOptions::default());
+    assert_eq!((tally.num, tally.num_failed), (1, 1));
+    assert_eq!(tree.errors[0].kind, FileErrorKind::StorageFull);
+    assert_eq!(tree.errors[0].operation, FileOperation::Copy);
+    // no partial copy is left behind
+    assert_eq!(fs.files(), paths(&["/backup/a.txt", "/data/a.txt", "/data/docs/b.txt"]));
+
+    let fs = Arc::new(MemoryFileSystem::new());
+    fs.fail(FsOperation::Remove, "/data/docs", ErrorKind::PermissionDenied);
+    let mut tree = sample_tree(&fs);
+    let tally = Action::Delete.apply(&mut tree, &TransferOptions::default());
+    assert_eq!((tally.num, tally.num_failed), (1, 1));
+    assert_eq!(tree.errors[0].kind, FileErrorKind::PermissionDenied);
+    assert_eq!(fs.files(), paths(&["/data/docs/b.txt"]));
+  }
+
+  #[test]
+  fn test_rate_display() {
+    let tally = ActionTally { num: 1, size: 2 * 1024 * 1024, num_failed: 0, elapsed: Duration::from_secs(2) };
+    assert_eq!(tally.rate_display(), "1.00 MiB/s");
+  }
+}
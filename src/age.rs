@@ -0,0 +1,467 @@
+//! Parse and display file ages
+//!
+//! Ages are expressed as a number with an unambiguous unit suffix:
+//! `s` seconds, `min` minutes, `h` hours, `d` days, `w` weeks, `mo` months and `y` years,
+//! e.g. `90min`, `36h`, `2w`, `6mo` or `1y6mo`. Numbers without a unit are days.
+//! ISO-8601 durations such as `P1M2D` or `PT12H` are also accepted.
+//! Months and years are subtracted from the current date on the calendar,
+//! so `1mo` on 31 March means files older than 28 or 29 February.
+
+use chrono::{DateTime, Months, Utc};
+use std::fmt;
+use crate::utils::pluralize_64;
+
+const SECONDS_PER_MINUTE: f64 = 60f64;
+const SECONDS_PER_HOUR: f64 = 3600f64;
+const SECONDS_PER_DAY: f64 = 86400f64;
+const SECONDS_PER_WEEK: f64 = SECONDS_PER_DAY * 7f64;
+const DAYS_PER_YEAR: f64 = 365.25;
+const SECONDS_PER_MONTH: f64 = SECONDS_PER_DAY * DAYS_PER_YEAR / 12f64;
+
+/// Time units accepted in age arguments
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AgeUnit {
+  Second,
+  Minute,
+  Hour,
+  Day,
+  Week,
+  Month,
+  Year
+}
+
+impl AgeUnit {
+  /// Match a unit suffix. A bare `m` is rejected because it could mean minutes or months
+  pub fn from_suffix(suffix: &str) -> Result<AgeUnit, AgeError> {
+    match suffix {
+      "s" | "sec" | "secs" | "second" | "seconds" => Ok(AgeUnit::Second),
+      "min" | "mins" | "minute" | "minutes" => Ok(AgeUnit::Minute),
+      "h" | "hr" | "hrs" | "hour" | "hours" => Ok(AgeUnit::Hour),
+      "" | "d" | "day" | "days" => Ok(AgeUnit::Day),
+      "w" | "wk" | "wks" | "week" | "weeks" => Ok(AgeUnit::Week),
+      "mo" | "mon" | "mos" | "month" | "months" => Ok(AgeUnit::Month),
+      "y" | "yr" | "yrs" | "year" | "years" => Ok(AgeUnit::Year),
+      "m" => Err(AgeError::AmbiguousUnit(suffix.to_owned())),
+      _ => Err(AgeError::UnknownUnit(suffix.to_owned()))
+    }
+  }
+
+  /// Length of the unit in seconds. Calendar units return their average length
+  pub fn seconds(&self) -> f64 {
+    match self {
+      AgeUnit::Second => 1f64,
+      AgeUnit::Minute => SECONDS_PER_MINUTE,
+      AgeUnit::Hour => SECONDS_PER_HOUR,
+      AgeUnit::Day => SECONDS_PER_DAY,
+      AgeUnit::Week => SECONDS_PER_WEEK,
+      AgeUnit::Month => SECONDS_PER_MONTH,
+      AgeUnit::Year => SECONDS_PER_MONTH * 12f64,
+    }
+  }
+
+  pub fn is_calendar(&self) -> bool {
+    match self {
+      AgeUnit::Month | AgeUnit::Year => true,
+      _ => false
+    }
+  }
+}
+
+/// Errors raised while parsing age arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgeError {
+  InvalidNumber(String),
+  AmbiguousUnit(String),
+  UnknownUnit(String),
+  InvalidDuration(String),
+  InvalidRange(String, String),
+}
+
+impl fmt::Display for AgeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AgeError::InvalidNumber(input) => write!(f, "invalid age '{}': expected a number followed by a unit, e.g. 30d or 6mo", input),
+      AgeError::AmbiguousUnit(unit) => write!(f, "ambiguous age unit '{}': use 'min' for minutes or 'mo' for months", unit),
+      AgeError::UnknownUnit(unit) => write!(f, "unknown age unit '{}': use s, min, h, d, w, mo or y", unit),
+      AgeError::InvalidDuration(input) => write!(f, "invalid ISO-8601 duration '{}', e.g. P1M2D or PT12H", input),
+      AgeError::InvalidRange(input, reason) => write!(f, "invalid age range '{}': {}", input, reason),
+    }
+  }
+}
+
+impl std::error::Error for AgeError {}
+
+/// A file age made up of calendar months and a fixed number of seconds
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct AgeSpan {
+  pub months: u32,
+  pub seconds: f64,
+}
+
+impl AgeSpan {
+  pub fn new(months: u32, seconds: f64) -> Self {
+    AgeSpan { months, seconds }
+  }
+
+  /// Add a quantity of the given unit. Fractions of months and years are added as average-length seconds
+  pub fn add(&mut self, num: f64, unit: AgeUnit) {
+    if unit.is_calendar() {
+      let months = if unit == AgeUnit::Year { num * 12f64 } else { num };
+      let whole_months = months.trunc();
+      self.months += whole_months as u32;
+      self.seconds += (months - whole_months) * SECONDS_PER_MONTH;
+    } else {
+      self.seconds += num * unit.seconds();
+    }
+  }
+
+  pub fn is_zero(&self) -> bool {
+    self.months == 0 && self.seconds <= 0f64
+  }
+
+  /// Length in seconds, counting calendar months at their average length
+  pub fn average_seconds(&self) -> f64 {
+    self.months as f64 * SECONDS_PER_MONTH + self.seconds
+  }
+
+  /// Unix timestamp of the moment that lies this age before the reference time
+  pub fn start_timestamp(&self, now: &DateTime<Utc>) -> i64 {
+    let start = now.checked_sub_months(Months::new(self.months)).unwrap_or(DateTime::<Utc>::MIN_UTC);
+    start.timestamp() - self.seconds.round() as i64
+  }
+
+  /// Resolve the age against the reference time as a 64-bit float day value
+  pub fn to_days(&self, now: &DateTime<Utc>) -> f64 {
+    (now.timestamp() - self.start_timestamp(now)) as f64 / SECONDS_PER_DAY
+  }
+
+  pub fn to_days_now(&self) -> f64 {
+    self.to_days(&Utc::now())
+  }
+
+  pub fn to_label(&self) -> String {
+    let mut parts: Vec<String> = vec![];
+    let years = self.months / 12;
+    let months = self.months % 12;
+    if years > 0 {
+      parts.push(format!("{} {}", years, pluralize_64("year", "s", years as u64)));
+    }
+    if months > 0 {
+      parts.push(format!("{} {}", months, pluralize_64("month", "s", months as u64)));
+    }
+    if self.seconds >= 1f64 || parts.len() < 1 {
+      parts.push(seconds_to_day_hours_min_secs(self.seconds.round() as u64));
+    }
+    parts.join(" ")
+  }
+}
+
+impl fmt::Display for AgeSpan {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.to_label())
+  }
+}
+
+fn parse_number(num_str: &str, input: &str) -> Result<f64, AgeError> {
+  match num_str.parse::<f64>() {
+    Ok(num) if num.is_finite() && num >= 0f64 => Ok(num),
+    _ => Err(AgeError::InvalidNumber(input.to_owned()))
+  }
+}
+
+/// Split a compound age such as `1y6mo` into number and unit suffix pairs
+fn split_number_unit_pairs(age_str: &str) -> Result<Vec<(String, String)>, AgeError> {
+  let mut pairs: Vec<(String, String)> = vec![];
+  let mut num_chars = String::new();
+  let mut unit_chars = String::new();
+  for c in age_str.chars() {
+    if c.is_ascii_digit() || c == '.' {
+      if unit_chars.len() > 0 {
+        pairs.push((num_chars.clone(), unit_chars.clone()));
+        num_chars.clear();
+        unit_chars.clear();
+      }
+      num_chars.push(c);
+    } else if c.is_alphabetic() && num_chars.len() > 0 {
+      unit_chars.push(c);
+    } else if !c.is_whitespace() {
+      return Err(AgeError::InvalidNumber(age_str.to_owned()));
+    }
+  }
+  if num_chars.len() < 1 {
+    return Err(AgeError::InvalidNumber(age_str.to_owned()));
+  }
+  pairs.push((num_chars, unit_chars));
+  Ok(pairs)
+}
+
+/// Parse an ISO-8601 duration such as `P1Y2M10DT2H30M`, where M means months before T and minutes after it
+pub fn parse_iso_duration(duration_str: &str) -> Result<AgeSpan, AgeError> {
+  let upper = duration_str.trim().to_uppercase();
+  let invalid = || AgeError::InvalidDuration(duration_str.to_owned());
+  let body = upper.strip_prefix('P').ok_or_else(invalid)?;
+  let mut span = AgeSpan::new(0, 0f64);
+  let mut in_time = false;
+  let mut num_chars = String::new();
+  let mut num_parts = 0;
+  for c in body.chars() {
+    if c.is_ascii_digit() || c == '.' || c == ',' {
+      num_chars.push(if c == ',' { '.' } else { c });
+    } else if c == 'T' && num_chars.len() < 1 && !in_time {
+      in_time = true;
+    } else {
+      if num_chars.len() < 1 {
+        return Err(invalid());
+      }
+      let num = num_chars.parse::<f64>().map_err(|_| invalid())?;
+      let unit = match (c, in_time) {
+        ('Y', false) => AgeUnit::Year,
+        ('M', false) => AgeUnit::Month,
+        ('W', false) => AgeUnit::Week,
+        ('D', false) => AgeUnit::Day,
+        ('H', true) => AgeUnit::Hour,
+        ('M', true) => AgeUnit::Minute,
+        ('S', true) => AgeUnit::Second,
+        _ => return Err(invalid())
+      };
+      span.add(num, unit);
+      num_chars.clear();
+      num_parts += 1;
+    }
+  }
+  if num_chars.len() > 0 || num_parts < 1 {
+    return Err(invalid());
+  }
+  Ok(span)
+}
+
+/// Parse a single age value, e.g. `30`, `90min`, `6mo`, `1y6mo` or `P1M2D`
+pub fn parse_age(age_str: &str) -> Result<AgeSpan, AgeError> {
+  let ref_str = age_str.trim().to_lowercase();
+  if ref_str.starts_with('p') {
+    return parse_iso_duration(age_str);
+  }
+  let mut span = AgeSpan::new(0, 0f64);
+  for (num_str, unit_str) in split_number_unit_pairs(&ref_str)? {
+    let num = parse_number(&num_str, age_str)?;
+    let unit = AgeUnit::from_suffix(&unit_str)?;
+    span.add(num, unit);
+  }
+  Ok(span)
+}
+
+/// Extract the trailing unit suffix of a simple age value, e.g. `h` from `12h`
+fn extract_unit_suffix(age_str: &str) -> String {
+  let suffix: String = age_str.trim().chars().rev().take_while(|c| c.is_alphabetic()).collect();
+  suffix.chars().rev().collect()
+}
+
+/// Parse the --before and --after arguments into optional minimum and maximum ages.
+/// --before also accepts a range such as `7-14` or `30min-12h`. If only the upper bound
+/// has a unit, as in `2-6mo`, the lower bound inherits it. Ranges with a missing bound
+/// or a minimum above the maximum are rejected.
+pub fn parse_age_range(before: &str, after: &str) -> Result<(Option<AgeSpan>, Option<AgeSpan>), AgeError> {
+  let before_parts: Vec<&str> = before.split('-').map(|s| s.trim()).collect();
+  let (min_str, max_str) = if before_parts.len() > 1 {
+    let start = before_parts[0].to_owned();
+    let end = before_parts[1..].join("-");
+    if start.len() < 1 || end.len() < 1 {
+      return Err(AgeError::InvalidRange(before.to_owned(), "expected both a lower and an upper bound, e.g. 7-14".to_owned()));
+    }
+    let start_is_number = start.len() > 0 && start.chars().all(|c| c.is_ascii_digit() || c == '.');
+    let start = if start_is_number && !end.to_lowercase().starts_with('p') {
+      format!("{}{}", start, extract_unit_suffix(&end))
+    } else {
+      start
+    };
+    (start, end)
+  } else {
+    (before.trim().to_owned(), after.trim().to_owned())
+  };
+  let min_age = if min_str.len() > 0 { Some(parse_age(&min_str)?) } else { None };
+  let max_age = if max_str.len() > 0 { Some(parse_age(&max_str)?) } else { None };
+  if let (Some(min), Some(max)) = (min_age, max_age) {
+    if !max.is_zero() && max.average_seconds() < min.average_seconds() {
+      let input = if before_parts.len() > 1 { before.to_owned() } else { format!("{} to {}", before, after) };
+      return Err(AgeError::InvalidRange(input, "the maximum is smaller than the minimum".to_owned()));
+    }
+  }
+  Ok((min_age, max_age))
+}
+
+/// Describe the age filter of the current criteria
+pub fn age_range_display(min: &Option<AgeSpan>, max: &Option<AgeSpan>) -> String {
+  let min = min.filter(|span| !span.is_zero());
+  let max = max.filter(|span| !span.is_zero());
+  match (min, max) {
+    (Some(min_span), Some(max_span)) => format!("between {} and {} old", min_span, max_span),
+    (Some(min_span), None) => format!("older than {}", min_span),
+    (None, Some(max_span)) => format!("newer than {}", max_span),
+    (None, None) => "All ages".to_owned()
+  }
+}
+
+/// Convert seconds an unsigned 64-bit integer to a display string
+pub fn seconds_to_day_hours_min_secs(seconds: u64) -> String {
+  let secs_per_hour = 3600;
+  let secs_per_day = secs_per_hour * 24;
+  let has_days = seconds >= secs_per_day;
+  let show_hours = seconds < secs_per_day * 3;
+  let has_hours = seconds >= secs_per_hour;
+  let show_minutes = seconds < (secs_per_hour * 6);
+  let has_minutes = seconds >= 60;
+  let show_seconds = seconds < 60 * 5;
+  if has_days {
+    let days = seconds / secs_per_day;
+    let hours = (seconds % secs_per_day) / secs_per_hour;
+    if show_hours && hours != 0 {
+      format!("{} {} {}{}", days, pluralize_64("day", "s", days), hours, "h")
+    } else {
+      format!("{} {}", days, pluralize_64("day", "s", days))
+    }
+  } else if has_hours {
+    let hours = seconds / secs_per_hour;
+    let minutes = (seconds % secs_per_hour) / 60;
+    if show_minutes && minutes != 0 {
+      format!("{}{} {}{}", hours, "h", minutes, "min")
+    } else {
+      format!("{}{}", hours, "h")
+    }
+  } else if has_minutes {
+    let minutes = seconds / 60;
+    let secs = seconds % 60;
+    if show_seconds && secs != 0u64 {
+      format!("{:.0}{} {:.0}{}", minutes, "min", secs, "s")
+    } else {
+      format!("{:.0}{}", minutes, "min")
+    }
+  } else {
+    format!("{:.0}{}", seconds, "s")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  #[test]
+  fn test_time_unit_display() {
+    let hour = 3600;
+    let text_1 = seconds_to_day_hours_min_secs(hour * 3);
+    let expected_1 = "3h".to_string();
+    assert_eq!(text_1, expected_1);
+
+    let minute = 60;
+    let text_2 = seconds_to_day_hours_min_secs(minute * 2 + 15);
+    let expected_2 = "2min 15s".to_string();
+    assert_eq!(text_2, expected_2);
+    let text_3 = seconds_to_day_hours_min_secs(minute + 45);
+    let expected_3 = "1min 45s".to_string();
+    assert_eq!(text_3, expected_3);
+    assert_eq!(seconds_to_day_hours_min_secs(hour * 2 + minute * 15), "2h 15min");
+    assert_eq!(seconds_to_day_hours_min_secs(minute * 4 + 10), "4min 10s");
+    assert_eq!(seconds_to_day_hours_min_secs(minute * 10), "10min");
+    assert_eq!(seconds_to_day_hours_min_secs(hour * 24 * 2 + hour * 5), "2 days 5h");
+  }
+
+
+  #[test]
+  fn test_parse_simple_units() {
+    assert_eq!(parse_age("30"), Ok(AgeSpan::new(0, 30f64 * 86400f64)));
+    assert_eq!(parse_age("30d"), Ok(AgeSpan::new(0, 30f64 * 86400f64)));
+    assert_eq!(parse_age("45s"), Ok(AgeSpan::new(0, 45f64)));
+    assert_eq!(parse_age("90min"), Ok(AgeSpan::new(0, 5400f64)));
+    assert_eq!(parse_age("1.5h"), Ok(AgeSpan::new(0, 5400f64)));
+    assert_eq!(parse_age("2w"), Ok(AgeSpan::new(0, 14f64 * 86400f64)));
+    assert_eq!(parse_age("6mo"), Ok(AgeSpan::new(6, 0f64)));
+    assert_eq!(parse_age("2y"), Ok(AgeSpan::new(24, 0f64)));
+    assert_eq!(parse_age("1Y6MO"), Ok(AgeSpan::new(18, 0f64)));
+    assert_eq!(parse_age("3 days"), Ok(AgeSpan::new(0, 3f64 * 86400f64)));
+  }
+
+  #[test]
+  fn test_reject_ambiguous_and_invalid_units() {
+    assert_eq!(parse_age("1m"), Err(AgeError::AmbiguousUnit("m".to_owned())));
+    assert_eq!(parse_age("5x"), Err(AgeError::UnknownUnit("x".to_owned())));
+    assert_eq!(parse_age("abc"), Err(AgeError::InvalidNumber("abc".to_owned())));
+    assert_eq!(parse_age("1..2d"), Err(AgeError::InvalidNumber("1..2d".to_owned())));
+    assert!(parse_age("").is_err());
+  }
+
+  #[test]
+  fn test_parse_iso_durations() {
+    assert_eq!(parse_age("P1M2D"), Ok(AgeSpan::new(1, 2f64 * 86400f64)));
+    assert_eq!(parse_age("P1Y"), Ok(AgeSpan::new(12, 0f64)));
+    assert_eq!(parse_age("PT12H30M"), Ok(AgeSpan::new(0, 12.5 * 3600f64)));
+    assert_eq!(parse_age("P2W"), Ok(AgeSpan::new(0, 14f64 * 86400f64)));
+    assert_eq!(parse_age("P1DT1S"), Ok(AgeSpan::new(0, 86401f64)));
+    assert!(parse_age("P").is_err());
+    assert!(parse_age("PT").is_err());
+    assert!(parse_age("P1H").is_err());
+    assert!(parse_age("P1M2").is_err());
+  }
+
+  #[test]
+  fn test_calendar_month_subtraction() {
+    let now = Utc.with_ymd_and_hms(2024, 3, 31, 12, 0, 0).unwrap();
+    let one_month = AgeSpan::new(1, 0f64);
+    let expected = Utc.with_ymd_and_hms(2024, 2, 29, 12, 0, 0).unwrap();
+    assert_eq!(one_month.start_timestamp(&now), expected.timestamp());
+    assert_eq!(one_month.to_days(&now), 31f64);
+
+    let one_year = parse_age("1y").unwrap();
+    let last_year = Utc.with_ymd_and_hms(2023, 3, 31, 12, 0, 0).unwrap();
+    assert_eq!(one_year.start_timestamp(&now), last_year.timestamp());
+    assert_eq!(one_year.to_days(&now), 366f64);
+
+    let mixed = parse_age("P1M2D").unwrap();
+    let expected_mixed = Utc.with_ymd_and_hms(2024, 2, 27, 12, 0, 0).unwrap();
+    assert_eq!(mixed.start_timestamp(&now), expected_mixed.timestamp());
+  }
+
+  #[test]
+  fn test_parse_age_ranges() {
+    let (min, max) = parse_age_range("7-14", "").unwrap();
+    assert_eq!(min, Some(AgeSpan::new(0, 7f64 * 86400f64)));
+    assert_eq!(max, Some(AgeSpan::new(0, 14f64 * 86400f64)));
+
+    let (min, max) = parse_age_range("2-6mo", "").unwrap();
+    assert_eq!(min, Some(AgeSpan::new(2, 0f64)));
+    assert_eq!(max, Some(AgeSpan::new(6, 0f64)));
+
+    let (min, max) = parse_age_range("30min-12h", "").unwrap();
+    assert_eq!(min, Some(AgeSpan::new(0, 1800f64)));
+    assert_eq!(max, Some(AgeSpan::new(0, 12f64 * 3600f64)));
+
+    let (min, max) = parse_age_range("30", "1y").unwrap();
+    assert_eq!(min, Some(AgeSpan::new(0, 30f64 * 86400f64)));
+    assert_eq!(max, Some(AgeSpan::new(12, 0f64)));
+
+    assert_eq!(parse_age_range("", ""), Ok((None, None)));
+    assert!(parse_age_range("1m-2h", "").is_err());
+  }
+
+  #[test]
+  fn test_reject_incomplete_and_inverted_age_ranges() {
+    assert!(matches!(parse_age_range("10-", ""), Err(AgeError::InvalidRange(_, _))));
+    assert!(matches!(parse_age_range("-10", ""), Err(AgeError::InvalidRange(_, _))));
+    assert!(matches!(parse_age_range("14-7", ""), Err(AgeError::InvalidRange(_, _))));
+    assert!(matches!(parse_age_range("1y-6mo", ""), Err(AgeError::InvalidRange(_, _))));
+    assert!(matches!(parse_age_range("1y", "30"), Err(AgeError::InvalidRange(_, _))));
+    assert!(parse_age_range("7-7", "").is_ok());
+    assert!(parse_age_range("30d-2mo", "").is_ok());
+  }
+
+  #[test]
+  fn test_age_display() {
+    assert_eq!(parse_age("1y6mo").unwrap().to_string(), "1 year 6 months");
+    assert_eq!(parse_age("P1M2D").unwrap().to_string(), "1 month 2 days");
+    assert_eq!(parse_age("90min").unwrap().to_string(), "1h 30min");
+    assert_eq!(parse_age("3w").unwrap().to_string(), "21 days");
+    let (min, max) = parse_age_range("2-6mo", "").unwrap();
+    assert_eq!(age_range_display(&min, &max), "between 2 months and 6 months old");
+    assert_eq!(age_range_display(&min, &None), "older than 2 months");
+    assert_eq!(age_range_display(&None, &None), "All ages");
+  }
+}
@@ -1,4 +1,15 @@
-use clap::Parser;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use clap::{Parser, Subcommand};
+use crate::criteria::{Criteria, CriteriaBuilder, TimeField};
+use crate::errors::ArgError;
+use crate::hooks::{Hook, HookPhase};
+use crate::path_info::{PathInfo, read_path_list};
+use crate::progress::progress_enabled;
+use crate::resource_row::{DetailLevel, SortKey};
+use crate::utils::{is_full_path, path_string_to_file_name, path_string_to_head};
 
 fn empty_string() -> String {
   "".to_string()
@@ -8,6 +19,9 @@ fn empty_string() -> String {
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct Args {
+
+  #[command(subcommand)]
+  pub command: Option<Command>,
   
   #[clap(short, long, value_parser, default_value_t = empty_string()) ]
   pub before: String,
@@ -41,21 +55,109 @@ pub struct Args {
   #[clap(short, long, value_parser, default_value_t = empty_string()) ]
   pub size: String,
 
+  /// Timestamp used for age filters, listings and the oldest/newest stats
+  #[clap(long, value_enum, default_value_t = TimeField::Modified) ]
+  pub time_field: TimeField,
+
   #[clap(short = 'd', long, value_parser, default_value_t = 5) ]
   pub max_depth: u8,
 
+  /// Number of threads used to scan directories, or 0 for one per CPU
+  #[clap(long, value_parser, default_value_t = 1) ]
+  pub threads: usize,
+
+  /// Only files owned by these users, as comma-separated names or ids
+  #[clap(long, value_parser, default_value_t = empty_string()) ]
+  pub owner: String,
+
+  /// Only files owned by these user ids
+  #[clap(long, value_parser, default_value_t = empty_string()) ]
+  pub uid: String,
+
+  /// Only files belonging to these groups, as comma-separated names or ids
+  #[clap(long, value_parser, default_value_t = empty_string()) ]
+  pub group: String,
+
+  /// Permission bits as in find -perm: exact (0644), all of (-g+w) or any of (/o+w)
+  #[clap(long, value_parser, default_value_t = empty_string(), allow_hyphen_values = true) ]
+  pub perm: String,
+
+  /// Only files whose owner has no user account
+  #[arg(long, value_enum)]
+  pub nouser: bool,
+
+  /// Only files whose group does not exist
+  #[arg(long, value_enum)]
+  pub nogroup: bool,
+
   #[arg(short = 'y', long, value_enum)]
   pub hidden: bool,
 
   #[arg(short, long, value_enum)]
   pub list: bool,
 
+  /// Order of the files listed with -l, or of the --top files
+  #[clap(long, value_enum)]
+  pub sort: Option<SortKey>,
+
+  /// Reverse the --sort order, e.g. smallest or newest first
+  #[arg(long, value_enum)]
+  pub reverse: bool,
+
+  /// Show at most this many files per directory with -l
+  #[clap(long, value_parser, default_value_t = 0)]
+  pub limit: usize,
+
+  /// Show the N largest files across the whole tree, or the N first in the --sort order, instead of each directory
+  #[clap(long, value_parser, default_value_t = 0)]
+  pub top: usize,
+
   #[arg(short, long, value_enum)]
   pub groups: bool,
 
   #[arg(short, long, value_enum)]
   pub void: bool,
 
+  /// Show stats by file owner before the main overview
+  #[arg(long, value_enum)]
+  pub by_owner: bool,
+
+  /// Do not show the progress line on stderr during long scans and actions
+  #[arg(long, value_enum, global = true)]
+  pub quiet: bool,
+
+  /// Show each matched file as it is found and keep only running totals in memory
+  #[arg(long, value_enum)]
+  pub stream: bool,
+
+  /// Print a JSON report of the matched files, totals, action results and errors instead of text
+  #[arg(long, value_enum)]
+  pub json: bool,
+
+  /// Print only the matched paths, one per line, instead of the overview
+  #[arg(long, value_enum)]
+  pub paths_only: bool,
+
+  /// Print only the matched paths separated by NUL characters, e.g. for xargs -0
+  #[arg(long, value_enum)]
+  pub print0: bool,
+
+  /// Print paths relative to the scanned directory with --paths-only or --print0
+  #[arg(long, value_enum)]
+  pub relative: bool,
+
+  /// Check the files listed in this file, one per line, instead of walking the directory
+  #[clap(long, value_parser)]
+  pub from_file: Option<String>,
+
+  /// Check the files listed on stdin instead of walking the directory, e.g. from git ls-files
+  #[arg(long, value_enum, conflicts_with = "from_file")]
+  pub from_stdin: bool,
+
+  /// Paths in the --from-file or --from-stdin list are separated by NUL characters, as from find -print0
+  #[arg(short = '0', long, value_enum)]
+  pub null: bool,
+
   #[arg(short = 'x', long, value_enum)]
   pub regex_mode: bool,
 
@@ -65,12 +167,205 @@ pub struct Args {
   #[clap(short, long, value_parser)]
   pub r#copy: Option<String>,
 
+  /// Change the permissions of matched files, e.g. 0644 or g-w
+  #[clap(long, value_parser)]
+  pub chmod: Option<String>,
+
+  /// Change the owner of matched files as user, user:group or :group
+  #[clap(long, value_parser)]
+  pub chown: Option<String>,
+
+  /// Number of files copied or moved in parallel, or 0 for one per CPU
+  #[clap(short = 'j', long, value_parser, default_value_t = 1, global = true) ]
+  pub jobs: usize,
+
+  /// Limit the copy and move throughput across all jobs, e.g. 50M for 50 MiB per second
+  #[clap(long, value_parser, default_value_t = empty_string(), global = true) ]
+  pub bwlimit: String,
+
+  /// Job state file for resuming an interrupted copy or move, by default in the target directory
+  #[clap(long, value_parser)]
+  pub job_file: Option<String>,
+
+  /// Run a command for each matched file, e.g. "convert {path} -resize 50% {dir}/{stem}_small.jpg".
+  /// Placeholders: {path}, {relpath}, {dir}, {name}, {stem}, {ext}, {size} and {mtime}
+  #[clap(long, value_parser, allow_hyphen_values = true)]
+  pub exec: Option<String>,
+
+  /// Run a command for batches of matched files, e.g. "tar czf logs.tgz {relpath}", like xargs
+  #[clap(long, value_parser, allow_hyphen_values = true)]
+  pub exec_batch: Option<String>,
+
+  /// Command run after the scan and before the action, e.g. "systemctl stop myapp". The action is skipped if it fails.
+  #[clap(long, value_parser, allow_hyphen_values = true)]
+  pub before_hook: Option<String>,
+
+  /// Command run after the action, e.g. "systemctl start myapp", with the results as FILEORG_* variables and JSON on stdin
+  #[clap(long, value_parser, allow_hyphen_values = true)]
+  pub after_hook: Option<String>,
+
+  /// Rhai script defining fn rule(file), which matches or skips each file and may set its destination
+  #[clap(long, value_parser)]
+  pub script: Option<String>,
+
   // delete with prompt, abbr. u for unlink
   #[arg(short = 'u', long, value_enum)]
   pub delete: bool,
 
-  // in delete mode, by pass the prompt 
+  // in delete, chmod or chown mode, by pass the prompt 
   #[arg(short = 'f', long, value_enum)]
   pub force: bool,
 
-}
\ No newline at end of file
+}
+
+/// Subcommands that do not scan a directory
+#[derive(Subcommand, Debug)]
+pub enum Command {
+  /// Continue an interrupted copy or move from its job state file
+  Resume {
+    job_file: String,
+  },
+}
+
+fn parse_expanded_path_args(paths: &[String]) -> String {
+  let names: Vec<String> = paths.iter().map(|p| path_string_to_file_name(p)).collect();
+  let head = path_string_to_head(paths.first().unwrap());
+  format!("{}({})", head, names.join("|"))
+}
+
+impl Args {
+  /// Path info of the directory to scan. Shell-expanded paths are combined into one name pattern
+  pub fn path_info(&self) -> PathInfo {
+    let curr_ref = "".to_owned();
+    let path_args = self.path.clone().unwrap_or(vec![".".to_string()]);
+    let path_arg = if path_args.len() > 1 {
+      parse_expanded_path_args(&path_args)
+    } else if path_args.len() > 0 {
+      path_args.first().unwrap().to_owned()
+    } else {
+      "".to_string()
+    };
+
+    let curr_path = env::current_dir().unwrap().as_os_str().to_str().unwrap().to_string();
+    let path_str = if path_arg == curr_ref { curr_path.clone().to_owned() } else if is_full_path(path_arg.as_str()) { path_arg.to_owned() } else { format!("{}/{}", curr_path, path_arg) };
+    PathInfo::new(&path_str)
+  }
+
+  /// Directories to scan: each path when several are given, unless the shell expanded
+  /// a pattern into file names, which are combined into one name pattern as before
+  pub fn path_infos(&self) -> Vec<PathInfo> {
+    let path_args = self.path.clone().unwrap_or_default();
+    if path_args.len() > 1 && !path_args.iter().any(|path| Path::new(path).is_file()) {
+      let curr_path = env::current_dir().unwrap_or_default();
+      path_args.iter().map(|path| PathInfo::new_dir(&curr_path.join(path).to_string_lossy())).collect()
+    } else {
+      vec![self.path_info()]
+    }
+  }
+
+  /// Details shown in the text overview. Sorted and top listings need the matched files kept in memory.
+  pub fn detail_level(&self) -> Result<DetailLevel, ArgError> {
+    if self.stream && (self.sort.is_some() || self.limit > 0 || self.top > 0) {
+      return Err(ArgError::Conflict("--stream".to_owned(), "--sort, --limit or --top".to_owned()));
+    }
+    Ok(DetailLevel {
+      sort: self.sort,
+      reverse: self.reverse,
+      limit: self.limit,
+      top: self.top,
+      ..DetailLevel::new(&self.list, &self.groups, &self.void, &self.by_owner)
+    })
+  }
+
+  /// Files listed with --from-file or --from-stdin, with relative paths resolved against the current directory
+  pub fn path_list(&self) -> Option<io::Result<Vec<PathBuf>>> {
+    let base = env::current_dir().unwrap_or_default();
+    if let Some(list_file) = &self.from_file {
+      Some(File::open(list_file).and_then(|file| read_path_list(file, self.null, &base)))
+    } else if self.from_stdin {
+      Some(read_path_list(io::stdin().lock(), self.null, &base))
+    } else {
+      None
+    }
+  }
+
+  /// Parse the --before-hook and --after-hook commands
+  pub fn hooks(&self) -> Result<(Option<Hook>, Option<Hook>), ArgError> {
+    let before = self.before_hook.as_ref().map(|command| Hook::parse(command, HookPhase::Before)).transpose()?;
+    let after = self.after_hook.as_ref().map(|command| Hook::parse(command, HookPhase::After)).transpose()?;
+    Ok((before, after))
+  }
+
+  pub fn to_criteria(&self, file_pattern: Option<String>) -> Result<Criteria, ArgError> {
+    let mut builder = CriteriaBuilder::new()
+      .before(&self.before)
+      .after(&self.after)
+      .extensions(&self.ext)
+      .exclude_extensions(&self.not_ext)
+      .exclude_dirs(&self.exclude_dirs)
+      .pattern(&self.pattern)
+      .omit_pattern(&self.omit_pattern)
+      .starts_with(&self.starts_with)
+      .ends_with(&self.ends_with)
+      .regex(self.regex_mode)
+      .file_pattern(file_pattern)
+      .size(&self.size)
+      .time_field(self.time_field)
+      .max_depth(self.max_depth)
+      .threads(self.threads)
+      .owner(&self.owner)
+      .uid(&self.uid)
+      .group(&self.group)
+      .perm(&self.perm)
+      .no_user(self.nouser)
+      .no_group(self.nogroup)
+      .hidden(self.hidden)
+      .delete(self.delete)
+      .force(self.force)
+      .stream(self.stream)
+      .json(self.json)
+      .paths_only(self.paths_only)
+      .print0(self.print0)
+      .relative_paths(self.relative)
+      .stdin_list(self.from_stdin)
+      .show_progress(progress_enabled(self.quiet))
+      .jobs(self.jobs)
+      .bwlimit(&self.bwlimit)
+      .resumable(true);
+    if let Some(target) = &self.copy {
+      builder = builder.copy_to(target);
+    }
+    if let Some(target) = &self.r#move {
+      builder = builder.move_to(target);
+    }
+    if let Some(mode) = &self.chmod {
+      builder = builder.chmod(mode);
+    }
+    if let Some(owner) = &self.chown {
+      builder = builder.chown(owner);
+    }
+    if let Some(job_file) = &self.job_file {
+      builder = builder.job_file(job_file);
+    }
+    if let Some(command) = &self.exec {
+      builder = builder.exec(command);
+    }
+    if let Some(command) = &self.exec_batch {
+      builder = builder.exec_batch(command);
+    }
+    if let Some(script) = &self.script {
+      builder = builder.script(script);
+    }
+    builder.build()
+  }
+
+  /// Criteria for the resume command, which only takes the transfer and progress options
+  pub fn resume_criteria(&self) -> Result<Criteria, ArgError> {
+    CriteriaBuilder::new()
+      .show_progress(progress_enabled(self.quiet))
+      .jobs(self.jobs)
+      .bwlimit(&self.bwlimit)
+      .resumable(true)
+      .build()
+  }
+}
@@ -1,34 +1,88 @@
-use crate::args::Args;
 use crate::utils::*;
+use crate::age::{AgeSpan, parse_age_range, age_range_display};
 use crate::matches::{build_matcher, MatchBounds};
 use crate::path_info::PathInfo;
+use crate::errors::ArgError;
+use crate::owners::{resolve_uid, resolve_gid, user_display, group_name};
+use crate::permissions::{PermMatch, PermMatchMode, parse_perm_match};
+use crate::manage::PermissionChange;
+use crate::transfer::TransferOptions;
+use crate::action::Action;
+use crate::filters::*;
+use crate::resource_row::ResourceRow;
+use crate::script::Script;
+use crate::exec::ExecCommand;
+use std::sync::Arc;
 use std::fs::create_dir_all;
 use std::path::{Path, PathBuf};
+use clap::ValueEnum;
 use color_print::{cprintln,cformat};
-use string_patterns::{PatternMatch, Regex};
+use string_patterns::Regex;
+use crate::file_system::FileEntry;
 
 #[derive(Debug, Copy, Clone)]
 pub enum MatchMode {
   Simple,
   Regex
 }
+/// File timestamp used to calculate the age of a file
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum TimeField {
+  /// Last modification time (mtime)
+  Modified,
+  /// Last access time (atime)
+  Accessed,
+  /// Last status change time (ctime)
+  Changed,
+  /// Birth or creation time where the file system records it
+  Created
+}
+
+impl TimeField {
+  pub fn to_label(&self) -> &'static str {
+    match self {
+      TimeField::Modified => "modified",
+      TimeField::Accessed => "accessed",
+      TimeField::Changed => "changed",
+      TimeField::Created => "created",
+    }
+  }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum ActionMode {
   List,
   Move,
   Copy,
   Delete,
-  DirectDelete // unprompted
+  DirectDelete, // unprompted
+  Permissions,
+  DirectPermissions, // unprompted
+  Exec
 }
 
 impl ActionMode {
+  pub fn past_word(&self) -> &'static str {
+    match self {
+      ActionMode::List => "listed",
+      ActionMode::Move => "moved",
+      ActionMode::Copy => "copied",
+      ActionMode::Delete | ActionMode::DirectDelete => "deleted",
+      ActionMode::Permissions | ActionMode::DirectPermissions => "changed",
+      ActionMode::Exec => "processed",
+    }
+  }
+
   pub fn to_past_string(&self, not_mode: bool) -> String {
     let prefix = if not_mode { "not "} else { ""};
+    let word = self.past_word();
     match self {
-      ActionMode::List => cformat!("<yellow>{}{}</yellow>", prefix, "listed"),
-      ActionMode::Move => cformat!("<cyan>{}{}</cyan>", prefix, "moved"),
-      ActionMode::Copy => cformat!("<green>{}{}</green>", prefix, "copied"),
-      ActionMode::Delete | ActionMode::DirectDelete => cformat!("<red>{}{}</red>", prefix, "deleted"),
+      ActionMode::List => cformat!("<yellow>{}{}</yellow>", prefix, word),
+      ActionMode::Move => cformat!("<cyan>{}{}</cyan>", prefix, word),
+      ActionMode::Copy => cformat!("<green>{}{}</green>", prefix, word),
+      ActionMode::Delete | ActionMode::DirectDelete => cformat!("<red>{}{}</red>", prefix, word),
+      ActionMode::Permissions | ActionMode::DirectPermissions => cformat!("<magenta>{}{}</magenta>", prefix, word),
+      ActionMode::Exec => cformat!("<blue>{}{}</blue>", prefix, word),
     }
   }
   pub fn to_past(&self) -> String {
@@ -45,83 +99,522 @@ impl ActionMode {
       _ => false
     }
   }
+
+  pub fn permissions_confirmed(&self) -> bool {
+    match self {
+      ActionMode::DirectPermissions => true,
+      _ => false
+    }
+  }
+
+  pub fn is_move(&self) -> bool {
+    match self {
+      ActionMode::Move => true,
+      _ => false
+    }
+  }
+
+  /// Name of the action in the JSON report
+  pub fn key(&self) -> &'static str {
+    match self {
+      ActionMode::List => "list",
+      ActionMode::Move => "move",
+      ActionMode::Copy => "copy",
+      ActionMode::Delete | ActionMode::DirectDelete => "delete",
+      ActionMode::Permissions | ActionMode::DirectPermissions => "permissions",
+      ActionMode::Exec => "exec",
+    }
+  }
+
+  pub fn is_delete(&self) -> bool {
+    match self {
+      ActionMode::Delete | ActionMode::DirectDelete => true,
+      _ => false
+    }
+  }
+}
+
+/// Build a case-insensitive file name matcher and reject patterns that are not valid regular expressions
+fn build_valid_matcher(pattern: &str, bounds: MatchBounds, mode: MatchMode) -> Result<Regex, ArgError> {
+  build_matcher(pattern, true, bounds, mode).ok_or_else(|| ArgError::InvalidPattern(pattern.to_owned()))
+}
+
+/// Zero threads or jobs means one per available CPU
+pub fn resolve_threads(threads: usize) -> usize {
+  if threads > 0 {
+    threads
+  } else {
+    std::thread::available_parallelism().map(|num| num.get()).unwrap_or(1)
+  }
+}
+
+/// Plain list of matched paths printed by --paths-only or --print0 instead of the overview
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PathOutput {
+  /// Paths relative to the scanned directory rather than absolute
+  pub relative: bool,
+  /// Separated by NUL characters for `xargs -0`, rather than newlines
+  pub null_separated: bool,
 }
 
 /// Defines the criteria used to scan directories and filter files
-/// by age, size, extension and/or name patterns
+/// by age, size, extension and/or name patterns.
+/// Values turned into filters when built are read-only; add further filters with `add_filter`.
 #[derive(Debug, Clone)]
 pub struct Criteria {
-  pub sizes: (u64, u64),
-  pub include_extensions: Vec<String>,
-  pub exclude_extensions: Vec<String>,
+  sizes: (u64, u64),
+  include_extensions: Vec<String>,
+  exclude_extensions: Vec<String>,
   pub exclude_directories: Vec<String>,
-  pub pattern: Option<Regex>,
-  pub exclude_pattern: Option<Regex>,
+  pattern: Option<Regex>,
+  exclude_pattern: Option<Regex>,
   pub match_mode: MatchMode,
   pub bounds: MatchBounds,
   pub max_depth: u8,
-  pub min_age: f64,
-  pub max_age: f64,
-  pub show_hidden: bool,
+  pub threads: usize,
+  pub stream: bool,
+  pub json: bool,
+  pub path_output: Option<PathOutput>,
+  pub show_progress: bool,
+  pub transfer: TransferOptions,
+  min_age: f64,
+  max_age: f64,
+  age_spans: (Option<AgeSpan>, Option<AgeSpan>),
+  owner_ids: Vec<u32>,
+  group_ids: Vec<u32>,
+  perm: Option<PermMatch>,
+  no_user: bool,
+  no_group: bool,
+  show_hidden: bool,
+  pub time_field: TimeField,
   pub action: ActionMode,
   pub may: ActionMode,
   pub target: Option<String>,
+  pub permission_change: Option<PermissionChange>,
+  /// A file matches when every filter accepts it
+  pub filters: Vec<Arc<dyn Filter>>,
+  /// Rule script run on files that pass all filters
+  pub script: Option<Arc<Script>>,
+  /// Command run on matched files with --exec or --exec-batch
+  pub exec: Option<ExecCommand>,
 }
 
-impl Criteria {
-  pub fn new(args: &Args, file_pattern: Option<String>) -> Criteria {
-    // accept -separated range or single for --before
-    let before_parts = extract_string_parts(&args.before);
-    let has_before_range = before_parts.len() > 1 && before_parts.get(1).is_some();
-    let mut before_ref = if has_before_range { before_parts.get(0).unwrap().to_owned() } else { "".to_owned() };
-    // use either -a, --after value for min. age or first value in the before range
-    let after_ref = if has_before_range { before_parts.get(1).unwrap().to_owned() } else { args.after.to_owned() };
-    if has_before_range && before_ref.pattern_match_ci("[mdhdwy]$") && after_ref.pattern_match_ci("[mdhdwy]$") {
-      let suffix = extract_first_suffix_letter(&after_ref);
-      before_ref = format!("{},{}", before_ref, suffix);
+/// Builds scan criteria from the same values as the command line options,
+/// e.g. ages such as `1y` or `2w-3mo`, sizes such as `10M-1G` and comma-separated extensions.
+/// Values are validated when the criteria are built.
+///
+/// ```
+/// use file_organiser::CriteriaBuilder;
+///
+/// let criteria = CriteriaBuilder::new().extensions("jpg,png").size("1M").before("1y").build().unwrap();
+/// assert_eq!(criteria.include_extensions(), ["jpg", "png"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CriteriaBuilder {
+  before: String,
+  after: String,
+  ext: String,
+  not_ext: String,
+  exclude_dirs: String,
+  pattern: String,
+  omit_pattern: String,
+  starts_with: String,
+  ends_with: String,
+  regex_mode: bool,
+  file_pattern: Option<String>,
+  size: String,
+  time_field: TimeField,
+  max_depth: u8,
+  threads: usize,
+  owner: String,
+  uid: String,
+  group: String,
+  perm: String,
+  nouser: bool,
+  nogroup: bool,
+  hidden: bool,
+  copy_target: Option<String>,
+  move_target: Option<String>,
+  delete: bool,
+  chmod: Option<String>,
+  chown: Option<String>,
+  force: bool,
+  stream: bool,
+  json: bool,
+  paths_only: bool,
+  print0: bool,
+  relative_paths: bool,
+  stdin_list: bool,
+  show_progress: bool,
+  jobs: usize,
+  bwlimit: String,
+  resumable: bool,
+  job_file: Option<String>,
+  filters: Vec<Arc<dyn Filter>>,
+  script: Option<String>,
+  exec: Option<String>,
+  exec_batch: Option<String>,
+}
+
+impl Default for CriteriaBuilder {
+  fn default() -> Self {
+    CriteriaBuilder {
+      before: "".to_owned(),
+      after: "".to_owned(),
+      ext: "".to_owned(),
+      not_ext: "".to_owned(),
+      exclude_dirs: "".to_owned(),
+      pattern: "".to_owned(),
+      omit_pattern: "".to_owned(),
+      starts_with: "".to_owned(),
+      ends_with: "".to_owned(),
+      regex_mode: false,
+      file_pattern: None,
+      size: "".to_owned(),
+      time_field: TimeField::Modified,
+      max_depth: 5,
+      threads: 1,
+      owner: "".to_owned(),
+      uid: "".to_owned(),
+      group: "".to_owned(),
+      perm: "".to_owned(),
+      nouser: false,
+      nogroup: false,
+      hidden: false,
+      copy_target: None,
+      move_target: None,
+      delete: false,
+      chmod: None,
+      chown: None,
+      force: false,
+      stream: false,
+      json: false,
+      paths_only: false,
+      print0: false,
+      relative_paths: false,
+      stdin_list: false,
+      show_progress: false,
+      jobs: 1,
+      bwlimit: "".to_owned(),
+      resumable: false,
+      job_file: None,
+      filters: vec![],
+      script: None,
+      exec: None,
+      exec_batch: None,
     }
+  }
+}
+
+impl CriteriaBuilder {
+  pub fn new() -> Self {
+    CriteriaBuilder::default()
+  }
+
+  /// Only files older than this age, or within an age range such as `1w-1mo`
+  pub fn before(mut self, age: &str) -> Self {
+    self.before = age.to_owned();
+    self
+  }
+
+  /// Only files newer than this age
+  pub fn after(mut self, age: &str) -> Self {
+    self.after = age.to_owned();
+    self
+  }
+
+  /// Comma-separated extensions to include
+  pub fn extensions(mut self, extensions: &str) -> Self {
+    self.ext = extensions.to_owned();
+    self
+  }
+
+  /// Comma-separated extensions to exclude
+  pub fn exclude_extensions(mut self, extensions: &str) -> Self {
+    self.not_ext = extensions.to_owned();
+    self
+  }
+
+  /// Comma-separated directory names or paths relative to the root, skipped with their subdirectories
+  pub fn exclude_dirs(mut self, dirs: &str) -> Self {
+    self.exclude_dirs = dirs.to_owned();
+    self
+  }
+
+  pub fn pattern(mut self, pattern: &str) -> Self {
+    self.pattern = pattern.to_owned();
+    self
+  }
 
-    let before = extract_age(&before_ref);
-    let after = extract_age(&after_ref);
-    let max_depth = if args.max_depth > 0 && file_pattern.is_none() { args.max_depth } else { 1 };
+  pub fn omit_pattern(mut self, pattern: &str) -> Self {
+    self.omit_pattern = pattern.to_owned();
+    self
+  }
+
+  pub fn starts_with(mut self, pattern: &str) -> Self {
+    self.starts_with = pattern.to_owned();
+    self
+  }
+
+  pub fn ends_with(mut self, pattern: &str) -> Self {
+    self.ends_with = pattern.to_owned();
+    self
+  }
+
+  /// Interpret patterns as full regular expressions
+  pub fn regex(mut self, regex_mode: bool) -> Self {
+    self.regex_mode = regex_mode;
+    self
+  }
+
+  /// File name pattern taken from the scanned path, which limits the scan to one level
+  pub fn file_pattern(mut self, pattern: Option<String>) -> Self {
+    self.file_pattern = pattern;
+    self
+  }
+
+  /// Minimum size such as `10M`, or a size range such as `10M-1G`
+  pub fn size(mut self, size: &str) -> Self {
+    self.size = size.to_owned();
+    self
+  }
+
+  pub fn time_field(mut self, time_field: TimeField) -> Self {
+    self.time_field = time_field;
+    self
+  }
+
+  pub fn max_depth(mut self, max_depth: u8) -> Self {
+    self.max_depth = max_depth;
+    self
+  }
+
+  /// Number of threads used to scan directories, or 0 for one per CPU
+  pub fn threads(mut self, threads: usize) -> Self {
+    self.threads = threads;
+    self
+  }
+
+  /// Comma-separated user names or ids
+  pub fn owner(mut self, owner: &str) -> Self {
+    self.owner = owner.to_owned();
+    self
+  }
+
+  /// Comma-separated user ids
+  pub fn uid(mut self, uid: &str) -> Self {
+    self.uid = uid.to_owned();
+    self
+  }
+
+  /// Comma-separated group names or ids
+  pub fn group(mut self, group: &str) -> Self {
+    self.group = group.to_owned();
+    self
+  }
+
+  /// Permission bits as find -perm, e.g. 644, -g+w or /o+w
+  pub fn perm(mut self, perm: &str) -> Self {
+    self.perm = perm.to_owned();
+    self
+  }
+
+  pub fn no_user(mut self, no_user: bool) -> Self {
+    self.nouser = no_user;
+    self
+  }
+
+  pub fn no_group(mut self, no_group: bool) -> Self {
+    self.nogroup = no_group;
+    self
+  }
+
+  /// Include hidden files and directories
+  pub fn hidden(mut self, hidden: bool) -> Self {
+    self.hidden = hidden;
+    self
+  }
+
+  pub fn copy_to(mut self, target: &str) -> Self {
+    self.copy_target = Some(target.to_owned());
+    self
+  }
+
+  pub fn move_to(mut self, target: &str) -> Self {
+    self.move_target = Some(target.to_owned());
+    self
+  }
+
+  pub fn delete(mut self, delete: bool) -> Self {
+    self.delete = delete;
+    self
+  }
+
+  /// Permission mode such as 0644 or g-w
+  pub fn chmod(mut self, mode: &str) -> Self {
+    self.chmod = Some(mode.to_owned());
+    self
+  }
+
+  /// New owner as user, user:group or :group
+  pub fn chown(mut self, owner: &str) -> Self {
+    self.chown = Some(owner.to_owned());
+    self
+  }
+
+  /// Command to run for each matched file, with placeholders such as `{path}` or `{stem}`
+  pub fn exec(mut self, command: &str) -> Self {
+    self.exec = Some(command.to_owned());
+    self
+  }
+
+  /// Command to run for batches of matched files, repeating each argument with a placeholder once per file
+  pub fn exec_batch(mut self, command: &str) -> Self {
+    self.exec_batch = Some(command.to_owned());
+    self
+  }
 
-    let include_extensions:Vec<String> = extract_extensions(&args.ext);
+  /// Apply deletes and permission changes without a prompt
+  pub fn force(mut self, force: bool) -> Self {
+    self.force = force;
+    self
+  }
+
+  pub fn stream(mut self, stream: bool) -> Self {
+    self.stream = stream;
+    self
+  }
+
+  pub fn json(mut self, json: bool) -> Self {
+    self.json = json;
+    self
+  }
 
-    let exclude_extensions:Vec<String> = extract_extensions(&args.not_ext);
+  /// Print only the matched paths, one per line
+  pub fn paths_only(mut self, paths_only: bool) -> Self {
+    self.paths_only = paths_only;
+    self
+  }
 
-    let exclude_directories:Vec<String> = extract_from_list(&args.exclude_dirs);
+  /// Print only the matched paths, separated by NUL characters
+  pub fn print0(mut self, print0: bool) -> Self {
+    self.print0 = print0;
+    self
+  }
 
-    let sizes = extract_sizes(&args.size);
+  /// Print paths relative to the scanned directory with paths_only or print0
+  pub fn relative_paths(mut self, relative_paths: bool) -> Self {
+    self.relative_paths = relative_paths;
+    self
+  }
 
-    let (target, copy_mode) = extract_move_target(args.copy.clone());
-    let (target, move_mode) = if copy_mode { (target, false ) } else { extract_move_target(args.r#move.clone()) };
-    let target = if move_mode { Some(target) } else { None };
+  /// The files to check are read from stdin, so prompts cannot be answered
+  pub fn stdin_list(mut self, stdin_list: bool) -> Self {
+    self.stdin_list = stdin_list;
+    self
+  }
+
+  /// Draw a progress line on stderr during long scans and actions
+  pub fn show_progress(mut self, show_progress: bool) -> Self {
+    self.show_progress = show_progress;
+    self
+  }
+
+  /// Number of files copied or moved in parallel, or 0 for one per CPU
+  pub fn jobs(mut self, jobs: usize) -> Self {
+    self.jobs = jobs;
+    self
+  }
+
+  /// Bandwidth limit for copy and move, e.g. `50M` for 50 MiB per second
+  pub fn bwlimit(mut self, rate: &str) -> Self {
+    self.bwlimit = rate.to_owned();
+    self
+  }
 
-    let has_start_pattern = args.starts_with.len() > 0;
-    let has_end_pattern = !has_start_pattern && args.ends_with.len() > 0;
-    let pattern_str = if has_start_pattern { args.starts_with.clone() } else if has_end_pattern { args.ends_with.clone() } else { args.pattern.clone() };
+  /// Save copy and move jobs in a state file, so they can be resumed if interrupted
+  pub fn resumable(mut self, resumable: bool) -> Self {
+    self.resumable = resumable;
+    self
+  }
+
+  /// State file of a resumable copy or move job, which also makes it resumable
+  pub fn job_file(mut self, path: &str) -> Self {
+    self.job_file = Some(path.to_owned());
+    self
+  }
+
+  /// Add a custom filter, checked after the built-in ones
+  pub fn filter(mut self, filter: impl Filter + 'static) -> Self {
+    self.filters.push(Arc::new(filter));
+    self
+  }
+
+  /// Rhai script defining `fn rule(file)`, compiled when the criteria are built
+  pub fn script(mut self, path: &str) -> Self {
+    self.script = Some(path.to_owned());
+    self
+  }
+
+  pub fn build(&self) -> Result<Criteria, ArgError> {
+    self.check_conflicts()?;
+    // accept -separated range or single for --before
+    let (min_age_span, max_age_span) = parse_age_range(&self.before, &self.after)?;
+    let before = min_age_span.map(|span| span.to_days_now()).unwrap_or(0f64);
+    let after = max_age_span.map(|span| span.to_days_now()).unwrap_or(0f64);
+    let max_depth = if self.max_depth > 0 && self.file_pattern.is_none() { self.max_depth } else { 1 };
+
+    let include_extensions:Vec<String> = extract_extensions(&self.ext);
+
+    let exclude_extensions:Vec<String> = extract_extensions(&self.not_ext);
+
+    let exclude_directories:Vec<String> = extract_from_list(&self.exclude_dirs);
+
+    let sizes = extract_sizes(&self.size)?;
+
+    let (target, copy_mode) = extract_move_target(self.copy_target.clone());
+    let (target, move_mode) = if copy_mode { (target, false ) } else { extract_move_target(self.move_target.clone()) };
+    let target = if copy_mode || move_mode { Some(target) } else { None };
+
+    let has_start_pattern = self.starts_with.len() > 0;
+    let has_end_pattern = self.ends_with.len() > 0;
+    let pattern_str = if has_start_pattern { self.starts_with.clone() } else if has_end_pattern { self.ends_with.clone() } else { self.pattern.clone() };
     
-    let match_mode = if args.regex_mode { MatchMode::Regex } else { MatchMode::Simple };
+    let match_mode = if self.regex_mode { MatchMode::Regex } else { MatchMode::Simple };
 
     let bounds = if has_start_pattern { MatchBounds::Start } else if has_end_pattern { MatchBounds::End } else { MatchBounds::Open };
     
-    let pattern = if file_pattern.is_some() { 
-      build_matcher(&file_pattern.unwrap(), true, bounds, match_mode)
+    let pattern = if let Some(file_pattern_str) = &self.file_pattern { 
+      Some(build_valid_matcher(file_pattern_str, bounds, match_mode)?)
     } else if pattern_str.len() > 0 {
-      build_matcher(&pattern_str, true, bounds, match_mode)
+      Some(build_valid_matcher(&pattern_str, bounds, match_mode)?)
     } else { 
       None
     };
 
-    let exclude_pattern = if args.omit_pattern.len() > 0 { 
-      build_matcher(&args.omit_pattern, true, bounds, match_mode)
+    let exclude_pattern = if self.omit_pattern.len() > 0 { 
+      Some(build_valid_matcher(&self.omit_pattern, bounds, match_mode)?)
     } else {
       None
     };
     
-    let delete_mode = !move_mode && args.delete;
+    let permission_change = PermissionChange::from_args(&self.chmod, &self.chown)?;
 
-    let force_delete = delete_mode && args.force;
+    let exec = match (&self.exec, &self.exec_batch) {
+      (Some(command), _) => Some(ExecCommand::parse(command, false)?),
+      (None, Some(command)) => Some(ExecCommand::parse(command, true)?),
+      (None, None) => None,
+    }.map(|command| ExecCommand { output_to_stderr: self.json || self.paths_only || self.print0, ..command });
+
+    let path_output = if self.paths_only || self.print0 {
+      Some(PathOutput { relative: self.relative_paths, null_separated: self.print0 })
+    } else {
+      None
+    };
+
+    let delete_mode = !move_mode && self.delete;
+
+    let force_delete = delete_mode && self.force;
     let action = if copy_mode {
       ActionMode::Copy
     } else if move_mode {
@@ -130,14 +623,66 @@ impl Criteria {
       ActionMode::DirectDelete
     } else if delete_mode {
       ActionMode::Delete
+    } else if permission_change.is_some() && self.force {
+      ActionMode::DirectPermissions
+    } else if permission_change.is_some() {
+      ActionMode::Permissions
+    } else if exec.is_some() {
+      ActionMode::Exec
     } else {
       ActionMode::List
     };
 
     
-    let show_hidden = args.hidden;
+    let mut owner_ids: Vec<u32> = vec![];
+    for user_ref in extract_from_list(&self.owner) {
+      owner_ids.push(resolve_uid(&user_ref).ok_or(ArgError::UnknownUser(user_ref))?);
+    }
+    for uid_ref in extract_from_list(&self.uid) {
+      owner_ids.push(uid_ref.trim().parse::<u32>().map_err(|_| ArgError::UnknownUser(uid_ref.clone()))?);
+    }
+    let mut group_ids: Vec<u32> = vec![];
+    for group_ref in extract_from_list(&self.group) {
+      group_ids.push(resolve_gid(&group_ref).ok_or(ArgError::UnknownGroup(group_ref))?);
+    }
+    let perm = if self.perm.len() > 0 { Some(parse_perm_match(&self.perm)?) } else { None };
+
+    let bwlimit = if self.bwlimit.len() > 0 { Some(extract_rate(&self.bwlimit)?) } else { None };
+
+    let show_hidden = self.hidden;
+
+    // name filters first, as they need no file metadata
+    let mut filters: Vec<Arc<dyn Filter>> = vec![];
+    if !show_hidden {
+      filters.push(Arc::new(HiddenFilter));
+    }
+    if include_extensions.len() > 0 || exclude_extensions.len() > 0 {
+      filters.push(Arc::new(ExtensionFilter { include: include_extensions.clone(), exclude: exclude_extensions.clone() }));
+    }
+    if let Some(re) = &pattern {
+      filters.push(Arc::new(PatternFilter(re.clone())));
+    }
+    if let Some(re) = &exclude_pattern {
+      filters.push(Arc::new(OmitPatternFilter(re.clone())));
+    }
+    if before > 0f64 || after > 0f64 {
+      filters.push(Arc::new(AgeFilter { min_age: before, max_age: after }));
+    }
+    if sizes.0 > 0 || sizes.1 > 0 {
+      filters.push(Arc::new(SizeFilter { min: sizes.0, max: sizes.1 }));
+    }
+    let owner_filter = OwnerFilter { owner_ids: owner_ids.clone(), group_ids: group_ids.clone(), perm, no_user: self.nouser, no_group: self.nogroup };
+    if owner_filter.owner_ids.len() > 0 || owner_filter.group_ids.len() > 0 || perm.is_some() || self.nouser || self.nogroup {
+      filters.push(Arc::new(owner_filter));
+    }
+    filters.extend(self.filters.iter().cloned());
+
+    let script = match &self.script {
+      Some(path) => Some(Arc::new(Script::load(path)?)),
+      None => None,
+    };
     
-    Criteria { 
+    Ok(Criteria { 
       sizes,
       include_extensions,
       exclude_extensions,
@@ -147,19 +692,128 @@ impl Criteria {
       match_mode,
       bounds,
       max_depth,
+      threads: resolve_threads(self.threads),
+      stream: self.stream,
+      json: self.json,
+      path_output,
+      // matched files are already shown as they are found when streaming
+      show_progress: self.show_progress && !self.stream,
+      transfer: TransferOptions { jobs: resolve_threads(self.jobs), bwlimit, resumable: self.resumable || self.job_file.is_some(), job_file: self.job_file.as_ref().map(PathBuf::from) },
       min_age: before,
       max_age: after,
+      age_spans: (min_age_span, max_age_span),
+      owner_ids,
+      group_ids,
+      perm,
+      no_user: self.nouser,
+      no_group: self.nogroup,
       show_hidden,
+      time_field: self.time_field,
       action,
       target,
-      may: ActionMode::List
+      may: ActionMode::List,
+      permission_change,
+      filters,
+      script,
+      exec
+    })
+  }
+
+  /// Reject options that would otherwise silently override each other
+  fn check_conflicts(&self) -> Result<(), ArgError> {
+    let missing_target = [&self.copy_target, &self.move_target].into_iter().flatten().any(|target| !Path::new(target).is_dir());
+    let conflicts = [
+      (self.copy_target.is_some(), "--copy", self.move_target.is_some(), "--move"),
+      (self.delete, "--delete", self.copy_target.is_some(), "--copy"),
+      (self.delete, "--delete", self.move_target.is_some(), "--move"),
+      (self.chmod.is_some() || self.chown.is_some(), "--chmod or --chown", self.delete || self.copy_target.is_some() || self.move_target.is_some(), "--delete, --copy or --move"),
+      (self.starts_with.len() > 0, "--starts-with", self.ends_with.len() > 0, "--ends-with"),
+      (self.pattern.len() > 0, "--pattern", self.starts_with.len() > 0, "--starts-with"),
+      (self.pattern.len() > 0, "--pattern", self.ends_with.len() > 0, "--ends-with"),
+      (self.before.contains('-'), "a --before range", self.after.len() > 0, "--after"),
+      (self.nouser, "--nouser", self.owner.len() > 0 || self.uid.len() > 0, "--owner or --uid"),
+      (self.nogroup, "--nogroup", self.group.len() > 0, "--group"),
+      (self.stream, "--stream", self.threads != 1, "--threads"),
+      (self.stream, "--stream", self.jobs != 1, "--jobs"),
+      (self.stream, "--stream", !self.force && (self.delete || self.chmod.is_some() || self.chown.is_some()), "--delete, --chmod or --chown without --force"),
+      (self.exec.is_some(), "--exec", self.exec_batch.is_some(), "--exec-batch"),
+      (self.exec.is_some() || self.exec_batch.is_some(), "--exec or --exec-batch", self.delete || self.copy_target.is_some() || self.move_target.is_some() || self.chmod.is_some() || self.chown.is_some(), "--delete, --copy, --move, --chmod or --chown"),
+      (self.stream, "--stream", self.exec_batch.is_some(), "--exec-batch"),
+      (self.json, "--json", self.stream, "--stream"),
+      (self.stdin_list, "--from-stdin", !self.force && (self.delete || self.chmod.is_some() || self.chown.is_some()), "--delete, --chmod or --chown without --force"),
+      (self.stdin_list, "--from-stdin", missing_target, "a --copy or --move target that does not exist yet"),
+      (self.paths_only || self.print0, "--paths-only or --print0", self.json || self.stream, "--json or --stream"),
+      (self.json, "--json", !self.force && (self.delete || self.chmod.is_some() || self.chown.is_some()), "--delete, --chmod or --chown without --force"),
+    ];
+    for (first_set, first, second_set, second) in conflicts {
+      if first_set && second_set {
+        return Err(ArgError::Conflict(first.to_owned(), second.to_owned()));
+      }
     }
+    Ok(())
   }
+}
 
+impl Criteria {
   pub fn min_size(&self) -> u64 {
     self.sizes.0
   }
 
+  pub fn include_extensions(&self) -> &[String] {
+    &self.include_extensions
+  }
+
+  pub fn exclude_extensions(&self) -> &[String] {
+    &self.exclude_extensions
+  }
+
+  pub fn pattern(&self) -> Option<&Regex> {
+    self.pattern.as_ref()
+  }
+
+  pub fn exclude_pattern(&self) -> Option<&Regex> {
+    self.exclude_pattern.as_ref()
+  }
+
+  /// Minimum age in days, or 0 for none
+  pub fn min_age(&self) -> f64 {
+    self.min_age
+  }
+
+  /// Maximum age in days, or 0 for none
+  pub fn max_age(&self) -> f64 {
+    self.max_age
+  }
+
+  /// Minimum and maximum age as given, before they are resolved to days
+  pub fn age_spans(&self) -> (Option<AgeSpan>, Option<AgeSpan>) {
+    self.age_spans
+  }
+
+  pub fn owner_ids(&self) -> &[u32] {
+    &self.owner_ids
+  }
+
+  pub fn group_ids(&self) -> &[u32] {
+    &self.group_ids
+  }
+
+  pub fn perm(&self) -> Option<PermMatch> {
+    self.perm
+  }
+
+  pub fn no_user(&self) -> bool {
+    self.no_user
+  }
+
+  pub fn no_group(&self) -> bool {
+    self.no_group
+  }
+
+  pub fn show_hidden(&self) -> bool {
+    self.show_hidden
+  }
+
   pub fn max_size(&self) -> u64 {
     self.sizes.1
   }
@@ -178,7 +832,7 @@ impl Criteria {
 
   pub fn target_info(&self) -> PathInfo {
     if let Some(tg) = self.target.clone() {
-      PathInfo::new(tg.clone().as_str())
+      PathInfo::new_dir(tg.as_str())
     } else {
       PathInfo::new_empty()
     }
@@ -191,35 +845,64 @@ impl Criteria {
   pub fn create_target(&self) -> bool {
     if let Some(tg) = self.target.clone() {
       let new_parent_path = Path::new(&tg).to_owned();
-      if let Ok(_created) = create_dir_all(new_parent_path) {
-        true
-      } else {
-        false
-      }
+      create_dir_all(new_parent_path).is_ok()
     } else {
       false
     }
   }
 
-  pub fn apply_action_permissions(&mut self) -> Option<Box<PathBuf>> {
-    let mut target_path: Option<Box<PathBuf>> = None;
+  /// Allow copy and move once the target exists, and deletes once confirmed
+  pub fn apply_action_permissions(&mut self) {
     let delete_confirmed = self.action.delete_confirmed();
     if self.move_or_copy_mode() {
-      let move_dir_info = self.target_info();
-      if move_dir_info.exists {
-        target_path = Some(move_dir_info.path);
+      if self.has_target() {
         if self.copy_mode() {
           self.set_may_copy();
         } else {
           self.set_may_move();
         }
       }
-    } else if self.delete_mode() {
-      if self.delete_mode() && delete_confirmed {
-        self.set_may_delete();
-      }
+    } else if self.delete_mode() && delete_confirmed {
+      self.set_may_delete();
+    }
+  }
+
+  /// Check the filters that need no file system metadata, such as extensions and file name patterns
+  pub fn matches_name(&self, row: &ResourceRow, ctx: &ScanContext) -> bool {
+    self.filters.iter().filter(|filter| !filter.needs_metadata()).all(|filter| filter.matches(row, ctx))
+  }
+
+  /// Check the filters that depend on file system metadata, such as age, size and owner
+  pub fn matches_metadata(&self, row: &ResourceRow, ctx: &ScanContext) -> bool {
+    self.filters.iter().filter(|filter| filter.needs_metadata()).all(|filter| filter.matches(row, ctx))
+  }
+
+  /// Add a filter to criteria that are already built
+  pub fn add_filter(&mut self, filter: impl Filter + 'static) {
+    self.filters.push(Arc::new(filter));
+  }
+
+  /// Excluded and hidden directories are skipped with their whole subtree.
+  /// Hidden files are left to the hidden filter.
+  pub fn is_walkable(&self, entry: &FileEntry, root_ref: &Option<FileEntry>) -> bool {
+    if entry.depth() < 1 {
+      true
+    } else if entry.is_dir() {
+      (self.show_hidden || !is_hidden_entry(entry)) && is_not_excluded_dir(entry, &self.exclude_directories, root_ref)
+    } else {
+      true
     }
-    target_path
+  }
+
+  pub fn exec_mode(&self) -> bool {
+    match self.action {
+      ActionMode::Exec => true,
+      _ => false,
+    }
+  }
+
+  pub fn filter_by_owner(&self) -> bool {
+    self.owner_ids.len() > 0 || self.group_ids.len() > 0 || self.perm.is_some() || self.no_user || self.no_group
   }
 
   pub fn has_size_limits(&self) -> bool {
@@ -240,6 +923,13 @@ impl Criteria {
     }
   }
 
+  pub fn permissions_mode(&self) -> bool {
+    match self.action {
+      ActionMode::Permissions | ActionMode::DirectPermissions => true,
+      _ => false,
+    }
+  }
+
   pub fn move_mode(&self) -> bool {
     match self.action {
       ActionMode::Move => true,
@@ -291,6 +981,27 @@ impl Criteria {
     }
   }
 
+  /// Whether stdout carries a JSON report or path list, so messages go to stderr
+  pub fn machine_output(&self) -> bool {
+    self.json || self.path_output.is_some()
+  }
+
+  /// Whether the action is applied to files as they are found rather than after the scan
+  pub fn acts_during_scan(&self) -> bool {
+    self.stream || self.action.delete_confirmed()
+  }
+
+  /// Action applied to each file as it is matched: confirmed deletes, or any confirmed action when streaming
+  pub fn scan_action(&self) -> Option<Action> {
+    if self.may_delete() {
+      Some(Action::Delete)
+    } else if self.stream && (self.may_copy() || self.may_move() || self.action.permissions_confirmed() || self.exec_mode()) {
+      Action::from_criteria(self)
+    } else {
+      None
+    }
+  }
+
   pub fn target_ref(&self) -> Box<String> {
     if let Some(tg) = self.target.clone() {
       Box::new(tg)
@@ -319,15 +1030,18 @@ impl Criteria {
     let action = match self.action {
       ActionMode::Move => "move to",
       ActionMode::Copy => "copy to",
-      ActionMode::Delete => if self.delete_mode() {
-        "delete"
-      } else {
-        "list"
-      },
+      ActionMode::Delete | ActionMode::DirectDelete => "delete",
+      ActionMode::Permissions | ActionMode::DirectPermissions => "change",
+      ActionMode::Exec => "run",
       _ => "list"
     };
     let target = if self.target_mode() {
         format!(" {}", self.target_ref())
+    } else if let Some(change) = &self.permission_change {
+        format!(" {}", change.to_text())
+    } else if let Some(command) = &self.exec {
+        let batch_text = if command.batch { " (batches)" } else { "" };
+        format!(" {}{}", command.to_text(), batch_text)
     } else {
       "".to_owned()
     };
@@ -339,9 +1053,12 @@ impl Criteria {
     let max_size_display = size_display(self.max_size(), "max.");
     let has_size_constraint = self.has_size_limits();
     let size_display = if has_size_constraint { format!("{} {}", min_size_display, max_size_display) } else { "[all]".to_owned() };
-    let age_range = days_age_display(self.min_age, self.max_age);
+    let age_range = age_range_display(&self.age_spans.0, &self.age_spans.1);
     cprintln!("<cyan,italics>CRITERIA</cyan,italics>");
     cprintln!("<yellow>{}</yellow>", age_range);
+    if self.time_field != TimeField::Modified {
+      cprintln!("{: <12} <cyan>{}</cyan>", "time field", self.time_field.to_label());
+    }
     cprintln!("{: <12} <cyan>{}</cyan>", "size range", size_display);
     let ext_text = if self.include_extensions.len() > 0 { self.include_extensions.join(", ") } else { "[all]".to_owned()  };
     cprintln!("{: <12} <cyan>{}</cyan>", "extensions", ext_text);
@@ -362,8 +1079,71 @@ impl Criteria {
         cprintln!("{: <12} {}", "file names", parts.join(" and "));
       }
     }
+    if self.filter_by_owner() {
+      let mut parts: Vec<String> = vec![];
+      if self.owner_ids.len() > 0 {
+        let names: Vec<String> = self.owner_ids.iter().map(|uid| user_display(*uid)).collect();
+        parts.push(cformat!("owner <cyan>{}</cyan>", names.join(", ")));
+      }
+      if self.group_ids.len() > 0 {
+        let names: Vec<String> = self.group_ids.iter().map(|gid| group_name(*gid).unwrap_or(gid.to_string())).collect();
+        parts.push(cformat!("group <cyan>{}</cyan>", names.join(", ")));
+      }
+      if self.no_user {
+        parts.push(cformat!("<cyan>no user</cyan>"));
+      }
+      if self.no_group {
+        parts.push(cformat!("<cyan>no group</cyan>"));
+      }
+      if let Some(perm) = self.perm {
+        let prefix = match perm.mode {
+          PermMatchMode::Exact => "",
+          PermMatchMode::All => "-",
+          PermMatchMode::Any => "/",
+        };
+        parts.push(cformat!("perm <cyan>{}{:04o}</cyan>", prefix, perm.bits));
+      }
+      cprintln!("{: <12} {}", "ownership", parts.join(", "));
+    }
+    if let Some(script) = &self.script {
+      cprintln!("{: <12} <cyan>{}</cyan>", "script", script.path);
+    }
     let action_text = self.to_text();
     cprintln!("{} <yellow>{: <12}</yellow>", "action", action_text);
   }
 
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::age::AgeError;
+
+  fn conflict(builder: CriteriaBuilder) -> Option<(String, String)> {
+    match builder.build() {
+      Err(ArgError::Conflict(first, second)) => Some((first, second)),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn test_check_conflicts() {
+    assert_eq!(conflict(CriteriaBuilder::new().copy_to("/tmp").move_to("/tmp")), Some(("--copy".to_owned(), "--move".to_owned())));
+    assert_eq!(conflict(CriteriaBuilder::new().starts_with("a").ends_with("b")), Some(("--starts-with".to_owned(), "--ends-with".to_owned())));
+    assert_eq!(conflict(CriteriaBuilder::new().before("7-14").after("30")), Some(("a --before range".to_owned(), "--after".to_owned())));
+    assert_eq!(conflict(CriteriaBuilder::new().stream(true).delete(true)), Some(("--stream".to_owned(), "--delete, --chmod or --chown without --force".to_owned())));
+    assert_eq!(conflict(CriteriaBuilder::new().stdin_list(true).copy_to("/no/such/target")), Some(("--from-stdin".to_owned(), "a --copy or --move target that does not exist yet".to_owned())));
+    assert_eq!(conflict(CriteriaBuilder::new().stream(true).jobs(4)), Some(("--stream".to_owned(), "--jobs".to_owned())));
+    assert_eq!(conflict(CriteriaBuilder::new().stream(true).delete(true).force(true)), None);
+    assert!(CriteriaBuilder::new().stream(true).delete(true).force(true).build().is_ok());
+  }
+
+  #[test]
+  fn test_reject_invalid_age_ranges() {
+    for range in ["10-", "14-7"] {
+      assert!(matches!(CriteriaBuilder::new().before(range).build(), Err(ArgError::InvalidAge(AgeError::InvalidRange(_, _)))));
+    }
+    assert!(matches!(CriteriaBuilder::new().before("1y").after("30").build(), Err(ArgError::InvalidAge(AgeError::InvalidRange(_, _)))));
+    assert!(CriteriaBuilder::new().before("7-14").build().is_ok());
+  }
+}
@@ -0,0 +1,221 @@
+use std::fmt;
+use color_print::cprintln;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::age::AgeError;
+use crate::exec::is_command_failure;
+
+/// Errors raised while validating command line arguments, before any directory is scanned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+  InvalidAge(AgeError),
+  InvalidSize(String, String),
+  InvalidPattern(String),
+  InvalidMode(String),
+  UnknownUser(String),
+  UnknownGroup(String),
+  Conflict(String, String),
+  InvalidScript(String, String),
+  InvalidCommand(String, String),
+}
+
+impl fmt::Display for ArgError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ArgError::InvalidAge(error) => write!(f, "{}", error),
+      ArgError::InvalidSize(input, reason) => write!(f, "invalid size '{}': {}", input, reason),
+      ArgError::InvalidPattern(pattern) => write!(f, "invalid file name pattern '{}'", pattern),
+      ArgError::InvalidMode(mode) => write!(f, "invalid permission mode '{}', e.g. 0644, u+x or go-w", mode),
+      ArgError::UnknownUser(name) => write!(f, "unknown user '{}'", name),
+      ArgError::UnknownGroup(name) => write!(f, "unknown group '{}'", name),
+      ArgError::Conflict(first, second) => write!(f, "{} cannot be combined with {}", first, second),
+      ArgError::InvalidScript(path, reason) => write!(f, "invalid script '{}': {}", path, reason),
+      ArgError::InvalidCommand(command, reason) => write!(f, "invalid command '{}': {}", command, reason),
+    }
+  }
+}
+
+impl std::error::Error for ArgError {}
+
+impl From<AgeError> for ArgError {
+  fn from(error: AgeError) -> Self {
+    ArgError::InvalidAge(error)
+  }
+}
+
+/// Exit code when the action failed on some files
+pub const ACTION_FAILED_EXIT_CODE: i32 = 1;
+
+/// Operation during which a file system error occurred
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOperation {
+  Scan,
+  Copy,
+  Move,
+  Delete,
+  Permissions,
+  Exec,
+  /// Writing or removing the state file of a copy or move job
+  JobFile,
+}
+
+impl FileOperation {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      FileOperation::Scan => "scan",
+      FileOperation::Copy => "copy",
+      FileOperation::Move => "move",
+      FileOperation::Delete => "delete",
+      FileOperation::Permissions => "permissions",
+      FileOperation::Exec => "exec",
+      FileOperation::JobFile => "job file",
+    }
+  }
+
+  /// Whether the error means an action was not applied, rather than a path could not be read
+  pub fn is_action(&self) -> bool {
+    match self {
+      FileOperation::Scan | FileOperation::JobFile => false,
+      _ => true,
+    }
+  }
+}
+
+/// Broad category of a file system error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileErrorKind {
+  PermissionDenied,
+  NotFound,
+  SymlinkLoop,
+  AlreadyExists,
+  StorageFull,
+  CrossesDevices,
+  Script,
+  CommandFailed,
+  /// A listed path that is not below the scanned directory
+  OutsideRoot,
+  Other,
+}
+
+impl FileErrorKind {
+  pub fn from_io(error: &io::Error) -> Self {
+    if is_command_failure(error) {
+      return FileErrorKind::CommandFailed;
+    }
+    match error.kind() {
+      io::ErrorKind::PermissionDenied => FileErrorKind::PermissionDenied,
+      io::ErrorKind::NotFound => FileErrorKind::NotFound,
+      io::ErrorKind::AlreadyExists => FileErrorKind::AlreadyExists,
+      io::ErrorKind::StorageFull => FileErrorKind::StorageFull,
+      io::ErrorKind::CrossesDevices => FileErrorKind::CrossesDevices,
+      _ => FileErrorKind::Other,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      FileErrorKind::PermissionDenied => "permission denied",
+      FileErrorKind::NotFound => "not found",
+      FileErrorKind::SymlinkLoop => "symlink loop",
+      FileErrorKind::AlreadyExists => "already exists",
+      FileErrorKind::StorageFull => "no space left",
+      FileErrorKind::CrossesDevices => "crosses devices",
+      FileErrorKind::Script => "script error",
+      FileErrorKind::CommandFailed => "command failed",
+      FileErrorKind::OutsideRoot => "outside root",
+      FileErrorKind::Other => "i/o error",
+    }
+  }
+
+  /// Kinds whose message tells more than the kind itself, e.g. the exit status of a command
+  pub fn has_detail(&self) -> bool {
+    match self {
+      FileErrorKind::Script | FileErrorKind::CommandFailed | FileErrorKind::Other => true,
+      _ => false,
+    }
+  }
+}
+
+/// An I/O error on a path while scanning or applying an action
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileError {
+  pub path: PathBuf,
+  pub operation: FileOperation,
+  pub kind: FileErrorKind,
+  pub message: String,
+}
+
+impl FileError {
+  pub fn from_io(path: &Path, operation: FileOperation, error: &io::Error) -> Self {
+    FileError { path: path.to_path_buf(), operation, kind: FileErrorKind::from_io(error), message: error.to_string() }
+  }
+
+  /// A listed path that is skipped as it is not below the scanned directory
+  pub fn outside_root(path: &Path, root: &Path) -> Self {
+    FileError { path: path.to_path_buf(), operation: FileOperation::Scan, kind: FileErrorKind::OutsideRoot, message: format!("not below {}", root.to_string_lossy()) }
+  }
+}
+
+impl fmt::Display for FileError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} failed for {}: {}", self.operation.as_str(), self.path.to_string_lossy(), self.message)
+  }
+}
+
+/// Errors collected from scan and action threads
+#[derive(Debug, Default)]
+pub struct ErrorLog {
+  errors: Mutex<Vec<FileError>>,
+}
+
+impl ErrorLog {
+  pub fn add(&self, error: FileError) {
+    if let Ok(mut errors) = self.errors.lock() {
+      errors.push(error);
+    }
+  }
+
+  pub fn into_errors(self) -> Vec<FileError> {
+    self.errors.into_inner().unwrap_or_default()
+  }
+}
+
+/// Errors sorted by path, as threads may report them in any order
+pub fn sorted_by_path(errors: &[FileError]) -> Vec<&FileError> {
+  let mut sorted: Vec<&FileError> = errors.iter().collect();
+  sorted.sort_by(|a, b| a.path.cmp(&b.path));
+  sorted
+}
+
+/// List unreadable paths and failed actions after the summary
+pub fn show_errors(errors: &[FileError]) {
+  if errors.len() < 1 {
+    return;
+  }
+  cprintln!("<red,italics>ERRORS</red,italics>");
+  for error in sorted_by_path(errors) {
+    let detail = if error.kind.has_detail() { format!("\t{}", error.message) } else { "".to_owned() };
+    cprintln!("<red>{: <17}</red>\t{: <11}\t<yellow>{}</yellow>{}", error.kind.as_str(), error.operation.as_str(), error.path.to_string_lossy(), detail);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_file_error_kinds() {
+    let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+    let error = FileError::from_io(Path::new("/data/b.txt"), FileOperation::Delete, &denied);
+    assert_eq!(error.kind, FileErrorKind::PermissionDenied);
+    assert!(error.operation.is_action());
+    let log = ErrorLog::default();
+    log.add(error);
+    log.add(FileError::from_io(Path::new("/data/a"), FileOperation::Scan, &io::Error::other("bad sector")));
+    let errors = log.into_errors();
+    let sorted = sorted_by_path(&errors);
+    assert_eq!(sorted[0].kind, FileErrorKind::Other);
+    assert!(!sorted[0].operation.is_action());
+  }
+}
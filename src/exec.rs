@@ -0,0 +1,347 @@
+//! Run a command for each matched file with --exec, or for batches of files with --exec-batch.
+//! Placeholders such as `{path}` or `{stem}` are replaced in each argument, so paths with spaces
+//! stay whole. The command is run directly, without a shell.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use rayon::prelude::*;
+use crate::errors::ArgError;
+use crate::file_system::FileEntry;
+use crate::progress::Progress;
+use crate::interrupt::is_interrupted;
+use crate::resource_row::ResourceRow;
+
+/// Placeholder names. `{}` is short for `{path}`.
+pub const PLACEHOLDERS: [&str; 8] = ["path", "relpath", "dir", "name", "stem", "ext", "size", "mtime"];
+
+/// Combined length of the file arguments in one batch, well below the usual limits of the OS
+const MAX_BATCH_BYTES: usize = 128 * 1024;
+const MAX_BATCH_FILES: usize = 4096;
+
+/// A command that ran but exited with a failure status, carried in an `io::Error`
+#[derive(Debug)]
+pub struct CommandFailed(pub String);
+
+impl fmt::Display for CommandFailed {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl std::error::Error for CommandFailed {}
+
+/// Whether the error is a failure status rather than a command that could not be started
+pub fn is_command_failure(error: &io::Error) -> bool {
+  error.get_ref().map(|inner| inner.is::<CommandFailed>()).unwrap_or(false)
+}
+
+/// Command template for --exec and --exec-batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecCommand {
+  pub args: Vec<String>,
+  /// Pass many files to each run, repeating every argument with a placeholder once per file
+  pub batch: bool,
+  /// Command output goes to stderr when stdout carries the JSON report
+  pub output_to_stderr: bool,
+}
+
+/// Split a command line into arguments with single and double quotes and backslash escapes, as a shell would
+pub(crate) fn split_args(command: &str) -> Option<Vec<String>> {
+  let mut args: Vec<String> = vec![];
+  let mut current: Option<String> = None;
+  let mut chars = command.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\'' => {
+        let arg = current.get_or_insert_with(String::new);
+        loop {
+          match chars.next()? {
+            '\'' => break,
+            inner => arg.push(inner),
+          }
+        }
+      },
+      '"' => {
+        let arg = current.get_or_insert_with(String::new);
+        loop {
+          match chars.next()? {
+            '"' => break,
+            '\\' => {
+              let escaped = chars.next()?;
+              if escaped != '"' && escaped != '\\' {
+                arg.push('\\');
+              }
+              arg.push(escaped);
+            },
+            inner => arg.push(inner),
+          }
+        }
+      },
+      '\\' => current.get_or_insert_with(String::new).push(chars.next()?),
+      _ if c.is_whitespace() => {
+        if let Some(arg) = current.take() {
+          args.push(arg);
+        }
+      },
+      _ => current.get_or_insert_with(String::new).push(c),
+    }
+  }
+  args.extend(current);
+  Some(args)
+}
+
+/// Piece of a command argument, either literal text or a placeholder name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgPart<'a> {
+  Text(&'a str),
+  Placeholder(&'a str),
+}
+
+/// Split an argument into literal text and `{name}` placeholders, with `{}` as `path`.
+/// `{{` and `}}` stand for literal braces. Braces around anything but a lowercase word,
+/// e.g. in `{print $1}`, are left as they are.
+fn parse_arg(arg: &str) -> Vec<ArgPart<'_>> {
+  let mut parts = vec![];
+  let mut text_start = 0;
+  let mut index = 0;
+  while index < arg.len() {
+    let rest = &arg[index..];
+    let escaped = rest.starts_with("{{") || rest.starts_with("}}");
+    let key = if rest.starts_with('{') && !escaped {
+      rest[1..].find('}').map(|len| &rest[1..1 + len]).filter(|key| key.chars().all(|c| c.is_ascii_lowercase() || c == '_'))
+    } else {
+      None
+    };
+    if escaped {
+      parts.push(ArgPart::Text(&arg[text_start..index + 1]));
+      index += 2;
+      text_start = index;
+    } else if let Some(key) = key {
+      parts.push(ArgPart::Text(&arg[text_start..index]));
+      parts.push(ArgPart::Placeholder(if key.len() < 1 { "path" } else { key }));
+      index += key.len() + 2;
+      text_start = index;
+    } else {
+      index += rest.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+    }
+  }
+  parts.push(ArgPart::Text(&arg[text_start..]));
+  parts.into_iter().filter(|part| *part != ArgPart::Text("")).collect()
+}
+
+/// Names of the placeholders in an argument
+fn find_placeholders(arg: &str) -> Vec<&str> {
+  parse_arg(arg).into_iter().filter_map(|part| match part {
+    ArgPart::Placeholder(key) => Some(key),
+    ArgPart::Text(_) => None,
+  }).collect()
+}
+
+fn placeholder_value(key: &str, row: &ResourceRow, root_ref: &Option<FileEntry>) -> String {
+  let path = row.path_ref();
+  match key {
+    "path" => path.to_string_lossy().to_string(),
+    "relpath" => row.relative_path(root_ref),
+    "dir" => path.parent().unwrap_or(Path::new("")).to_string_lossy().to_string(),
+    "name" => row.file_name(),
+    "stem" => path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default(),
+    "ext" => row.extension.clone(),
+    "size" => row.size().to_string(),
+    "mtime" => row.meta.modified.to_string(),
+    _ => "".to_owned(),
+  }
+}
+
+fn substitute(arg: &str, row: &ResourceRow, root_ref: &Option<FileEntry>) -> String {
+  parse_arg(arg).into_iter().map(|part| match part {
+    ArgPart::Text(text) => text.to_owned(),
+    ArgPart::Placeholder(key) => placeholder_value(key, row, root_ref),
+  }).collect()
+}
+
+/// An argument without placeholders, with escaped braces resolved
+fn unescape(arg: &str) -> String {
+  parse_arg(arg).into_iter().map(|part| match part {
+    ArgPart::Text(text) => text,
+    ArgPart::Placeholder(_) => "",
+  }).collect()
+}
+
+fn has_placeholder(arg: &str) -> bool {
+  find_placeholders(arg).len() > 0
+}
+
+fn status_error(program: &str, output: &Output) -> io::Error {
+  io::Error::other(CommandFailed(format!("{} failed with {}", program, output.status)))
+}
+
+impl ExecCommand {
+  /// Parse the command template. Without any placeholder, the file path is added as the last argument.
+  pub fn parse(command: &str, batch: bool) -> Result<ExecCommand, ArgError> {
+    let invalid = |reason: &str| ArgError::InvalidCommand(command.to_owned(), reason.to_owned());
+    let mut args = split_args(command).ok_or_else(|| invalid("unterminated quote or escape"))?;
+    if args.len() < 1 {
+      return Err(invalid("no command given"));
+    }
+    for arg in &args {
+      if let Some(key) = find_placeholders(arg).into_iter().find(|key| !PLACEHOLDERS.contains(key)) {
+        return Err(invalid(&format!("unknown placeholder {{{}}}, use one of {{{}}}, or {{{{ and }}}} for literal braces", key, PLACEHOLDERS.join("}, {"))));
+      }
+    }
+    if has_placeholder(&args[0]) {
+      return Err(invalid("the program name cannot be a placeholder"));
+    }
+    if !args.iter().any(|arg| has_placeholder(arg)) {
+      args.push("{path}".to_owned());
+    }
+    Ok(ExecCommand { args, batch, output_to_stderr: false })
+  }
+
+  pub fn program(&self) -> &str {
+    &self.args[0]
+  }
+
+  /// The command template with arguments quoted where needed
+  pub fn to_text(&self) -> String {
+    self.args.iter().map(|arg| {
+      if arg.len() < 1 || arg.contains(char::is_whitespace) { format!("'{}'", arg) } else { arg.to_owned() }
+    }).collect::<Vec<String>>().join(" ")
+  }
+
+  /// Arguments for the given files, where each argument with a placeholder is repeated once per file
+  pub fn command_args(&self, rows: &[&ResourceRow], root_ref: &Option<FileEntry>) -> Vec<String> {
+    let mut args = vec![];
+    for arg in &self.args {
+      if has_placeholder(arg) {
+        args.extend(rows.iter().map(|row| substitute(arg, row, root_ref)));
+      } else {
+        args.push(unescape(arg));
+      }
+    }
+    args
+  }
+
+  /// Group files so the arguments of each run stay within the batch limits
+  pub fn batches<'a>(&self, rows: &[&'a ResourceRow], root_ref: &Option<FileEntry>) -> Vec<Vec<&'a ResourceRow>> {
+    let mut batches: Vec<Vec<&ResourceRow>> = vec![];
+    let mut batch: Vec<&ResourceRow> = vec![];
+    let mut batch_bytes = 0;
+    for row in rows {
+      let file_bytes: usize = self.args.iter().filter(|arg| has_placeholder(arg)).map(|arg| substitute(arg, row, root_ref).len() + 1).sum();
+      if batch.len() > 0 && (batch_bytes + file_bytes > MAX_BATCH_BYTES || batch.len() >= MAX_BATCH_FILES) {
+        batches.push(std::mem::take(&mut batch));
+        batch_bytes = 0;
+      }
+      batch.push(*row);
+      batch_bytes += file_bytes;
+    }
+    if batch.len() > 0 {
+      batches.push(batch);
+    }
+    batches
+  }
+
+  /// Run the command once and pass on its output once it has finished, so parallel runs do not interleave
+  fn run_args(&self, args: &[String], progress: Option<&Progress>) -> io::Result<()> {
+    let output = Command::new(&args[0]).args(&args[1..]).stdin(Stdio::null()).output()
+      .map_err(|error| io::Error::new(error.kind(), format!("could not run {}: {}", self.program(), error)))?;
+    if output.stdout.len() > 0 || output.stderr.len() > 0 {
+      if let Some(progress) = progress {
+        progress.finish();
+      }
+      if output.stdout.len() > 0 {
+        let _ = if self.output_to_stderr { io::stderr().lock().write_all(&output.stdout) } else { io::stdout().lock().write_all(&output.stdout) };
+      }
+      if output.stderr.len() > 0 {
+        let _ = io::stderr().lock().write_all(&output.stderr);
+      }
+    }
+    if output.status.success() {
+      Ok(())
+    } else {
+      Err(status_error(self.program(), &output))
+    }
+  }
+
+  /// Run the command for one file, e.g. as it is found while streaming
+  pub fn run_file(&self, row: &ResourceRow, root_ref: &Option<FileEntry>) -> io::Result<()> {
+    self.run_args(&self.command_args(&[row], root_ref), None)
+  }
+
+  /// Run the command for each file or batch of files on a pool of `jobs` threads.
+  /// Once interrupted, runs not yet started are skipped.
+  pub fn run<'a>(&self, rows: &[&'a ResourceRow], root_ref: &Option<FileEntry>, jobs: usize, progress: &Progress) -> Vec<(Vec<&'a ResourceRow>, io::Result<()>)> {
+    let batches = if self.batch { self.batches(rows, root_ref) } else { rows.iter().map(|row| vec![*row]).collect() };
+    let run = || batches.into_par_iter().filter(|_| !is_interrupted()).map(|batch| {
+      let result = self.run_args(&self.command_args(&batch, root_ref), Some(progress));
+      for row in &batch {
+        progress.add_matched(row.size());
+      }
+      (batch, result)
+    }).collect();
+    match rayon::ThreadPoolBuilder::new().num_threads(jobs.max(1)).build() {
+      Ok(pool) => pool.install(run),
+      Err(_) => run(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+  use crate::file_meta::FileMeta;
+
+  fn sample_rows(paths: &[&str]) -> Vec<ResourceRow> {
+    crate::resource_row::sample_rows(paths, |_| FileMeta { size: 1200, modified: 1700000000, ..FileMeta::default() })
+  }
+
+  #[test]
+  fn test_parse_and_placeholders() {
+    let command = ExecCommand::parse(r#"convert {path} -resize "50%" '{dir}/{stem}_small.jpg'"#, false).unwrap();
+    assert_eq!(command.args, vec!["convert", "{path}", "-resize", "50%", "{dir}/{stem}_small.jpg"]);
+    let rows = sample_rows(&["/photos/2024/beach day.jpg"]);
+    let root = Some(FileEntry::new(PathBuf::from("/photos"), 0, true, false));
+    let row_refs: Vec<&ResourceRow> = rows.iter().collect();
+    assert_eq!(command.command_args(&row_refs, &root), vec!["convert", "/photos/2024/beach day.jpg", "-resize", "50%", "/photos/2024/beach day_small.jpg"]);
+    let command = ExecCommand::parse("echo {relpath}:{ext}:{size}:{mtime} {name} awk '{print $1}'", false).unwrap();
+    assert_eq!(command.command_args(&row_refs, &root), vec!["echo", "2024/beach day.jpg:jpg:1200:1700000000", "beach day.jpg", "awk", "{print $1}"]);
+    assert_eq!(ExecCommand::parse("gzip -k", false).unwrap().args, vec!["gzip", "-k", "{path}"]);
+    assert_eq!(ExecCommand::parse("ls {}", false).unwrap().command_args(&row_refs, &root), vec!["ls", "/photos/2024/beach day.jpg"]);
+    assert!(matches!(ExecCommand::parse("echo {base}", false), Err(ArgError::InvalidCommand(_, _))));
+    let command = ExecCommand::parse("awk '{{print}}' {path}", false).unwrap();
+    assert_eq!(command.command_args(&row_refs, &root), vec!["awk", "{print}", "/photos/2024/beach day.jpg"]);
+    let command = ExecCommand::parse("echo {{{name}}} {{}}", false).unwrap();
+    assert_eq!(command.command_args(&row_refs, &root), vec!["echo", "{beach day.jpg}", "{}"]);
+    assert!(matches!(ExecCommand::parse("echo 'open", false), Err(ArgError::InvalidCommand(_, _))));
+    assert!(matches!(ExecCommand::parse("  ", false), Err(ArgError::InvalidCommand(_, _))));
+  }
+
+  #[test]
+  fn test_batches() {
+    let paths: Vec<String> = (0..10).map(|index| format!("/data/{}.log", index)).collect();
+    let rows = sample_rows(&paths.iter().map(|path| path.as_str()).collect::<Vec<&str>>());
+    let row_refs: Vec<&ResourceRow> = rows.iter().collect();
+    let command = ExecCommand::parse("tar czf logs.tgz {path}", true).unwrap();
+    let batches = command.batches(&row_refs, &None);
+    assert_eq!(batches.len(), 1);
+    let args = command.command_args(&batches[0], &None);
+    assert_eq!(args.len(), 13);
+    assert_eq!(&args[..4], &["tar", "czf", "logs.tgz", "/data/0.log"]);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_exit_status() {
+    let rows = sample_rows(&["/data/a.log"]);
+    assert!(ExecCommand::parse("true", false).unwrap().run_file(&rows[0], &None).is_ok());
+    let error = ExecCommand::parse("sh -c 'exit 3' sh {path}", false).unwrap().run_file(&rows[0], &None).unwrap_err();
+    assert!(is_command_failure(&error));
+    assert!(error.to_string().contains("sh failed with exit status: 3"));
+    let error = ExecCommand::parse("fileorg-missing-command", false).unwrap().run_file(&rows[0], &None).unwrap_err();
+    assert!(!is_command_failure(&error));
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+  }
+}
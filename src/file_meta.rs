@@ -0,0 +1,84 @@
+//! File metadata captured once per directory entry.
+//! Every stat may be a network round trip on NFS or FUSE mounts, so filters,
+//! listings and statistics all read from this snapshot.
+
+use std::fs::Metadata;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use crate::criteria::TimeField;
+
+/// Size, timestamps, ownership and inode of a file as Unix seconds and ids
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FileMeta {
+  pub size: u64,
+  pub modified: u64,
+  pub accessed: u64,
+  pub changed: u64,
+  pub created: Option<u64>,
+  pub uid: u32,
+  pub gid: u32,
+  pub mode: u32,
+  pub inode: u64,
+}
+
+fn to_timestamp(time_ref: std::io::Result<SystemTime>) -> Option<u64> {
+  time_ref.ok().and_then(|sys_time| sys_time.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs())
+}
+
+impl FileMeta {
+  #[cfg(unix)]
+  pub fn from_metadata(meta: &Metadata) -> Self {
+    FileMeta {
+      size: meta.len(),
+      modified: to_timestamp(meta.modified()).unwrap_or(0),
+      accessed: to_timestamp(meta.accessed()).unwrap_or(0),
+      changed: meta.ctime().max(0) as u64,
+      created: to_timestamp(meta.created()),
+      uid: meta.uid(),
+      gid: meta.gid(),
+      mode: meta.mode(),
+      inode: meta.ino(),
+    }
+  }
+
+  /// Windows has no Unix owners, mode bits, inodes or status change time
+  #[cfg(not(unix))]
+  pub fn from_metadata(meta: &Metadata) -> Self {
+    let modified = to_timestamp(meta.modified()).unwrap_or(0);
+    FileMeta {
+      size: meta.len(),
+      modified,
+      accessed: to_timestamp(meta.accessed()).unwrap_or(0),
+      changed: modified,
+      created: to_timestamp(meta.created()),
+      ..FileMeta::default()
+    }
+  }
+
+  /// Timestamp used for age filters and listings
+  pub fn timestamp(&self, time_field: TimeField) -> u64 {
+    match time_field {
+      TimeField::Modified => self.modified,
+      TimeField::Accessed => self.accessed,
+      TimeField::Changed => self.changed,
+      // fall back to the status change time if the file system does not record birth times
+      TimeField::Created => self.created.unwrap_or(self.changed),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_created_falls_back_to_changed() {
+    let meta = FileMeta { modified: 30, accessed: 40, changed: 20, ..FileMeta::default() };
+    assert_eq!(meta.timestamp(TimeField::Modified), 30);
+    assert_eq!(meta.timestamp(TimeField::Accessed), 40);
+    assert_eq!(meta.timestamp(TimeField::Created), 20);
+    let meta = FileMeta { created: Some(10), ..meta };
+    assert_eq!(meta.timestamp(TimeField::Created), 10);
+  }
+}
@@ -0,0 +1,462 @@
+//! File system access used by scans and actions.
+//!
+//! [`OsFileSystem`] works on the disk, while [`MemoryFileSystem`] keeps files in memory with
+//! controllable timestamps and injected failures, so scans and actions can be tested deterministically.
+//! Permission changes and job state files always work on the disk.
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::file_meta::FileMeta;
+use crate::transfer::Throttle;
+
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A file or directory found while walking, with its depth below the scanned root.
+/// Symlinks are followed, so a link to a directory is a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+  path: PathBuf,
+  depth: usize,
+  is_dir: bool,
+  is_symlink: bool,
+}
+
+impl FileEntry {
+  pub fn new(path: PathBuf, depth: usize, is_dir: bool, is_symlink: bool) -> Self {
+    FileEntry { path, depth, is_dir, is_symlink }
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn depth(&self) -> usize {
+    self.depth
+  }
+
+  /// The last path component, or the whole path for a root such as `/`
+  pub fn file_name(&self) -> &OsStr {
+    self.path.file_name().unwrap_or(self.path.as_os_str())
+  }
+
+  pub fn is_dir(&self) -> bool {
+    self.is_dir
+  }
+
+  pub fn path_is_symlink(&self) -> bool {
+    self.is_symlink
+  }
+}
+
+/// The children of a directory, one level deeper, in the order the file system lists them,
+/// and the entries that could not be read with their paths
+#[derive(Debug, Default)]
+pub struct DirListing {
+  pub entries: Vec<FileEntry>,
+  pub failures: Vec<(PathBuf, io::Error)>,
+}
+
+/// Directory walking, metadata and the file operations behind copy, move and delete
+pub trait FileSystem: Debug + Send + Sync {
+  /// The entry for a path at depth 0
+  fn entry(&self, path: &Path) -> io::Result<FileEntry>;
+
+  /// The children of a directory. An entry that cannot be read is skipped and listed as a failure,
+  /// so only an unreadable directory fails the whole listing
+  fn read_dir(&self, dir: &FileEntry) -> io::Result<DirListing>;
+
+  /// Metadata of a file, following symlinks
+  fn metadata(&self, path: &Path) -> io::Result<FileMeta>;
+
+  fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+  /// Device number of the file system containing the path, if known
+  fn device(&self, path: &Path) -> Option<u64>;
+
+  fn exists(&self, path: &Path) -> bool;
+
+  fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+  fn rename(&self, source: &Path, target: &Path) -> io::Result<()>;
+
+  /// Copy the contents and permissions of a file, within the bandwidth limit if throttled
+  fn copy(&self, source: &Path, target: &Path, throttle: Option<&Throttle>) -> io::Result<u64>;
+
+  fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+  /// Job state files for resuming copies and moves are only written next to files on disk
+  fn is_on_disk(&self) -> bool {
+    false
+  }
+}
+
+/// The file system of the operating system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+  fn entry(&self, path: &Path) -> io::Result<FileEntry> {
+    let is_dir = fs::metadata(path)?.is_dir();
+    let is_symlink = path.is_symlink();
+    Ok(FileEntry::new(path.to_path_buf(), 0, is_dir, is_symlink))
+  }
+
+  fn read_dir(&self, dir: &FileEntry) -> io::Result<DirListing> {
+    let mut listing = DirListing::default();
+    for item in fs::read_dir(dir.path())? {
+      // an entry that cannot be read has no path of its own, so it is reported on the directory
+      let item = match item {
+        Ok(item) => item,
+        Err(error) => {
+          listing.failures.push((dir.path().to_path_buf(), error));
+          continue;
+        }
+      };
+      let path = item.path();
+      let file_type = match item.file_type() {
+        Ok(file_type) => file_type,
+        Err(error) => {
+          listing.failures.push((path, error));
+          continue;
+        }
+      };
+      let is_symlink = file_type.is_symlink();
+      // broken links are listed as files, so reading their metadata reports the error
+      let is_dir = if is_symlink { path.is_dir() } else { file_type.is_dir() };
+      listing.entries.push(FileEntry::new(path, dir.depth() + 1, is_dir, is_symlink));
+    }
+    Ok(listing)
+  }
+
+  fn metadata(&self, path: &Path) -> io::Result<FileMeta> {
+    fs::metadata(path).map(|meta| FileMeta::from_metadata(&meta))
+  }
+
+  fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+    fs::canonicalize(path)
+  }
+
+  #[cfg(unix)]
+  fn device(&self, path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|meta| meta.dev())
+  }
+
+  /// Device numbers are not compared on other platforms
+  #[cfg(not(unix))]
+  fn device(&self, _path: &Path) -> Option<u64> {
+    None
+  }
+
+  fn exists(&self, path: &Path) -> bool {
+    path.exists()
+  }
+
+  fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+    fs::create_dir_all(path)
+  }
+
+  fn rename(&self, source: &Path, target: &Path) -> io::Result<()> {
+    fs::rename(source, target)
+  }
+
+  /// Copy in chunks when throttled, so large files do not exceed the bandwidth limit
+  fn copy(&self, source: &Path, target: &Path, throttle: Option<&Throttle>) -> io::Result<u64> {
+    if let Some(throttle) = throttle {
+      let mut reader = File::open(source)?;
+      let mut writer = File::create(target)?;
+      let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+      let mut num_bytes = 0u64;
+      loop {
+        let len = reader.read(&mut buffer)?;
+        if len == 0 {
+          break;
+        }
+        writer.write_all(&buffer[..len])?;
+        num_bytes += len as u64;
+        throttle.consume(len as u64);
+      }
+      fs::set_permissions(target, reader.metadata()?.permissions())?;
+      Ok(num_bytes)
+    } else {
+      fs::copy(source, target)
+    }
+  }
+
+  fn remove_file(&self, path: &Path) -> io::Result<()> {
+    fs::remove_file(path)
+  }
+
+  fn is_on_disk(&self) -> bool {
+    true
+  }
+}
+
+/// File system calls that can be made to fail in a [`MemoryFileSystem`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FsOperation {
+  ReadDir,
+  /// Reading one entry of a directory listing, which leaves its siblings listed
+  ReadEntry,
+  Metadata,
+  CreateDir,
+  Rename,
+  Copy,
+  Remove,
+}
+
+#[derive(Debug, Clone)]
+struct MemoryNode {
+  is_dir: bool,
+  contents: Vec<u8>,
+  meta: FileMeta,
+}
+
+/// Injected error for an operation on a path or anything below it
+#[derive(Debug, Clone)]
+struct MemoryFailure {
+  operation: FsOperation,
+  path: PathBuf,
+  kind: io::ErrorKind,
+}
+
+/// An in-memory file system for tests. Directories list their children sorted by name.
+///
+/// ```
+/// use std::io::ErrorKind;
+/// use std::path::Path;
+/// use file_organiser::file_system::{FileSystem, FsOperation, MemoryFileSystem};
+///
+/// let fs = MemoryFileSystem::new();
+/// fs.add_file("/data/report.pdf", b"pdf", 1_600_000_000);
+/// fs.mount("/backup", 2);
+/// fs.fail(FsOperation::Copy, "/backup/full", ErrorKind::StorageFull);
+/// assert_eq!(fs.metadata(Path::new("/data/report.pdf")).unwrap().size, 3);
+/// assert!(fs.rename(Path::new("/data/report.pdf"), Path::new("/backup/report.pdf")).is_err());
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryFileSystem {
+  nodes: Mutex<BTreeMap<PathBuf, MemoryNode>>,
+  mounts: Mutex<Vec<(PathBuf, u64)>>,
+  failures: Mutex<Vec<MemoryFailure>>,
+  last_inode: AtomicU64,
+}
+
+fn not_found(path: &Path) -> io::Error {
+  io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path.display()))
+}
+
+impl MemoryFileSystem {
+  pub fn new() -> Self {
+    let fs = MemoryFileSystem::default();
+    fs.add_dir("/");
+    fs
+  }
+
+  fn next_inode(&self) -> u64 {
+    self.last_inode.fetch_add(1, Ordering::Relaxed) + 1
+  }
+
+  /// Add a directory and any missing parents
+  pub fn add_dir(&self, path: impl AsRef<Path>) {
+    let mut nodes = self.nodes.lock().unwrap();
+    for dir in path.as_ref().ancestors() {
+      if dir.as_os_str().len() > 0 && !nodes.contains_key(dir) {
+        let meta = FileMeta { mode: 0o040755, inode: self.next_inode(), ..FileMeta::default() };
+        nodes.insert(dir.to_path_buf(), MemoryNode { is_dir: true, contents: vec![], meta });
+      }
+    }
+  }
+
+  /// Add a file and any missing parent directories, with all timestamps set to modified
+  pub fn add_file(&self, path: impl AsRef<Path>, contents: &[u8], modified: u64) {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+      self.add_dir(parent);
+    }
+    let mut nodes = self.nodes.lock().unwrap();
+    let meta = FileMeta {
+      size: contents.len() as u64,
+      modified,
+      accessed: modified,
+      changed: modified,
+      created: Some(modified),
+      mode: 0o100644,
+      inode: self.next_inode(),
+      ..FileMeta::default()
+    };
+    nodes.insert(path.to_path_buf(), MemoryNode { is_dir: false, contents: contents.to_vec(), meta });
+  }
+
+  /// Replace the metadata of a file, e.g. to set its timestamps or owner. The size follows the contents.
+  pub fn set_meta(&self, path: impl AsRef<Path>, meta: FileMeta) {
+    if let Some(node) = self.nodes.lock().unwrap().get_mut(path.as_ref()) {
+      node.meta = FileMeta { size: node.contents.len() as u64, ..meta };
+    }
+  }
+
+  /// Place a directory and everything below it on another device, so moves out of it cross devices
+  pub fn mount(&self, path: impl AsRef<Path>, device: u64) {
+    self.add_dir(path.as_ref());
+    self.mounts.lock().unwrap().push((path.as_ref().to_path_buf(), device));
+  }
+
+  /// Make an operation fail when it involves the path or anything below it,
+  /// e.g. `ErrorKind::PermissionDenied` for EACCES or `ErrorKind::StorageFull` for ENOSPC
+  pub fn fail(&self, operation: FsOperation, path: impl AsRef<Path>, kind: io::ErrorKind) {
+    self.failures.lock().unwrap().push(MemoryFailure { operation, path: path.as_ref().to_path_buf(), kind });
+  }
+
+  /// Contents of a file, or None if there is no such file
+  pub fn contents(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+    self.nodes.lock().unwrap().get(path.as_ref()).filter(|node| !node.is_dir).map(|node| node.contents.clone())
+  }
+
+  /// Paths of all files, sorted
+  pub fn files(&self) -> Vec<PathBuf> {
+    self.nodes.lock().unwrap().iter().filter(|(_, node)| !node.is_dir).map(|(path, _)| path.clone()).collect()
+  }
+
+  fn check(&self, operation: FsOperation, paths: &[&Path]) -> io::Result<()> {
+    let failures = self.failures.lock().unwrap();
+    let failure = failures.iter().find(|failure| failure.operation == operation && paths.iter().any(|path| path.starts_with(&failure.path)));
+    match failure {
+      Some(failure) => Err(io::Error::new(failure.kind, format!("injected {:?} failure", operation))),
+      None => Ok(()),
+    }
+  }
+
+  fn node(&self, path: &Path) -> io::Result<MemoryNode> {
+    self.nodes.lock().unwrap().get(path).cloned().ok_or(not_found(path))
+  }
+
+  /// The parent directory must exist, as on disk
+  fn check_parent(&self, path: &Path) -> io::Result<()> {
+    match path.parent().map(|parent| self.node(parent)) {
+      Some(Ok(node)) if node.is_dir => Ok(()),
+      _ => Err(not_found(path)),
+    }
+  }
+}
+
+impl FileSystem for MemoryFileSystem {
+  fn entry(&self, path: &Path) -> io::Result<FileEntry> {
+    self.check(FsOperation::Metadata, &[path])?;
+    let node = self.node(path)?;
+    Ok(FileEntry::new(path.to_path_buf(), 0, node.is_dir, false))
+  }
+
+  fn read_dir(&self, dir: &FileEntry) -> io::Result<DirListing> {
+    self.check(FsOperation::ReadDir, &[dir.path()])?;
+    let children: Vec<(PathBuf, bool)> = self.nodes.lock().unwrap().iter()
+      .filter(|(path, _)| path.parent() == Some(dir.path()))
+      .map(|(path, node)| (path.clone(), node.is_dir))
+      .collect();
+    let mut listing = DirListing::default();
+    for (path, is_dir) in children {
+      match self.check(FsOperation::ReadEntry, &[&path]) {
+        Ok(()) => listing.entries.push(FileEntry::new(path, dir.depth() + 1, is_dir, false)),
+        Err(error) => listing.failures.push((path, error)),
+      }
+    }
+    Ok(listing)
+  }
+
+  fn metadata(&self, path: &Path) -> io::Result<FileMeta> {
+    self.check(FsOperation::Metadata, &[path])?;
+    self.node(path).map(|node| node.meta)
+  }
+
+  fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+    self.node(path).map(|_| path.to_path_buf())
+  }
+
+  fn device(&self, path: &Path) -> Option<u64> {
+    let mounts = self.mounts.lock().unwrap();
+    let mount = mounts.iter().filter(|(mount_path, _)| path.starts_with(mount_path)).max_by_key(|(mount_path, _)| mount_path.components().count());
+    Some(mount.map(|(_, device)| *device).unwrap_or(1))
+  }
+
+  fn exists(&self, path: &Path) -> bool {
+    self.nodes.lock().unwrap().contains_key(path)
+  }
+
+  fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+    self.check(FsOperation::CreateDir, &[path])?;
+    self.add_dir(path);
+    Ok(())
+  }
+
+  fn rename(&self, source: &Path, target: &Path) -> io::Result<()> {
+    self.check(FsOperation::Rename, &[source, target])?;
+    self.check_parent(target)?;
+    if self.device(source) != self.device(target) {
+      return Err(io::Error::new(io::ErrorKind::CrossesDevices, "cross-device link"));
+    }
+    let mut nodes = self.nodes.lock().unwrap();
+    let node = nodes.remove(source).ok_or(not_found(source))?;
+    nodes.insert(target.to_path_buf(), node);
+    Ok(())
+  }
+
+  fn copy(&self, source: &Path, target: &Path, throttle: Option<&Throttle>) -> io::Result<u64> {
+    self.check(FsOperation::Copy, &[source, target])?;
+    self.check_parent(target)?;
+    let mut node = self.node(source)?;
+    let num_bytes = node.contents.len() as u64;
+    if let Some(throttle) = throttle {
+      throttle.consume(num_bytes);
+    }
+    node.meta.inode = self.next_inode();
+    self.nodes.lock().unwrap().insert(target.to_path_buf(), node);
+    Ok(num_bytes)
+  }
+
+  fn remove_file(&self, path: &Path) -> io::Result<()> {
+    self.check(FsOperation::Remove, &[path])?;
+    let mut nodes = self.nodes.lock().unwrap();
+    match nodes.get(path) {
+      Some(node) if node.is_dir => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is a directory", path.display()))),
+      Some(_) => {
+        nodes.remove(path);
+        Ok(())
+      },
+      None => Err(not_found(path)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_memory_file_system() {
+    let fs = MemoryFileSystem::new();
+    fs.add_file("/a/b/c.txt", b"abc", 100);
+    let root = fs.entry(Path::new("/a")).unwrap();
+    let children = fs.read_dir(&root).unwrap();
+    assert_eq!(children.entries, vec![FileEntry::new(PathBuf::from("/a/b"), 1, true, false)]);
+    fs.copy(Path::new("/a/b/c.txt"), Path::new("/a/c.txt"), None).unwrap();
+    assert_eq!(fs.contents("/a/c.txt"), Some(b"abc".to_vec()));
+    assert_eq!(fs.metadata(Path::new("/a/c.txt")).unwrap().modified, 100);
+    assert_eq!(fs.copy(Path::new("/a/c.txt"), Path::new("/x/c.txt"), None).unwrap_err().kind(), io::ErrorKind::NotFound);
+    fs.mount("/mnt", 2);
+    assert_eq!(fs.rename(Path::new("/a/c.txt"), Path::new("/mnt/c.txt")).unwrap_err().kind(), io::ErrorKind::CrossesDevices);
+    fs.add_file("/a/d.txt", b"d", 100);
+    fs.fail(FsOperation::ReadEntry, "/a/b", io::ErrorKind::PermissionDenied);
+    let children = fs.read_dir(&root).unwrap();
+    assert_eq!(children.entries.iter().map(FileEntry::path).collect::<Vec<_>>(), vec![Path::new("/a/c.txt"), Path::new("/a/d.txt")]);
+    assert_eq!(children.failures.iter().map(|(path, error)| (path.as_path(), error.kind())).collect::<Vec<_>>(), vec![(Path::new("/a/b"), io::ErrorKind::PermissionDenied)]);
+    fs.fail(FsOperation::Remove, "/a/b", io::ErrorKind::PermissionDenied);
+    assert_eq!(fs.remove_file(Path::new("/a/b/c.txt")).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    fs.remove_file(Path::new("/a/c.txt")).unwrap();
+    assert_eq!(fs.files(), vec![PathBuf::from("/a/b/c.txt"), PathBuf::from("/a/d.txt")]);
+  }
+}
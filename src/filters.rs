@@ -0,0 +1,206 @@
+//! Match predicates applied to each scanned file.
+//! Criteria hold a list of filters and a file matches when every filter accepts it.
+//! Filters that only look at the file name run before the file metadata is read.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use string_patterns::Regex;
+use crate::resource_row::ResourceRow;
+use crate::file_system::{FileEntry, FileSystem};
+use crate::permissions::PermMatch;
+use crate::owners::{user_name, group_name};
+use crate::utils::{current_timestamp, is_in_extensions, is_not_in_extensions};
+
+/// State shared by all filters during one scan
+#[derive(Debug, Clone)]
+pub struct ScanContext {
+  pub root: Option<FileEntry>,
+  /// Unix time when the scan started, so ages do not drift during long scans
+  pub now: u64,
+  /// File system being scanned, e.g. to check for related files
+  pub fs: Arc<dyn FileSystem>,
+}
+
+impl ScanContext {
+  pub fn new(root: Option<FileEntry>, fs: Arc<dyn FileSystem>) -> Self {
+    ScanContext { root, now: current_timestamp() as u64, fs }
+  }
+
+  /// Age of the file in days at the start of the scan
+  pub fn days_old(&self, row: &ResourceRow) -> f64 {
+    self.now.saturating_sub(row.ts) as f64 / 86400f64
+  }
+}
+
+/// A predicate on scanned files. Custom filters can be added with `CriteriaBuilder::filter`.
+pub trait Filter: Debug + Send + Sync {
+  fn matches(&self, row: &ResourceRow, ctx: &ScanContext) -> bool;
+
+  /// Filters that only need the path can skip reading the file metadata
+  fn needs_metadata(&self) -> bool {
+    true
+  }
+}
+
+/// Files older than min_age days and/or younger than max_age days
+#[derive(Debug, Clone)]
+pub struct AgeFilter {
+  pub min_age: f64,
+  pub max_age: f64,
+}
+
+impl Filter for AgeFilter {
+  fn matches(&self, row: &ResourceRow, ctx: &ScanContext) -> bool {
+    let days_old = ctx.days_old(row);
+    let has_min = self.min_age > 0f64;
+    let has_max = self.max_age > 0f64 && self.max_age > self.min_age;
+    if !has_min && !has_max {
+      return true;
+    }
+    let mut matches = false;
+    if has_min {
+      matches = days_old >= self.min_age;
+    }
+    if has_max {
+      let matches_max = days_old < self.max_age;
+      if matches && !matches_max {
+        matches = false;
+      } else if !has_min {
+        matches = matches_max;
+      }
+    }
+    matches
+  }
+}
+
+/// Files within a size range in bytes, where 0 means no limit
+#[derive(Debug, Clone)]
+pub struct SizeFilter {
+  pub min: u64,
+  pub max: u64,
+}
+
+impl Filter for SizeFilter {
+  fn matches(&self, row: &ResourceRow, _ctx: &ScanContext) -> bool {
+    let size = row.size();
+    (size >= self.min || self.min < 1) && (size <= self.max || self.max < 1)
+  }
+}
+
+/// Included and excluded extensions, where `_` stands for files without an extension
+#[derive(Debug, Clone)]
+pub struct ExtensionFilter {
+  pub include: Vec<String>,
+  pub exclude: Vec<String>,
+}
+
+impl Filter for ExtensionFilter {
+  fn matches(&self, row: &ResourceRow, _ctx: &ScanContext) -> bool {
+    is_in_extensions(&row.extension, &self.include) && is_not_in_extensions(&row.extension, &self.exclude)
+  }
+
+  fn needs_metadata(&self) -> bool {
+    false
+  }
+}
+
+/// File names matching a pattern
+#[derive(Debug, Clone)]
+pub struct PatternFilter(pub Regex);
+
+impl Filter for PatternFilter {
+  fn matches(&self, row: &ResourceRow, _ctx: &ScanContext) -> bool {
+    self.0.is_match(&row.file_name())
+  }
+
+  fn needs_metadata(&self) -> bool {
+    false
+  }
+}
+
+/// File names not matching a pattern
+#[derive(Debug, Clone)]
+pub struct OmitPatternFilter(pub Regex);
+
+impl Filter for OmitPatternFilter {
+  fn matches(&self, row: &ResourceRow, _ctx: &ScanContext) -> bool {
+    !self.0.is_match(&row.file_name())
+  }
+
+  fn needs_metadata(&self) -> bool {
+    false
+  }
+}
+
+/// Skips hidden files. Hidden directories are already pruned from the walk.
+#[derive(Debug, Clone)]
+pub struct HiddenFilter;
+
+impl Filter for HiddenFilter {
+  fn matches(&self, row: &ResourceRow, _ctx: &ScanContext) -> bool {
+    !row.file_name().starts_with('.')
+  }
+
+  fn needs_metadata(&self) -> bool {
+    false
+  }
+}
+
+/// Owner, group and permission bits, or files whose owner or group no longer exists
+#[derive(Debug, Clone, Default)]
+pub struct OwnerFilter {
+  pub owner_ids: Vec<u32>,
+  pub group_ids: Vec<u32>,
+  pub perm: Option<PermMatch>,
+  pub no_user: bool,
+  pub no_group: bool,
+}
+
+impl Filter for OwnerFilter {
+  fn matches(&self, row: &ResourceRow, _ctx: &ScanContext) -> bool {
+    let (uid, gid, mode) = row.owner_mode();
+    (self.owner_ids.len() < 1 || self.owner_ids.contains(&uid))
+    && (self.group_ids.len() < 1 || self.group_ids.contains(&gid))
+    && (!self.no_user || user_name(uid).is_none())
+    && (!self.no_group || group_name(gid).is_none())
+    && self.perm.map(|perm| perm.matches(mode)).unwrap_or(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::criteria::CriteriaBuilder;
+  use crate::resource_row::sample_rows;
+  use crate::file_meta::FileMeta;
+  use crate::file_system::MemoryFileSystem;
+
+  #[test]
+  fn test_name_filters() {
+    let rows = sample_rows(&["/data/.hidden.txt", "/data/notes.txt", "/data/photo.jpg", "/data/todo.txt"], |_| FileMeta::default());
+    let ctx = ScanContext::new(None, Arc::new(MemoryFileSystem::new()));
+    let criteria = CriteriaBuilder::new().extensions("txt").omit_pattern("todo").build().unwrap();
+    let names: Vec<String> = rows.iter().filter(|row| criteria.matches_name(row, &ctx)).map(|row| row.file_name()).collect();
+    assert_eq!(names, vec!["notes.txt"]);
+    assert!(criteria.matches_metadata(&rows[1], &ctx));
+  }
+
+  #[test]
+  fn test_metadata_filters() {
+    let mut rows = sample_rows(&["/data/a.log", "/data/b.log"], |_| FileMeta::default());
+    let ctx = ScanContext { root: None, now: 100 * 86400, fs: Arc::new(MemoryFileSystem::new()) };
+    rows[0].ts = 10 * 86400;
+    rows[0].meta.size = 2048;
+    rows[1].ts = 95 * 86400;
+    rows[1].meta.size = 512;
+    let age = AgeFilter { min_age: 30f64, max_age: 0f64 };
+    assert!(age.matches(&rows[0], &ctx));
+    assert!(!age.matches(&rows[1], &ctx));
+    let size = SizeFilter { min: 1024, max: 0 };
+    assert!(size.matches(&rows[0], &ctx));
+    assert!(!size.matches(&rows[1], &ctx));
+    let criteria = CriteriaBuilder::new().filter(SizeFilter { min: 0, max: 1000 }).build().unwrap();
+    assert!(!criteria.matches_metadata(&rows[0], &ctx));
+    assert!(criteria.matches_metadata(&rows[1], &ctx));
+  }
+}
@@ -0,0 +1,168 @@
+//! Commands run before and after the action phase with --before-hook and --after-hook,
+//! e.g. to stop a service before moving its data and start it again afterwards.
+//! Each hook receives a summary of the run as FILEORG_* environment variables and as JSON on stdin.
+
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use serde_json::{json, Value};
+use crate::action::ActionTally;
+use crate::criteria::Criteria;
+use crate::errors::ArgError;
+use crate::exec::{CommandFailed, split_args};
+use crate::resource_row::ResourceTree;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HookPhase {
+  Before,
+  After,
+}
+
+impl HookPhase {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      HookPhase::Before => "before",
+      HookPhase::After => "after",
+    }
+  }
+}
+
+/// Matched files and action results passed to a hook
+#[derive(Debug, Clone)]
+pub struct RunSummary {
+  pub phase: HookPhase,
+  pub path: String,
+  pub action: String,
+  pub target: Option<String>,
+  pub files: u64,
+  pub size: u64,
+  /// Files processed by the action, or None if it has not been applied
+  pub result: Option<ActionTally>,
+  pub errors: u64,
+}
+
+impl RunSummary {
+  /// Summary of the scanned trees, or of the criteria alone before a streaming scan.
+  /// With several roots, the path lists them separated by colons and the counts are combined.
+  pub fn new(phase: HookPhase, path: &str, criteria: &Criteria, trees: &[ResourceTree]) -> Self {
+    let result = trees.iter().filter_map(|tree| tree.tally).reduce(|mut combined, tally| {
+      combined.merge(&tally);
+      combined
+    });
+    RunSummary {
+      phase,
+      path: path.to_owned(),
+      action: criteria.action.key().to_owned(),
+      target: criteria.target.clone(),
+      files: trees.iter().map(|tree| tree.num_files() as u64).sum(),
+      size: trees.iter().map(|tree| tree.size()).sum(),
+      result,
+      errors: trees.iter().map(|tree| tree.errors.len() as u64).sum(),
+    }
+  }
+
+  /// Same fields as the JSON report, with the result counts null until the action is applied
+  pub fn to_json(&self) -> Value {
+    json!({
+      "hook": self.phase.as_str(),
+      "path": self.path,
+      "action": self.action,
+      "target": self.target,
+      "files": self.files,
+      "size": self.size,
+      "result": self.result.map(|tally| json!({ "files": tally.num, "size": tally.size, "failed": tally.num_failed })),
+      "errors": self.errors,
+    })
+  }
+
+  pub fn env_vars(&self) -> Vec<(&'static str, String)> {
+    let tally = self.result.unwrap_or_default();
+    vec![
+      ("FILEORG_HOOK", self.phase.as_str().to_owned()),
+      ("FILEORG_PATH", self.path.clone()),
+      ("FILEORG_ACTION", self.action.clone()),
+      ("FILEORG_TARGET", self.target.clone().unwrap_or_default()),
+      ("FILEORG_FILES", self.files.to_string()),
+      ("FILEORG_SIZE", self.size.to_string()),
+      ("FILEORG_APPLIED", (self.result.is_some() as u8).to_string()),
+      ("FILEORG_PROCESSED", tally.num.to_string()),
+      ("FILEORG_PROCESSED_SIZE", tally.size.to_string()),
+      ("FILEORG_FAILED", tally.num_failed.to_string()),
+      ("FILEORG_ERRORS", self.errors.to_string()),
+    ]
+  }
+}
+
+/// A hook command, run directly without a shell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hook {
+  pub phase: HookPhase,
+  pub args: Vec<String>,
+}
+
+impl Hook {
+  pub fn parse(command: &str, phase: HookPhase) -> Result<Hook, ArgError> {
+    let invalid = |reason: &str| ArgError::InvalidCommand(command.to_owned(), reason.to_owned());
+    let args = split_args(command).ok_or_else(|| invalid("unterminated quote or escape"))?;
+    if args.len() < 1 {
+      return Err(invalid("no command given"));
+    }
+    Ok(Hook { phase, args })
+  }
+
+  /// Run the hook and wait for it. Its output is shown as it runs, on stderr if stdout carries the JSON report.
+  pub fn run(&self, summary: &RunSummary, output_to_stderr: bool) -> io::Result<()> {
+    let stdout = if output_to_stderr { Stdio::from(io::stderr()) } else { Stdio::inherit() };
+    let mut child = Command::new(&self.args[0])
+      .args(&self.args[1..])
+      .envs(summary.env_vars())
+      .stdin(Stdio::piped())
+      .stdout(stdout)
+      .spawn()
+      .map_err(|error| io::Error::new(error.kind(), format!("could not run {}: {}", self.args[0], error)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+      // hooks that do not read the summary may close stdin early
+      let _ = writeln!(stdin, "{}", summary.to_json());
+    }
+    let status = child.wait()?;
+    if status.success() {
+      Ok(())
+    } else {
+      Err(io::Error::other(CommandFailed(format!("{} failed with {}", self.args[0], status))))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::criteria::CriteriaBuilder;
+
+  #[test]
+  fn test_run_summary() {
+    let criteria = CriteriaBuilder::new().move_to("/mnt/archive").build().unwrap();
+    let mut summary = RunSummary::new(HookPhase::After, "/data", &criteria, &[]);
+    summary.files = 3;
+    summary.size = 4096;
+    summary.result = Some(ActionTally { num: 2, size: 3000, num_failed: 1, ..ActionTally::default() });
+    let report = summary.to_json();
+    assert_eq!(report["hook"], "after");
+    assert_eq!(report["action"], "move");
+    assert_eq!(report["target"], "/mnt/archive");
+    assert_eq!(report["result"]["failed"], 1);
+    let vars = summary.env_vars();
+    assert!(vars.contains(&("FILEORG_PROCESSED_SIZE", "3000".to_owned())));
+    assert!(vars.contains(&("FILEORG_APPLIED", "1".to_owned())));
+    assert!(matches!(Hook::parse(" ", HookPhase::Before), Err(ArgError::InvalidCommand(_, _))));
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_hook_receives_summary() {
+    let criteria = CriteriaBuilder::new().build().unwrap();
+    let summary = RunSummary::new(HookPhase::Before, "/data", &criteria, &[]);
+    let hook = Hook::parse(r#"sh -c 'test "$FILEORG_HOOK" = before && grep -q "\"action\":\"list\""'"#, HookPhase::Before).unwrap();
+    assert!(hook.run(&summary, true).is_ok());
+    let hook = Hook::parse("sh -c 'exit 4'", HookPhase::Before).unwrap();
+    assert!(hook.run(&summary, true).unwrap_err().to_string().contains("exit status: 4"));
+  }
+}
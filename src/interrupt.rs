@@ -0,0 +1,37 @@
+//! Stop scans and actions cleanly on SIGINT or SIGTERM.
+//! The first signal lets the current file finish so a summary can be shown; a second one exits at once.
+//! While a prompt waits for an answer nothing is running, so the first signal exits at once.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use color_print::cformat;
+
+/// Exit code after an interruption, as used by shells for SIGINT
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static AWAITING_INPUT: AtomicBool = AtomicBool::new(false);
+
+pub fn install_handler() {
+  let _ = ctrlc::set_handler(|| {
+    if AWAITING_INPUT.load(Ordering::SeqCst) || INTERRUPTED.swap(true, Ordering::SeqCst) {
+      std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+  });
+}
+
+/// Mark that a prompt is waiting for an answer, or has been answered
+pub fn set_awaiting_input(awaiting: bool) {
+  AWAITING_INPUT.store(awaiting, Ordering::SeqCst);
+}
+
+pub fn is_interrupted() -> bool {
+  INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Exit with the interrupted status code once the partial summary has been shown
+pub fn exit_if_interrupted() {
+  if is_interrupted() {
+    eprintln!("{}", cformat!("<red>Interrupted: the summary above only covers files processed so far</red>"));
+    std::process::exit(INTERRUPTED_EXIT_CODE);
+  }
+}
@@ -0,0 +1,210 @@
+//! Job state files for resumable copy and move runs.
+//!
+//! The file starts with the job settings and the planned source and destination of every file,
+//! followed by a `done` line appended as each file completes, so it stays valid after a crash.
+//! Fields are tab-separated, with tabs, newlines and backslashes in paths escaped.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const JOB_HEADER: &str = "#fileorg-job\t1";
+
+/// A file planned for copying or moving
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobEntry {
+  pub source: PathBuf,
+  pub destination: PathBuf,
+  pub size: u64,
+  pub done: bool,
+}
+
+impl JobEntry {
+  pub fn new(source: PathBuf, destination: PathBuf, size: u64) -> Self {
+    JobEntry { source, destination, size, done: false }
+  }
+
+  /// Check whether the file was transferred before the job stopped, but not yet recorded as done
+  pub fn is_complete(&self, move_mode: bool) -> bool {
+    let copied = fs::metadata(&self.destination).map(|meta| meta.len() == self.size).unwrap_or(false);
+    copied && (!move_mode || !self.source.exists())
+  }
+}
+
+/// A copy or move run with its planned files and, once persisted, its state file
+#[derive(Debug)]
+pub struct Job {
+  pub move_mode: bool,
+  pub root: PathBuf,
+  pub target: PathBuf,
+  pub entries: Vec<JobEntry>,
+  pub path: Option<PathBuf>,
+  log: Option<Mutex<File>>,
+}
+
+fn escape_field(field: &str) -> String {
+  field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape_field(field: &str) -> String {
+  let mut text = String::with_capacity(field.len());
+  let mut chars = field.chars();
+  while let Some(c) = chars.next() {
+    if c == '\\' {
+      match chars.next() {
+        Some('t') => text.push('\t'),
+        Some('n') => text.push('\n'),
+        Some(other) => text.push(other),
+        None => text.push('\\'),
+      }
+    } else {
+      text.push(c);
+    }
+  }
+  text
+}
+
+fn path_field(path: &Path) -> String {
+  escape_field(&path.to_string_lossy())
+}
+
+fn invalid_data(line_num: usize, message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line_num, message))
+}
+
+impl Job {
+  pub fn new(move_mode: bool, root: &Path, target: &Path, entries: Vec<JobEntry>) -> Self {
+    Job { move_mode, root: root.to_path_buf(), target: target.to_path_buf(), entries, path: None, log: None }
+  }
+
+  /// Write the job settings and planned files, and keep the file open to record progress
+  pub fn persist(&mut self, path: &Path) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    let mut lines = vec![
+      JOB_HEADER.to_owned(),
+      format!("action\t{}", if self.move_mode { "move" } else { "copy" }),
+      format!("root\t{}", path_field(&self.root)),
+      format!("target\t{}", path_field(&self.target)),
+    ];
+    for entry in &self.entries {
+      lines.push(format!("file\t{}\t{}\t{}", entry.size, path_field(&entry.source), path_field(&entry.destination)));
+    }
+    for (index, entry) in self.entries.iter().enumerate() {
+      if entry.done {
+        lines.push(format!("done\t{}", index));
+      }
+    }
+    file.write_all((lines.join("\n") + "\n").as_bytes())?;
+    file.sync_all()?;
+    self.path = Some(path.to_path_buf());
+    self.log = Some(Mutex::new(file));
+    Ok(())
+  }
+
+  /// Record progress to the given file instead of the persisted one, to simulate write errors
+  #[cfg(test)]
+  pub fn record_to(&mut self, path: &Path, file: File) {
+    self.path = Some(path.to_path_buf());
+    self.log = Some(Mutex::new(file));
+  }
+
+  /// Read a job state file and reopen it to record further progress
+  pub fn load(path: &Path) -> io::Result<Job> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut job = Job::new(false, Path::new(""), Path::new(""), vec![]);
+    for (index, line_result) in reader.lines().enumerate() {
+      let line = line_result?;
+      let line_num = index + 1;
+      if line_num == 1 {
+        if line != JOB_HEADER {
+          return Err(invalid_data(line_num, "not a fileorg job file"));
+        }
+        continue;
+      }
+      let fields: Vec<&str> = line.split('\t').collect();
+      match fields.as_slice() {
+        ["action", action] => job.move_mode = *action == "move",
+        ["root", root] => job.root = PathBuf::from(unescape_field(root)),
+        ["target", target] => job.target = PathBuf::from(unescape_field(target)),
+        ["file", size, source, destination] => {
+          let size = size.parse::<u64>().map_err(|_| invalid_data(line_num, "invalid file size"))?;
+          job.entries.push(JobEntry::new(PathBuf::from(unescape_field(source)), PathBuf::from(unescape_field(destination)), size));
+        },
+        ["done", entry_index] => {
+          let entry = entry_index.parse::<usize>().ok().and_then(|entry_index| job.entries.get_mut(entry_index));
+          entry.ok_or(invalid_data(line_num, "unknown file index"))?.done = true;
+        },
+        // a line cut short by a crash
+        _ if line.len() < 1 => (),
+        _ => return Err(invalid_data(line_num, "unexpected line")),
+      }
+    }
+    job.path = Some(path.to_path_buf());
+    job.log = Some(Mutex::new(OpenOptions::new().append(true).open(path)?));
+    Ok(job)
+  }
+
+  /// Record a completed file, if the job is persisted
+  pub fn mark_done(&self, index: usize) -> io::Result<()> {
+    if let Some(log) = &self.log {
+      let mut file = log.lock().unwrap();
+      writeln!(file, "done\t{}", index)?;
+      file.sync_data()?;
+    }
+    Ok(())
+  }
+
+  /// Indices of the files not yet recorded as done
+  pub fn pending(&self) -> Vec<usize> {
+    self.entries.iter().enumerate().filter(|(_, entry)| !entry.done).map(|(index, _)| index).collect()
+  }
+
+  /// Remove the state file once every file is done
+  pub fn remove_if_complete(&self, num_done: usize) -> io::Result<bool> {
+    if let Some(path) = &self.path {
+      if num_done >= self.entries.len() {
+        fs::remove_file(path)?;
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+}
+
+/// Default state file in the target directory, named after the start time
+pub fn default_job_path(target: &Path) -> PathBuf {
+  target.join(format!(".fileorg-job-{}.tsv", chrono::Local::now().format("%Y%m%d-%H%M%S")))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_escape_fields() {
+    let path = "dir\\name\twith tab\nnewline.txt";
+    assert_eq!(escape_field(path), "dir\\\\name\\twith tab\\nnewline.txt");
+    assert_eq!(unescape_field(&escape_field(path)), path);
+  }
+
+  #[test]
+  fn test_persist_and_load() {
+    let dir = std::env::temp_dir().join(format!("fileorg-job-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let job_path = dir.join("job.tsv");
+    let entries = vec![
+      JobEntry::new(PathBuf::from("/src/a.txt"), PathBuf::from("/dst/a.txt"), 10),
+      JobEntry::new(PathBuf::from("/src/b\tc.txt"), PathBuf::from("/dst/b\tc.txt"), 20),
+    ];
+    let mut job = Job::new(true, Path::new("/src"), Path::new("/dst"), entries);
+    job.persist(&job_path).unwrap();
+    job.mark_done(1).unwrap();
+    let loaded = Job::load(&job_path).unwrap();
+    assert!(loaded.move_mode);
+    assert_eq!(loaded.target, PathBuf::from("/dst"));
+    assert_eq!(loaded.entries[1].source, PathBuf::from("/src/b\tc.txt"));
+    assert_eq!(loaded.pending(), vec![0]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,76 @@
+//! Filter large numbers of files in nested directories by age, size, extension, name pattern
+//! and owner, gather statistics about them, and copy, move, delete or change them.
+//!
+//! This library powers the `fileorg` command line tool. Build [`Criteria`] with a
+//! [`CriteriaBuilder`], scan a directory with a [`Scanner`] and apply an [`Action`]
+//! to the matched files in the returned [`ResourceTree`].
+//!
+//! ```
+//! use file_organiser::CriteriaBuilder;
+//!
+//! let criteria = CriteriaBuilder::new().extensions("log").before("3mo").build().unwrap();
+//! assert_eq!(criteria.include_extensions(), ["log"]);
+//! ```
+//!
+//! Then scan a directory, list the matched files and move them:
+//!
+//! ```no_run
+//! use file_organiser::{Action, CriteriaBuilder, Scanner, TransferOptions};
+//! # let criteria = CriteriaBuilder::new().extensions("log").before("3mo").build().unwrap();
+//! let mut tree = Scanner::new(criteria).scan("/var/log/app");
+//! for row in tree.action_rows() {
+//!   println!("{} {} bytes, {:.0} days old", row.path_ref().display(), row.size(), row.days_old());
+//! }
+//! let tally = Action::Move("/mnt/archive".into()).apply(&mut tree, &TransferOptions::default());
+//! println!("moved {} files, {} failed", tally.num, tally.num_failed);
+//! for error in &tree.errors {
+//!   println!("{}", error);
+//! }
+//! ```
+
+// explicit length checks and match blocks in predicates are the house style
+#![allow(clippy::len_zero, clippy::match_like_matches_macro)]
+
+extern crate chrono;
+
+pub mod age; // Parse and display file ages
+pub mod errors; // Argument validation and file system errors
+pub mod file_meta; // Cached file metadata
+pub mod file_system; // Disk and in-memory file systems
+pub mod permissions; // Parse octal and symbolic permission modes
+pub mod criteria; // Scan criteria and operations
+pub mod filters; // Match predicates for scanned files
+pub mod resource_row; // Manage flattened directory contents
+pub mod run; // Scan the work directory after processing all options
+pub mod action; // Copy, move, delete and permission actions
+pub mod transfer; // Parallel copy and move workers
+pub mod script; // Rhai rule scripts
+pub mod exec; // Run commands on matched files
+mod utils; // Miscellaneous functions
+mod owners; // Look up user and group names
+mod path_info; // Custom path info
+mod matches; // Build optional regular expression for pattern matching
+mod manage; // Handle copy, move and delete operations
+mod job; // Resumable copy and move job files
+mod progress; // Progress line for long scans and actions
+mod interrupt; // Stop cleanly on SIGINT and SIGTERM
+mod report; // JSON report of a run
+mod hooks; // Commands run before and after the action phase
+mod args; // Manage command line arguments
+mod start; // Initialise the utility after validating core arguments
+
+pub use criteria::{ActionMode, Criteria, CriteriaBuilder, PathOutput, TimeField};
+pub use filters::{Filter, ScanContext};
+pub use run::Scanner;
+pub use action::{Action, ActionTally};
+pub use resource_row::{DetailLevel, ResourceRow, ResourceSet, ResourceTree, SortKey};
+pub use file_meta::FileMeta;
+pub use file_system::{DirListing, FileEntry, FileSystem, MemoryFileSystem, OsFileSystem};
+pub use errors::{ArgError, FileError, FileErrorKind, FileOperation};
+pub use manage::PermissionChange;
+pub use transfer::TransferOptions;
+pub use script::{Script, ScriptOutcome};
+pub use exec::ExecCommand;
+
+/// Run the `fileorg` command line tool with the process arguments
+pub use start::init as run_cli;
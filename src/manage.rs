@@ -1,50 +1,154 @@
-use std::fs::{rename, copy, create_dir_all};
+use std::io;
 use std::path::{Path, PathBuf, MAIN_SEPARATOR};
-use walkdir::DirEntry;
 use crate::resource_row::*;
+use crate::errors::ArgError;
+use crate::owners::{resolve_uid, resolve_gid, user_display, group_name};
+use crate::permissions::{FileMode, parse_mode};
+use crate::transfer::Throttle;
+use crate::file_system::{FileEntry, FileSystem};
+#[cfg(unix)]
+use std::fs::set_permissions;
+#[cfg(unix)]
+use std::os::unix::fs::{lchown, PermissionsExt};
 
-/// Move a file to another directory
-pub fn move_file(resource: &ResourceRow, target: &Option<Box<PathBuf>>, root_ref: &Option<DirEntry>) -> (bool, String) {
-  copy_move_file(resource, target, root_ref, true)
+/// Destination of a file in the target directory, keeping its path relative to the root
+/// unless a rule script set another destination
+pub fn target_file_path(resource: &ResourceRow, target: &Path, root_ref: &Option<FileEntry>) -> PathBuf {
+  let target_base_string = target.to_str().unwrap_or("").to_owned();
+  let relative_path = resource.destination.clone().unwrap_or_else(|| resource.relative_path(root_ref));
+  PathBuf::from([target_base_string, relative_path].join(MAIN_SEPARATOR.to_string().as_str()))
 }
 
-/// Copy a file to another directory
-pub fn copy_file(resource: &ResourceRow, target: &Option<Box<PathBuf>>, root_ref: &Option<DirEntry>) -> (bool, String) {
-  copy_move_file(resource, target, root_ref, false)
+/// Copy or move a file to its destination, creating any missing parent directories
+pub fn transfer_file(fs: &dyn FileSystem, source: &Path, new_path: &Path, move_mode: bool, throttle: Option<&Throttle>) -> io::Result<()> {
+  if let Some(new_parent_path) = new_path.parent() {
+    if !fs.exists(new_parent_path) {
+      fs.create_dir_all(new_parent_path)?;
+    }
+  }
+  if move_mode {
+    match fs.rename(source, new_path) {
+      Err(error) if error.kind() == io::ErrorKind::CrossesDevices => {
+        copy_contents(fs, source, new_path, throttle)?;
+        fs.remove_file(source)?;
+      },
+      result => result?,
+    }
+  } else {
+    copy_contents(fs, source, new_path, throttle)?;
+  }
+  Ok(())
+}
+
+/// Copy to a hidden temporary name in the target directory, then rename it into place,
+/// so an interrupted or failed copy never leaves a truncated file under the final name
+fn copy_contents(fs: &dyn FileSystem, source: &Path, target: &Path, throttle: Option<&Throttle>) -> io::Result<u64> {
+  let file_name = target.file_name().and_then(|name| name.to_str()).unwrap_or("");
+  let temp_path = target.with_file_name(format!(".{}.fileorg-part", file_name));
+  let result = fs.copy(source, &temp_path, throttle).and_then(|num_bytes| fs.rename(&temp_path, target).map(|_| num_bytes));
+  if result.is_err() {
+    let _ = fs.remove_file(&temp_path);
+  }
+  result
 }
 
-/// Common function for moving or copying a file. Much of the validation is the same
-fn copy_move_file(resource: &ResourceRow, target: &Option<Box<PathBuf>>, root_ref: &Option<DirEntry>, move_mode: bool) -> (bool, String) {
-  let mut moved = false;
-  let mut new_path_str = "".to_string();
-  if let Some(mp) = target {
-   let target_base_string = mp.to_str().unwrap().to_owned();
-   new_path_str = [target_base_string.clone(), resource.relative_path(root_ref)].join(MAIN_SEPARATOR.to_string().as_str());
-   let needs_parent = resource.depth() > 1;
-   let new_parent_dir = if needs_parent {
-    [target_base_string, resource.relative_parent_path(root_ref)].join(MAIN_SEPARATOR.to_string().as_str())
-   } else {
-    target_base_string
-   };
-   let new_parent_path = Path::new(new_parent_dir.as_str());
-   let mut has_parent = new_parent_path.exists();
-   if  !has_parent {
-    if let Ok(_ok) = create_dir_all(new_parent_path) {
-      has_parent = true;
-    }
-   }
-   if has_parent {
-    let new_path = Path::new(new_path_str.as_str());
-    if move_mode {
-      if let Ok(_success) = rename(resource.path_ref(), new_path) {
-        moved = true;
+/// Permission and ownership changes applied by --chmod and --chown
+#[derive(Debug, Clone)]
+pub struct PermissionChange {
+  pub mode: Option<FileMode>,
+  pub uid: Option<u32>,
+  pub gid: Option<u32>,
+}
+
+impl PermissionChange {
+  /// Build from the --chmod and --chown arguments. The owner may be given as user, user:group or :group
+  pub fn from_args(chmod: &Option<String>, chown: &Option<String>) -> Result<Option<PermissionChange>, ArgError> {
+    let mode = if let Some(mode_str) = chmod { Some(parse_mode(mode_str)?) } else { None };
+    let (mut uid, mut gid) = (None, None);
+    if let Some(owner_str) = chown {
+      let (user_ref, group_ref) = owner_str.split_once(':').unwrap_or((owner_str.as_str(), ""));
+      if user_ref.len() < 1 && group_ref.len() < 1 {
+        return Err(ArgError::UnknownUser(owner_str.to_owned()));
+      }
+      if user_ref.len() > 0 {
+        uid = Some(resolve_uid(user_ref).ok_or(ArgError::UnknownUser(user_ref.to_owned()))?);
+      }
+      if group_ref.len() > 0 {
+        gid = Some(resolve_gid(group_ref).ok_or(ArgError::UnknownGroup(group_ref.to_owned()))?);
       }
+    }
+    if mode.is_some() || uid.is_some() || gid.is_some() {
+      Ok(Some(PermissionChange { mode, uid, gid }))
     } else {
-      if let Ok(_success) = copy(resource.path_ref(), new_path) {
-        moved = true;
+      Ok(None)
+    }
+  }
+
+  pub fn to_text(&self) -> String {
+    let mut parts: Vec<String> = vec![];
+    if let Some(mode) = &self.mode {
+      parts.push(format!("mode {}", mode.to_text()));
+    }
+    if self.uid.is_some() || self.gid.is_some() {
+      let user = self.uid.map(user_display).unwrap_or_default();
+      let group = self.gid.map(|gid| group_name(gid).unwrap_or(gid.to_string())).unwrap_or_default();
+      let owner = if self.gid.is_some() { format!("{}:{}", user, group) } else { user };
+      parts.push(format!("owner {}", owner));
+    }
+    parts.join(" and ")
+  }
+
+  /// Apply the ownership change first, as chown may clear the setuid and setgid bits.
+  /// Symlinks are changed themselves, never their targets, which may be outside the scanned tree,
+  /// and keep their mode as links have no permissions of their own.
+  #[cfg(unix)]
+  pub fn apply(&self, resource: &ResourceRow) -> io::Result<()> {
+    let path = resource.path_ref();
+    if self.uid.is_some() || self.gid.is_some() {
+      lchown(path, self.uid, self.gid)?;
+    }
+    if let Some(mode) = &self.mode {
+      let meta = path.symlink_metadata()?;
+      if meta.file_type().is_symlink() {
+        return Ok(());
+      }
+      let current = meta.permissions().mode();
+      let new_mode = mode.apply(current, path.is_dir());
+      if new_mode != current {
+        set_permissions(path, std::fs::Permissions::from_mode(new_mode))?;
       }
     }
-   }
+    Ok(())
   }
-  (moved, new_path_str)
-}
\ No newline at end of file
+
+  #[cfg(not(unix))]
+  pub fn apply(&self, _resource: &ResourceRow) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "file permissions can only be changed on Unix systems"))
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use super::*;
+  use std::fs;
+  use std::os::unix::fs::symlink;
+  use crate::criteria::CriteriaBuilder;
+  use crate::run::Scanner;
+
+  #[test]
+  fn test_permissions_do_not_follow_symlinks() {
+    let dir = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    let target = outside.path().join("out.txt");
+    fs::write(&target, b"out").unwrap();
+    fs::set_permissions(&target, fs::Permissions::from_mode(0o644)).unwrap();
+    fs::write(dir.path().join("own.txt"), b"own").unwrap();
+    symlink(&target, dir.path().join("link.txt")).unwrap();
+    let tree = Scanner::new(CriteriaBuilder::new().build().unwrap()).scan(dir.path().to_str().unwrap());
+    let change = PermissionChange::from_args(&Some("600".to_owned()), &None).unwrap().unwrap();
+    let num_applied = tree.action_rows().map(|row| change.apply(row)).filter(|result| result.is_ok()).count();
+    assert_eq!(num_applied, 2);
+    assert_eq!(fs::metadata(dir.path().join("own.txt")).unwrap().permissions().mode() & 0o777, 0o600);
+    assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o644);
+  }
+}
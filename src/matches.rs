@@ -22,9 +22,5 @@ pub fn build_matcher(pattern: &str, case_insensitive: bool, bounds: MatchBounds,
     _ => pattern.to_owned()
   };
   let corrected_pattern = [start_bounds, parsed_pattern.as_str(), end_bounds].concat();
-  if let Ok(rgx) = build_regex(&corrected_pattern, case_insensitive) {
-    Some(rgx)
-  } else {
-    None
-  }
+  build_regex(&corrected_pattern, case_insensitive).ok()
 }
@@ -0,0 +1,86 @@
+//! Look up user and group names for file owners.
+//! Lookups may query NSS services such as LDAP, so names are cached per id.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+static USER_NAMES: OnceLock<Mutex<HashMap<u32, Option<String>>>> = OnceLock::new();
+static GROUP_NAMES: OnceLock<Mutex<HashMap<u32, Option<String>>>> = OnceLock::new();
+
+fn cached_name(cache: &OnceLock<Mutex<HashMap<u32, Option<String>>>>, id: u32, lookup: fn(u32) -> Option<String>) -> Option<String> {
+  let mut names = cache.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap();
+  names.entry(id).or_insert_with(|| lookup(id)).clone()
+}
+
+/// Name of the user with this id, or None if no such account exists
+pub fn user_name(uid: u32) -> Option<String> {
+  cached_name(&USER_NAMES, uid, lookup_user_name)
+}
+
+/// Name of the group with this id, or None if no such group exists
+pub fn group_name(gid: u32) -> Option<String> {
+  cached_name(&GROUP_NAMES, gid, lookup_group_name)
+}
+
+/// Display the owner name, falling back to the numeric id for deleted accounts
+pub fn user_display(uid: u32) -> String {
+  user_name(uid).unwrap_or(uid.to_string())
+}
+
+/// Resolve a user name or numeric id
+pub fn resolve_uid(user_ref: &str) -> Option<u32> {
+  if let Ok(uid) = user_ref.parse::<u32>() {
+    Some(uid)
+  } else {
+    lookup_uid(user_ref)
+  }
+}
+
+/// Resolve a group name or numeric id
+pub fn resolve_gid(group_ref: &str) -> Option<u32> {
+  if let Ok(gid) = group_ref.parse::<u32>() {
+    Some(gid)
+  } else {
+    lookup_gid(group_ref)
+  }
+}
+
+#[cfg(unix)]
+fn lookup_user_name(uid: u32) -> Option<String> {
+  uzers::get_user_by_uid(uid).map(|user| user.name().to_string_lossy().into_owned())
+}
+
+#[cfg(unix)]
+fn lookup_group_name(gid: u32) -> Option<String> {
+  uzers::get_group_by_gid(gid).map(|group| group.name().to_string_lossy().into_owned())
+}
+
+#[cfg(unix)]
+fn lookup_uid(name: &str) -> Option<u32> {
+  uzers::get_user_by_name(name).map(|user| user.uid())
+}
+
+#[cfg(unix)]
+fn lookup_gid(name: &str) -> Option<u32> {
+  uzers::get_group_by_name(name).map(|group| group.gid())
+}
+
+#[cfg(not(unix))]
+fn lookup_user_name(_uid: u32) -> Option<String> {
+  None
+}
+
+#[cfg(not(unix))]
+fn lookup_group_name(_gid: u32) -> Option<String> {
+  None
+}
+
+#[cfg(not(unix))]
+fn lookup_uid(_name: &str) -> Option<u32> {
+  None
+}
+
+#[cfg(not(unix))]
+fn lookup_gid(_name: &str) -> Option<u32> {
+  None
+}
@@ -1,7 +1,5 @@
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
-use std::env;
-use crate::args::Args;
-use crate::utils::{is_full_path,path_string_to_file_name,path_string_to_head};
 
 /// Overview of a resource path
 #[derive(Debug, Clone)]
@@ -13,12 +11,6 @@ pub struct PathInfo {
   pub pattern: Option<String>,
 }
 
-fn parse_expanded_path_args(paths: &Vec<String>) -> String {
-  let names: Vec<String> = paths.into_iter().map(|p| path_string_to_file_name(p)).collect();
-  let head = path_string_to_head(paths.get(0).unwrap());
-  format!("{}({})", head, names.join("|"))
-}
-
 /// Build path information from std::path::Path
 impl PathInfo {
   pub fn new(in_str: &str) -> Self {
@@ -56,6 +48,20 @@ impl PathInfo {
     }
   }
 
+  /// Target directory for copy and move actions, which may not exist yet
+  pub fn new_dir(in_str: &str) -> Self {
+    let path = Path::new(in_str);
+    let exists = path.is_dir();
+    let canonical = if exists { path.canonicalize().ok().and_then(|os_path| os_path.to_str().map(|s| s.to_owned())).unwrap_or_default() } else { "".to_owned() };
+    PathInfo {
+      path: Box::new(path.to_owned()),
+      canonical,
+      exists,
+      input: in_str.to_owned(),
+      pattern: None
+    }
+  }
+
   /// Default empty constructor
   pub fn new_empty() -> Self {
     PathInfo {
@@ -67,21 +73,67 @@ impl PathInfo {
     }
   }
 
-  /// Build from command line arguments
-  pub fn new_from_args(args: &Args) -> Self {
-    let curr_ref = "".to_owned();
-    let path_args = args.path.clone().unwrap_or(vec![".".to_string()]);
-    let path_arg = if path_args.len() > 1 {
-      parse_expanded_path_args(&path_args)
-    } else if path_args.len() > 0 {
-      path_args.get(0).unwrap().to_owned()
-    } else {
-      "".to_string()
-    };
+}
+
+/// Target subfolder for each scanned root, named after its last segment,
+/// with a numeric suffix when two roots share a name, e.g. `logs` and `logs_2`
+pub fn root_folder_names(roots: &[&Path]) -> Vec<String> {
+  let mut names: Vec<String> = vec![];
+  for root in roots {
+    let base = root.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or("root".to_owned());
+    let mut name = base.clone();
+    let mut index = 1;
+    while names.contains(&name) {
+      index += 1;
+      name = format!("{}_{}", base, index);
+    }
+    names.push(name);
+  }
+  names
+}
+
+/// Read a list of paths, one per line or separated by NUL characters as from `find -print0`.
+/// Relative paths are resolved against the base directory and empty entries are ignored.
+pub fn read_path_list(mut reader: impl Read, null_separated: bool, base: &Path) -> io::Result<Vec<PathBuf>> {
+  let mut bytes: Vec<u8> = vec![];
+  reader.read_to_end(&mut bytes)?;
+  let separator = if null_separated { b'\0' } else { b'\n' };
+  let paths = bytes.split(|byte| *byte == separator)
+    .map(|entry| if null_separated { entry } else { entry.strip_suffix(b"\r").unwrap_or(entry) })
+    .filter(|entry| entry.len() > 0)
+    // drop ./ segments, e.g. from find . -print0
+    .map(|entry| base.join(bytes_to_path(entry)).components().collect())
+    .collect();
+  Ok(paths)
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+  use std::os::unix::ffi::OsStrExt;
+  PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+  PathBuf::from(String::from_utf8_lossy(bytes).to_string())
+}
 
-    let curr_path = env::current_dir().unwrap().as_os_str().to_str().unwrap().to_string();
-    let path_str = if path_arg == curr_ref { curr_path.clone().to_owned() } else if is_full_path(path_arg.as_str()) { path_arg.to_owned() } else { format!("{}/{}", curr_path, path_arg) };
-    PathInfo::new(&path_str)
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_root_folder_names() {
+    let roots = [Path::new("/srv/uploads"), Path::new("/var/log/app"), Path::new("/srv/app"), Path::new("/")];
+    assert_eq!(root_folder_names(&roots), vec!["uploads", "app", "app_2", "root"]);
   }
 
-}
\ No newline at end of file
+  #[test]
+  fn test_read_path_list() {
+    let base = Path::new("/data");
+    let paths = read_path_list(&b"./a.txt\r\n\n/tmp/b c.log\n"[..], false, base).unwrap();
+    assert_eq!(paths, vec![PathBuf::from("/data/a.txt"), PathBuf::from("/tmp/b c.log")]);
+    let paths = read_path_list(&b"docs/x\ny.txt\0z.txt\0"[..], true, base).unwrap();
+    assert_eq!(paths, vec![PathBuf::from("/data/docs/x\ny.txt"), PathBuf::from("/data/z.txt")]);
+  }
+}
@@ -0,0 +1,271 @@
+//! Parse octal and symbolic permission modes for the --perm filter and the --chmod action
+//!
+//! Symbolic modes follow chmod, e.g. `u+x`, `go-w` or `u=rw,g=r,o=r`.
+
+use crate::errors::ArgError;
+
+const WHO_USER: u32 = 0o4700;
+const WHO_GROUP: u32 = 0o2070;
+const WHO_OTHER: u32 = 0o1007;
+const WHO_ALL: u32 = 0o7777;
+
+const PERM_READ: u32 = 0o444;
+const PERM_WRITE: u32 = 0o222;
+const PERM_EXEC: u32 = 0o111;
+const PERM_SET_ID: u32 = 0o6000;
+const PERM_STICKY: u32 = 0o1000;
+
+/// One comma-separated clause of a symbolic mode, e.g. `go-w`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ModeClause {
+  pub who: u32,
+  pub op: char,
+  pub perms: u32,
+  pub conditional_exec: bool,
+}
+
+impl ModeClause {
+  pub fn to_text(&self) -> String {
+    let who = if self.who == WHO_ALL {
+      "a".to_owned()
+    } else {
+      [(WHO_USER, 'u'), (WHO_GROUP, 'g'), (WHO_OTHER, 'o')].iter().filter(|(mask, _)| self.who & mask == *mask).map(|(_, c)| *c).collect()
+    };
+    let mut perms: String = [(PERM_READ, 'r'), (PERM_WRITE, 'w'), (PERM_EXEC, 'x'), (PERM_SET_ID, 's'), (PERM_STICKY, 't')]
+      .iter().filter(|(bits, _)| self.perms & bits == *bits).map(|(_, c)| *c).collect();
+    if self.conditional_exec {
+      perms.push('X');
+    }
+    format!("{}{}{}", who, self.op, perms)
+  }
+
+  pub fn apply(&self, mode: u32, is_dir: bool) -> u32 {
+    let exec_bits = if self.conditional_exec && (is_dir || mode & PERM_EXEC != 0) { PERM_EXEC } else { 0 };
+    let bits = (self.perms | exec_bits) & self.who;
+    match self.op {
+      '+' => mode | bits,
+      '-' => mode & !bits,
+      _ => (mode & !self.who) | bits,
+    }
+  }
+}
+
+/// A permission mode given either as octal digits or symbolic clauses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileMode {
+  Octal(u32),
+  Symbolic(Vec<ModeClause>),
+}
+
+impl FileMode {
+  /// Apply the mode to the current permission bits of a file or directory
+  pub fn apply(&self, mode: u32, is_dir: bool) -> u32 {
+    match self {
+      FileMode::Octal(bits) => (mode & !WHO_ALL) | bits,
+      FileMode::Symbolic(clauses) => clauses.iter().fold(mode, |current, clause| clause.apply(current, is_dir)),
+    }
+  }
+
+  /// Permission bits set by the mode when applied to a file without any permissions
+  pub fn bits(&self) -> u32 {
+    self.apply(0, false) & WHO_ALL
+  }
+
+  pub fn to_text(&self) -> String {
+    match self {
+      FileMode::Octal(bits) => format!("{:04o}", bits),
+      FileMode::Symbolic(clauses) => clauses.iter().map(|clause| clause.to_text()).collect::<Vec<String>>().join(","),
+    }
+  }
+
+  pub fn has_removals(&self) -> bool {
+    match self {
+      FileMode::Octal(_) => false,
+      FileMode::Symbolic(clauses) => clauses.iter().any(|clause| clause.op == '-'),
+    }
+  }
+}
+
+fn invalid_mode(mode_str: &str) -> ArgError {
+  ArgError::InvalidMode(mode_str.to_owned())
+}
+
+fn parse_symbolic_clause(clause_str: &str, mode_str: &str) -> Result<Vec<ModeClause>, ArgError> {
+  let mut who = 0u32;
+  let mut chars = clause_str.chars().peekable();
+  while let Some(c) = chars.peek() {
+    let mask = match c {
+      'u' => WHO_USER,
+      'g' => WHO_GROUP,
+      'o' => WHO_OTHER,
+      'a' => WHO_ALL,
+      _ => break
+    };
+    who |= mask;
+    chars.next();
+  }
+  if who == 0 {
+    who = WHO_ALL;
+  }
+  let mut clauses: Vec<ModeClause> = vec![];
+  while let Some(op) = chars.next() {
+    if !['+', '-', '='].contains(&op) {
+      return Err(invalid_mode(mode_str));
+    }
+    let mut perms = 0u32;
+    let mut conditional_exec = false;
+    while let Some(c) = chars.peek() {
+      let bits = match c {
+        'r' => PERM_READ,
+        'w' => PERM_WRITE,
+        'x' => PERM_EXEC,
+        's' => PERM_SET_ID,
+        't' => PERM_STICKY,
+        'X' => {
+          conditional_exec = true;
+          0
+        },
+        _ => break
+      };
+      perms |= bits;
+      chars.next();
+    }
+    clauses.push(ModeClause { who, op, perms, conditional_exec });
+  }
+  if clauses.len() < 1 {
+    return Err(invalid_mode(mode_str));
+  }
+  Ok(clauses)
+}
+
+/// Parse a chmod-style mode such as `0644`, `755`, `u+x` or `u=rw,go=r`
+pub fn parse_mode(mode_str: &str) -> Result<FileMode, ArgError> {
+  let ref_str = mode_str.trim();
+  if ref_str.len() < 1 {
+    return Err(invalid_mode(mode_str));
+  }
+  if ref_str.chars().all(|c| c.is_ascii_digit()) {
+    return match u32::from_str_radix(ref_str, 8) {
+      Ok(bits) if ref_str.len() <= 4 && bits <= WHO_ALL => Ok(FileMode::Octal(bits)),
+      _ => Err(invalid_mode(mode_str))
+    };
+  }
+  let mut clauses: Vec<ModeClause> = vec![];
+  for clause_str in ref_str.split(',') {
+    clauses.extend(parse_symbolic_clause(clause_str, mode_str)?);
+  }
+  Ok(FileMode::Symbolic(clauses))
+}
+
+/// How the permission bits of a file are compared with the --perm filter, as in find -perm
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PermMatchMode {
+  /// The permission bits must be exactly equal
+  Exact,
+  /// All of the given bits must be set (`-` prefix)
+  All,
+  /// Any of the given bits must be set (`/` prefix)
+  Any
+}
+
+/// Permission filter parsed from --perm, e.g. `0644`, `-g+w` or `/o+w`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PermMatch {
+  pub bits: u32,
+  pub mode: PermMatchMode,
+}
+
+impl PermMatch {
+  pub fn matches(&self, file_mode: u32) -> bool {
+    let file_bits = file_mode & WHO_ALL;
+    match self.mode {
+      PermMatchMode::Exact => file_bits == self.bits,
+      PermMatchMode::All => file_bits & self.bits == self.bits,
+      // find treats /000 as matching every file
+      PermMatchMode::Any => self.bits == 0 || file_bits & self.bits != 0,
+    }
+  }
+}
+
+/// Parse the --perm filter
+pub fn parse_perm_match(perm_str: &str) -> Result<PermMatch, ArgError> {
+  let ref_str = perm_str.trim();
+  let (mode, body) = if let Some(body) = ref_str.strip_prefix('-') {
+    (PermMatchMode::All, body)
+  } else if let Some(body) = ref_str.strip_prefix('/') {
+    (PermMatchMode::Any, body)
+  } else {
+    (PermMatchMode::Exact, ref_str)
+  };
+  let file_mode = parse_mode(body).map_err(|_| invalid_mode(perm_str))?;
+  if file_mode.has_removals() {
+    return Err(invalid_mode(perm_str));
+  }
+  Ok(PermMatch { bits: file_mode.bits(), mode })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_octal_and_symbolic_modes() {
+    assert_eq!(parse_mode("0644"), Ok(FileMode::Octal(0o644)));
+    assert_eq!(parse_mode("755"), Ok(FileMode::Octal(0o755)));
+    assert_eq!(parse_mode("u=rw,g=r,o=r").unwrap().bits(), 0o644);
+    assert_eq!(parse_mode("a+x").unwrap().bits(), 0o111);
+    assert_eq!(parse_mode("+w").unwrap().bits(), 0o222);
+    assert_eq!(parse_mode("ug+s").unwrap().bits(), 0o6000);
+    assert_eq!(parse_mode("o+t").unwrap().bits(), 0o1000);
+    assert!(parse_mode("0999").is_err());
+    assert!(parse_mode("77777").is_err());
+    assert!(parse_mode("u?x").is_err());
+    assert!(parse_mode("gw").is_err());
+    assert!(parse_mode("").is_err());
+  }
+
+  #[test]
+  fn test_apply_modes() {
+    let mode = parse_mode("go-w").unwrap();
+    assert_eq!(mode.apply(0o100666, false), 0o100644);
+    let mode = parse_mode("u+x,o=").unwrap();
+    assert_eq!(mode.apply(0o644, false), 0o740);
+    let mode = parse_mode("a+X").unwrap();
+    assert_eq!(mode.apply(0o644, false), 0o644);
+    assert_eq!(mode.apply(0o644, true), 0o755);
+    assert_eq!(mode.apply(0o744, false), 0o755);
+    assert_eq!(parse_mode("600").unwrap().apply(0o100755, false), 0o100600);
+  }
+
+  #[test]
+  fn test_mode_text() {
+    assert_eq!(parse_mode("644").unwrap().to_text(), "0644");
+    assert_eq!(parse_mode("g+w,o=").unwrap().to_text(), "g+w,o=");
+    assert_eq!(parse_mode("+X").unwrap().to_text(), "a+X");
+    assert_eq!(parse_mode("ug-rw").unwrap().to_text(), "ug-rw");
+  }
+
+  #[test]
+  fn test_perm_matches() {
+    let exact = parse_perm_match("0644").unwrap();
+    assert!(exact.matches(0o100644));
+    assert!(!exact.matches(0o100664));
+
+    let all = parse_perm_match("-g+w").unwrap();
+    assert_eq!(all, PermMatch { bits: 0o020, mode: PermMatchMode::All });
+    assert!(all.matches(0o664));
+    assert!(!all.matches(0o644));
+
+    let any = parse_perm_match("/o+w").unwrap();
+    assert!(any.matches(0o646));
+    assert!(!any.matches(0o644));
+
+    let any_octal = parse_perm_match("/022").unwrap();
+    assert!(any_octal.matches(0o624));
+    assert!(any_octal.matches(0o642));
+    assert!(!any_octal.matches(0o604));
+
+    assert!(parse_perm_match("-g-w").is_err());
+    assert!(parse_perm_match("/").is_err());
+  }
+}
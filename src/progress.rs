@@ -0,0 +1,139 @@
+//! Live progress line on stderr for long scans and actions.
+//! It is only drawn when stderr is a terminal, so piped and logged output stays clean.
+
+use std::io::{IsTerminal, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::age::seconds_to_day_hours_min_secs;
+use crate::utils::{smart_size, pluralize_64};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether to draw progress unless --quiet is set
+pub fn progress_enabled(quiet: bool) -> bool {
+  !quiet && std::io::stderr().is_terminal()
+}
+
+/// Counters shared by all scan or action threads
+#[derive(Debug)]
+pub struct Progress {
+  enabled: bool,
+  /// Past tense verb for actions, e.g. "deleted", or None while scanning
+  action: Option<String>,
+  total_files: u64,
+  total_bytes: u64,
+  dirs: AtomicU64,
+  examined: AtomicU64,
+  matched: AtomicU64,
+  bytes: AtomicU64,
+  start: Instant,
+  last_draw: Mutex<Option<Instant>>,
+}
+
+impl Progress {
+  fn new(enabled: bool, action: Option<String>, total_files: u64, total_bytes: u64) -> Self {
+    Progress {
+      enabled,
+      action,
+      total_files,
+      total_bytes,
+      dirs: AtomicU64::new(0),
+      examined: AtomicU64::new(0),
+      matched: AtomicU64::new(0),
+      bytes: AtomicU64::new(0),
+      start: Instant::now(),
+      last_draw: Mutex::new(None),
+    }
+  }
+
+  pub fn scan(enabled: bool) -> Self {
+    Progress::new(enabled, None, 0, 0)
+  }
+
+  /// Progress of an action on a known number of files and bytes, with an ETA
+  pub fn action(enabled: bool, verb: &str, total_files: u64, total_bytes: u64) -> Self {
+    Progress::new(enabled, Some(verb.to_owned()), total_files, total_bytes)
+  }
+
+  pub fn add_dir(&self) {
+    self.dirs.fetch_add(1, Ordering::Relaxed);
+    self.draw();
+  }
+
+  pub fn add_examined(&self) {
+    self.examined.fetch_add(1, Ordering::Relaxed);
+    self.draw();
+  }
+
+  /// A file matched the filters or an action on it has completed
+  pub fn add_matched(&self, size: u64) {
+    self.matched.fetch_add(1, Ordering::Relaxed);
+    self.bytes.fetch_add(size, Ordering::Relaxed);
+    self.draw();
+  }
+
+  /// Seconds left at the average rate so far, once some bytes have been processed
+  pub fn eta_seconds(&self) -> Option<u64> {
+    let bytes = self.bytes.load(Ordering::Relaxed);
+    let elapsed = self.start.elapsed().as_secs_f64();
+    if bytes > 0 && self.total_bytes >= bytes {
+      Some(((self.total_bytes - bytes) as f64 * elapsed / bytes as f64).round() as u64)
+    } else {
+      None
+    }
+  }
+
+  pub fn to_text(&self) -> String {
+    let matched = self.matched.load(Ordering::Relaxed);
+    let bytes = smart_size(self.bytes.load(Ordering::Relaxed));
+    if let Some(verb) = &self.action {
+      let eta = self.eta_seconds().map(|secs| format!(", ETA {}", seconds_to_day_hours_min_secs(secs))).unwrap_or_default();
+      format!("{} {}/{} {}, {} of {}{}", verb, matched, self.total_files, pluralize_64("file", "s", self.total_files), bytes, smart_size(self.total_bytes), eta)
+    } else {
+      let dirs = self.dirs.load(Ordering::Relaxed);
+      let examined = self.examined.load(Ordering::Relaxed);
+      format!("{} {}, {} {} examined, {} matched ({})", dirs, pluralize_64("dir", "s", dirs), examined, pluralize_64("file", "s", examined), matched, bytes)
+    }
+  }
+
+  fn draw(&self) {
+    if !self.enabled {
+      return;
+    }
+    if let Ok(mut last_draw) = self.last_draw.try_lock() {
+      if last_draw.map(|instant| instant.elapsed() >= REDRAW_INTERVAL).unwrap_or(true) {
+        *last_draw = Some(Instant::now());
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[K{}", self.to_text());
+        let _ = stderr.flush();
+      }
+    }
+  }
+
+  /// Clear the progress line before other output
+  pub fn finish(&self) {
+    if self.enabled && self.last_draw.lock().map(|last_draw| last_draw.is_some()).unwrap_or(false) {
+      eprint!("\r\x1b[K");
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_progress_text() {
+    let progress = Progress::scan(false);
+    progress.add_dir();
+    progress.add_examined();
+    progress.add_examined();
+    progress.add_matched(2048);
+    assert_eq!(progress.to_text(), "1 dir, 2 files examined, 1 matched (2.00 KiB)");
+
+    let progress = Progress::action(false, "deleted", 4, 4096);
+    progress.add_matched(1024);
+    assert!(progress.to_text().starts_with("deleted 1/4 files, 1.00 KiB of 4.00 KiB, ETA"));
+  }
+}
@@ -0,0 +1,92 @@
+//! JSON report of a run, printed by --json instead of the coloured text output,
+//! and the plain path list printed by --paths-only and --print0
+
+use std::io::{self, Write};
+use serde_json::{json, Value};
+use crate::resource_row::{ResourceRow, ResourceTree};
+use crate::criteria::{ActionMode, Criteria, PathOutput};
+use crate::errors::{FileError, sorted_by_path};
+use crate::interrupt::is_interrupted;
+use crate::file_system::FileEntry;
+
+fn row_json(row: &ResourceRow, root_ref: &Option<FileEntry>) -> Value {
+  json!({
+    "path": row.path_ref().to_string_lossy(),
+    "relative_path": row.relative_path(root_ref),
+    "size": row.size(),
+    "extension": row.extension,
+    "timestamp": row.ts,
+    "depth": row.depth(),
+    "deleted": row.deleted,
+    "destination": row.destination,
+  })
+}
+
+fn error_json(error: &FileError) -> Value {
+  json!({
+    "path": error.path.to_string_lossy(),
+    "operation": error.operation.as_str(),
+    "kind": error.kind.as_str(),
+    "message": error.message,
+  })
+}
+
+/// Action with its target and results, or null when only listing.
+/// The counts are null if the action was not applied, e.g. when the prompt was declined.
+fn action_json(tree: &ResourceTree, criteria: &Criteria) -> Value {
+  if let ActionMode::List = criteria.action {
+    return Value::Null;
+  }
+  let tally = tree.tally.map(|tally| json!({ "files": tally.num, "size": tally.size, "failed": tally.num_failed }));
+  json!({
+    "name": criteria.action.key(),
+    "target": criteria.target,
+    "command": criteria.exec.as_ref().map(|command| command.args.clone()),
+    "result": tally,
+  })
+}
+
+pub fn tree_json(tree: &ResourceTree, criteria: &Criteria) -> Value {
+  let root_ref = tree.root();
+  let files: Vec<Value> = tree.action_rows().map(|row| row_json(row, root_ref)).collect();
+  json!({
+    "path": tree.path_display(),
+    "files": files,
+    "totals": {
+      "files": tree.num_files(),
+      "size": tree.size(),
+      "subdirs": tree.num_sub_dirs(),
+      "max_depth": tree.max_depth_scanned(),
+    },
+    "action": action_json(tree, criteria),
+    "errors": sorted_by_path(&tree.errors).into_iter().map(error_json).collect::<Vec<Value>>(),
+    "interrupted": is_interrupted(),
+  })
+}
+
+/// Report of each root, with the combined totals, when several roots are scanned
+pub fn roots_json(trees: &[ResourceTree], criteria: &Criteria) -> Value {
+  json!({
+    "roots": trees.iter().map(|tree| tree_json(tree, criteria)).collect::<Vec<Value>>(),
+    "totals": {
+      "files": trees.iter().map(|tree| tree.num_files()).sum::<usize>(),
+      "size": trees.iter().map(|tree| tree.size()).sum::<u64>(),
+    },
+    "interrupted": is_interrupted(),
+  })
+}
+
+/// Write the matched paths as raw bytes, so names that are not valid UTF-8 survive a pipe to `xargs -0`
+pub fn write_paths(tree: &ResourceTree, output: PathOutput, out: &mut dyn Write) -> io::Result<()> {
+  let root = tree.root().as_ref().map(|root| root.path());
+  let separator: &[u8] = if output.null_separated { b"\0" } else { b"\n" };
+  for row in tree.action_rows() {
+    let path = match root {
+      Some(root) if output.relative => row.path_ref().strip_prefix(root).unwrap_or(row.path_ref()),
+      _ => row.path_ref(),
+    };
+    out.write_all(path.as_os_str().as_encoded_bytes())?;
+    out.write_all(separator)?;
+  }
+  out.flush()
+}
@@ -1,66 +1,143 @@
 use crate::utils::*;
-use string_patterns::Regex;
-use walkdir::DirEntry;
+use crate::owners::user_display;
+use crate::age::seconds_to_day_hours_min_secs;
+use crate::file_meta::FileMeta;
+use crate::file_system::{FileEntry, FileSystem, OsFileSystem};
+use std::sync::Arc;
 use std::path::{Path, PathBuf};
 use color_print::{cprintln, cformat};
 extern crate chrono;
 use chrono::prelude::*;
 use std::collections::HashMap;
+use clap::ValueEnum;
 
-/// conditionally include separate files for linux/unix/mac (unix) and windows
-#[cfg(unix)]
-use std::os::unix::prelude::MetadataExt;
-#[cfg(windows)]
-use std::os::windows::prelude::MetadataExt;
-
-use std::fs::remove_file;
-use crate::manage::{move_file, copy_file};
 use crate::criteria::*;
+use crate::action::ActionTally;
+use crate::errors::FileError;
 use crate::utils::{pluralize_64, smart_size};
 
+/// Order of files in the listing
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+  /// Largest first
+  Size,
+  /// Oldest first
+  Age,
+  Name,
+  /// Relative path, so nested files follow their directory
+  Path,
+  /// Extension, then name
+  Ext,
+}
+
+impl SortKey {
+  pub fn to_label(&self, reverse: bool) -> &'static str {
+    match (self, reverse) {
+      (SortKey::Size, false) => "largest",
+      (SortKey::Size, true) => "smallest",
+      (SortKey::Age, false) => "oldest",
+      (SortKey::Age, true) => "newest",
+      (SortKey::Name, false) => "by name",
+      (SortKey::Name, true) => "by name, descending",
+      (SortKey::Path, false) => "by path",
+      (SortKey::Path, true) => "by path, descending",
+      (SortKey::Ext, false) => "by extension",
+      (SortKey::Ext, true) => "by extension, descending",
+    }
+  }
+}
+
 /// Defines the details level of the file overview
 #[derive(Debug, Clone)]
 pub struct DetailLevel {
     pub show_files: bool,
     pub show_extension_groups: bool,
     pub show_void_directories: bool,
+    pub show_owner_groups: bool,
+    /// Order of the files listed in each directory, walk order if None
+    pub sort: Option<SortKey>,
+    pub reverse: bool,
+    /// Maximum number of files listed per directory, or 0 for all
+    pub limit: usize,
+    /// List this many files across the whole tree, by size unless sorted otherwise, instead of each directory
+    pub top: usize,
 }
 
 /// Simple constructor from boolean arguments
 impl DetailLevel {
-    pub fn new (show_files: &bool, show_extension_groups: &bool, show_void_directories: &bool) -> Self {
+    pub fn new (show_files: &bool, show_extension_groups: &bool, show_void_directories: &bool, show_owner_groups: &bool) -> Self {
         DetailLevel { 
           show_files: show_files.to_owned(),
           show_extension_groups: show_extension_groups.to_owned(),
-          show_void_directories: show_void_directories.to_owned()
+          show_void_directories: show_void_directories.to_owned(),
+          show_owner_groups: show_owner_groups.to_owned(),
+          sort: None,
+          reverse: false,
+          limit: 0,
+          top: 0,
         }
     }
+
+    /// Sort the files to list in the selected order, reversed if requested
+    pub fn sort_rows(&self, rows: &mut [&ResourceRow], sort: SortKey) {
+      match sort {
+        SortKey::Size => rows.sort_by_key(|row| std::cmp::Reverse(row.size())),
+        SortKey::Age => rows.sort_by_key(|row| row.ts),
+        SortKey::Name => rows.sort_by_key(|row| row.file_name()),
+        SortKey::Path => rows.sort_by(|a, b| a.path_ref().cmp(b.path_ref())),
+        SortKey::Ext => rows.sort_by_key(|row| (row.extension.clone(), row.file_name())),
+      }
+      if self.reverse {
+        rows.reverse();
+      }
+    }
+
+    /// Files of one directory in listing order, up to the limit
+    pub fn listed_rows<'a>(&self, resources: &'a [ResourceRow]) -> Vec<&'a ResourceRow> {
+      let mut rows: Vec<&ResourceRow> = resources.iter().collect();
+      if let Some(sort) = self.sort {
+        self.sort_rows(&mut rows, sort);
+      } else if self.reverse {
+        rows.reverse();
+      }
+      if self.limit > 0 {
+        rows.truncate(self.limit);
+      }
+      rows
+    }
 }
 
 /// Defines the core information about a file or directory
 #[derive(Debug, Clone)]
 pub struct ResourceRow {
-    pub file: DirEntry,
+    pub file: FileEntry,
     pub extension: String,
+    pub meta: FileMeta,
     pub ts: u64,
-    pub target: Option<String>,
+    pub depth: usize,
     pub deleted: bool,
+    /// Path relative to the copy or move target set by a rule script, instead of the relative path
+    pub destination: Option<String>,
 }
 
-/// The default constructor works with a DirEntry object from WalkDir
+/// The default constructor works with a FileEntry found while walking
 impl ResourceRow {
-    pub fn new(file: &DirEntry) -> Self {
+    pub fn new(file: &FileEntry, meta: FileMeta, time_field: TimeField) -> Self {
         ResourceRow { 
             file: file.to_owned(), 
             extension: extract_extension(file),
-            ts: extract_timestamp(file),
-            target: None,
+            meta,
+            ts: meta.timestamp(time_field),
+            depth: file.depth(),
             deleted: false,
+            destination: None,
          }
     }
 
-    pub fn set_target(&mut self, target: &str) {
-      self.target = Some(target.to_owned());
+    /// Set the metadata once it is read, after the name filters have passed
+    pub fn set_meta(&mut self, meta: FileMeta, time_field: TimeField) {
+        self.meta = meta;
+        self.ts = meta.timestamp(time_field);
     }
 
     pub fn set_deleted(&mut self) {
@@ -68,32 +145,19 @@ impl ResourceRow {
     }
 
     pub fn seconds_old(&self) -> u64 {
-        current_timestamp() as u64 - self.ts
+        (current_timestamp() as u64).saturating_sub(self.ts)
     }
 
     pub fn days_old(&self) -> f64 {
         self.seconds_old() as f64 / 86400f64
     }
 
-    pub fn has_valid_extension(&self, extensions: &Vec<String>, exclusions: &Vec<String>) -> bool {
-        is_in_extensions(&self.extension, extensions) && is_not_in_extensions(&self.extension, exclusions)
-    }
-
-    pub fn is_not_in_excluded_dir(&self, criteria: &Criteria, root_ref: &Option<DirEntry>) -> bool {
-      let e_dirs = &criteria.exclude_directories;
-      is_not_excluded_dir(&self.file, e_dirs, root_ref) && (criteria.show_hidden || is_not_in_hidden_dir(&self.file, root_ref))
-    }
-
     pub fn file_name(&self) -> String {
       self.file.file_name().to_str().unwrap_or("").to_owned()
     }
 
     pub fn size(&self) -> u64 {
-        if let Ok(meta) = self.file.metadata() {
-            meta.size()
-        } else {
-            0u64
-        }
+        self.meta.size
     }
 
     pub fn smart_size(&self) -> String {
@@ -104,84 +168,29 @@ impl ResourceRow {
       self.file.path()
     }
 
-    pub fn matches(&self, pattern: &Option<Regex>) -> bool {
-      if let Some(re) = pattern {
-        re.to_owned().is_match(&self.file_name())
-      } else {
-        true
-      }
+    /// User id, group id and mode bits of the file
+    pub fn owner_mode(&self) -> (u32, u32, u32) {
+        (self.meta.uid, self.meta.gid, self.meta.mode)
     }
 
-    pub fn is_in_size_range(&self, sizes: &(u64, u64)) -> bool {
-        let (min, max) = sizes.to_owned();
-        let size = self.size();
-        (size >= min || min < 1) && (size <= max || max < 1) 
-    }
-
-    pub fn is_in_day_range(&self, criteria: &Criteria) -> bool {
-        if criteria.filter_by_age() {
-          let mut matches = false;
-          let has_min = criteria.has_min_age();
-          if has_min {
-            matches = self.days_old() >= criteria.min_age;
-          }
-          if criteria.has_max_age() {
-            let matches_max = self.days_old() < criteria.max_age;
-            if matches && !matches_max {
-              matches = false;
-            } else if !has_min {
-              matches = matches_max;
-            }
-          }
-          matches
-        } else {
-          true
-        }
-    }
-
-    pub fn matches_criteria(&self, criteria: &Criteria, root_ref: &Option<DirEntry>) -> bool {
-        self.is_in_day_range(&criteria) 
-        && self.is_in_size_range(&criteria.sizes)
-        && self.has_valid_extension(&criteria.include_extensions, &criteria.exclude_extensions)
-        && (!criteria.has_pattern() || self.matches(&criteria.pattern))
-        && (!criteria.has_omit_pattern() || self.matches(&criteria.exclude_pattern) == false)
-        && self.show_if_hidden(criteria.show_hidden, root_ref)
-    }
-
-    pub fn show_if_hidden(&self, show_hidden: bool, root_ref: &Option<DirEntry>) -> bool {
-      show_hidden || (self.file_name().starts_with(".") || self.relative_parts(root_ref).into_iter().any(|s| s.starts_with("."))) == false
+    pub fn uid(&self) -> u32 {
+        self.meta.uid
     }
 
     pub fn age_display(&self) -> String {
         seconds_to_day_hours_min_secs(self.seconds_old())
     }
 
-    pub fn file_display(&self, root_ref: &Option<DirEntry>) -> String {
+    pub fn file_display(&self, root_ref: &Option<FileEntry>) -> String {
         to_relative_path(&self.file, root_ref)
     }
 
-    pub fn relative_path(&self, root_ref: &Option<DirEntry>) -> String {
+    pub fn relative_path(&self, root_ref: &Option<FileEntry>) -> String {
       self.file_display(root_ref)
     }
 
-    pub fn relative_parent_path(&self, root_ref: &Option<DirEntry>) -> String {
-      path_to_relative_path(&self.file.path().parent().unwrap(), root_ref)
-    }
-
-    pub fn relative_parts(&self, root_ref: &Option<DirEntry>) -> Vec<String> {
-        to_relative_parts(&self.file, root_ref)
-    } 
-
-    pub fn directory_path_string(&self) -> String {
-      if let Some(parent_dir) = &self.file.path().parent() {
-        path_to_string(parent_dir)
-      } else {
-        "".to_string()
-      }
-    }
-
     pub fn depth(&self) -> usize {
-       self.file.depth()
+       self.depth
     }
 
     pub fn modified_date(&self) -> NaiveDateTime {
@@ -192,7 +201,7 @@ impl ResourceRow {
         self.modified_date().format("%Y-%m-%d %H:%M:%S").to_string()
     }
 
-    pub fn show(&self, root_ref: &Option<DirEntry>) {
+    pub fn show(&self, root_ref: &Option<FileEntry>) {
       let rel_file_path = self.file_display(root_ref);
       let file_ref = if self.deleted {  cformat!("<red>{}</red> [del]", rel_file_path) } else { cformat!("<yellow>{}</yellow>", rel_file_path) };
       cprintln!("{: >9}\t<green>{}</green>\t<cyan>{: >9}</cyan>\t{}\t{}\t{}", self.age_display(), self.modified_display(), self.smart_size(), self.extension, self.depth(), file_ref);
@@ -200,53 +209,52 @@ impl ResourceRow {
 
 }
 
+/// Rows for unit tests, at their depth below the first directory of the path, e.g. `/photos` for `/photos/2024/a.jpg`
+#[cfg(test)]
+pub(crate) fn sample_rows(paths: &[&str], meta: impl Fn(&Path) -> FileMeta) -> Vec<ResourceRow> {
+  paths.iter().map(|path| {
+    let path = Path::new(path);
+    let entry = FileEntry::new(path.to_path_buf(), path.components().count() - 2, false, false);
+    ResourceRow::new(&entry, meta(path), TimeField::Modified)
+  }).collect()
+}
+
 /// Defines a folder structure relative to its parent and its depth from the start directory, but without nested child folders
 #[derive(Debug, Clone)]
 pub struct ResourceSet {
-  pub parent: DirEntry,
+  pub parent: FileEntry,
   pub resources: Vec<ResourceRow>,
   pub depth: usize,
   pub num_subs: usize,
+  size: u64,
 }
 
 impl ResourceSet {
-  pub fn new(parent: &DirEntry) -> Self {
-    let num_subs = get_num_subdirectories(parent);
-    ResourceSet { parent: parent.to_owned(), resources: vec![], depth: parent.depth(), num_subs }
+  pub fn new(parent: &FileEntry, num_subs: usize) -> Self {
+    ResourceSet { parent: parent.to_owned(), resources: vec![], depth: parent.depth(), num_subs, size: 0 }
   }
 
-  pub fn push(&mut self, resource: &ResourceRow) {
-    self.resources.push(resource.to_owned());
+  pub fn push(&mut self, resource: ResourceRow) {
+    self.size += resource.size();
+    self.resources.push(resource);
   }
 
   pub fn count(&self) -> usize {
     self.resources.len()
   }
 
-  pub fn is_not_excluded_dir(&self, e_dirs: &Vec<String>, root_ref: &Option<DirEntry>) -> bool {
-    is_not_excluded_dir(&self.parent, e_dirs, root_ref)
-  }
-  
   pub fn size(&self) -> u64 {
-    let mut size = 0u64;
-    for row in &self.resources {
-      size += row.size();
-    }
-    size
+    self.size
   }
 
   pub fn depth(&self) -> u8 {
-    self.parent.depth() as u8
+    self.depth as u8
   }
 
-  pub fn path_display(&self, root_ref: &Option<DirEntry>) -> String {
+  pub fn path_display(&self, root_ref: &Option<FileEntry>) -> String {
     to_relative_path(&self.parent, root_ref)
   }
 
-  pub fn full_path_string(&self) -> String {
-    path_to_string(&self.parent.path())
-  }
-
   pub fn smart_size(&self) -> String {
       smart_size(self.size())
   }
@@ -261,11 +269,15 @@ impl ResourceSet {
     }
   }
 
-  pub fn show(&self, root_ref: &Option<DirEntry>, show_files: bool) {  
-    if show_files {
-      for row in &self.resources {
+  pub fn show(&self, root_ref: &Option<FileEntry>, details: &DetailLevel) {
+    if details.show_files {
+      let rows = details.listed_rows(&self.resources);
+      for row in &rows {
         row.show(root_ref);
       }
+      if rows.len() < self.count() {
+        cprintln!("{: >9}\t<italics>and {} more</italics>", "", self.count() - rows.len());
+      }
     }
     let files_word = if self.count() == 1 { "file" } else { "files" };
     cprintln!("<cyan>{: >8}</cyan> {}\t{: >10}\t{: <10}\t<yellow>{: >9}</yellow>", self.count(), files_word, self.num_sub_dirs_display(), self.smart_size(), self.path_display(root_ref));
@@ -273,97 +285,222 @@ impl ResourceSet {
 
 }
 
-/// Data set about extension usage
+/// Data set about files grouped by extension or owner
 #[derive(Debug, Clone)]
-pub struct ExtensionStats {
+pub struct GroupStats {
   pub key: String,
   pub count: u32,
   pub size: u64
 }
 
-impl ExtensionStats {
+impl GroupStats {
   pub fn new(key: String, count: u32, size: u64) -> Self {
-    ExtensionStats { key, count, size }
+    GroupStats { key, count, size }
   }
 }
 
-/// Flattened Directory tree, with nested subidrectories listed after their parents
+/// A file noted by the running totals, e.g. the largest or the oldest match
 #[derive(Debug, Clone)]
-pub struct ResourceTree {
-  parent: Option<DirEntry>,
-  pub directories: Vec<Box<ResourceSet>>,
-  pub max_depth: u8,
+pub struct FileMark {
+  pub name: String,
+  pub size: u64,
+  pub ts: u64,
 }
 
-impl ResourceTree {
-  pub fn new(max_depth: u8) -> Self {
-    ResourceTree { max_depth, parent: None, directories: vec![] }
+impl FileMark {
+  pub fn new(row: &ResourceRow) -> Self {
+    FileMark { name: row.file_name(), size: row.size(), ts: row.ts }
   }
 
-  pub fn parent_dir(&mut self) -> Option<Box<&mut ResourceSet>> {
-    if self.directories.len() > 0 {
-      let first_opt = self.directories.get_mut(0);
+  pub fn age_display(&self) -> String {
+    seconds_to_day_hours_min_secs((current_timestamp() as u64).saturating_sub(self.ts))
+  }
+}
 
-              
-       if first_opt.is_some() {
-        // let par = first_opt.unwrap().to_owned().as_mut();
-        Some(Box::new(first_opt.unwrap().as_mut()))
-       } else {
-        None
-       }
-    } else {
-      None
+/// Running aggregates updated as each directory and matched file is found,
+/// so the overview does not need the matched files to be kept in memory
+#[derive(Debug, Clone, Default)]
+pub struct ScanTotals {
+  pub num_files: usize,
+  pub size: u64,
+  pub num_sub_dirs: usize,
+  pub max_dir_depth: usize,
+  pub smallest: Option<FileMark>,
+  pub largest: Option<FileMark>,
+  pub newest: Option<FileMark>,
+  pub oldest: Option<FileMark>,
+  extensions: HashMap<String, (u32, u64)>,
+  owners: HashMap<u32, (u32, u64)>,
+}
+
+impl ScanTotals {
+  pub fn add_dir(&mut self, resource_set: &ResourceSet) {
+    if resource_set.depth == 1 {
+      self.num_sub_dirs += 1;
     }
+    self.max_dir_depth = self.max_dir_depth.max(resource_set.depth);
   }
 
-   pub fn matched_sub_dir(&mut self, row: &ResourceRow) -> Option<Box<&mut ResourceSet>> {
-    if self.directories.len() > 1 {
-      let full_path = row.directory_path_string();
-      self.get_matched_sub(full_path)
-    } else {
-      None
+  pub fn add_file(&mut self, row: &ResourceRow) {
+    let size = row.size();
+    self.num_files += 1;
+    self.size += size;
+    if size > 0 {
+      if self.smallest.as_ref().map(|mark| size < mark.size).unwrap_or(true) {
+        self.smallest = Some(FileMark::new(row));
+      }
+      if self.largest.as_ref().map(|mark| size >= mark.size).unwrap_or(true) {
+        self.largest = Some(FileMark::new(row));
+      }
+    }
+    if self.newest.as_ref().map(|mark| row.ts > mark.ts).unwrap_or(true) {
+      self.newest = Some(FileMark::new(row));
+    }
+    if self.oldest.as_ref().map(|mark| row.ts <= mark.ts).unwrap_or(true) {
+      self.oldest = Some(FileMark::new(row));
+    }
+    let ext_entry = self.extensions.entry(row.extension.to_owned()).or_insert((0, 0));
+    ext_entry.0 += 1;
+    ext_entry.1 += size;
+    let owner_entry = self.owners.entry(row.uid()).or_insert((0, 0));
+    owner_entry.0 += 1;
+    owner_entry.1 += size;
+  }
+
+  /// Add the totals of another scanned root
+  pub fn merge(&mut self, other: &ScanTotals) {
+    self.num_files += other.num_files;
+    self.size += other.size;
+    self.num_sub_dirs += other.num_sub_dirs;
+    self.max_dir_depth = self.max_dir_depth.max(other.max_dir_depth);
+    if let Some(mark) = &other.smallest {
+      if self.smallest.as_ref().map(|own| mark.size < own.size).unwrap_or(true) {
+        self.smallest = Some(mark.clone());
+      }
+    }
+    if let Some(mark) = &other.largest {
+      if self.largest.as_ref().map(|own| mark.size >= own.size).unwrap_or(true) {
+        self.largest = Some(mark.clone());
+      }
+    }
+    if let Some(mark) = &other.newest {
+      if self.newest.as_ref().map(|own| mark.ts > own.ts).unwrap_or(true) {
+        self.newest = Some(mark.clone());
+      }
+    }
+    if let Some(mark) = &other.oldest {
+      if self.oldest.as_ref().map(|own| mark.ts <= own.ts).unwrap_or(true) {
+        self.oldest = Some(mark.clone());
+      }
+    }
+    for (key, (count, size)) in &other.extensions {
+      let entry = self.extensions.entry(key.to_owned()).or_insert((0, 0));
+      entry.0 += count;
+      entry.1 += size;
+    }
+    for (uid, (count, size)) in &other.owners {
+      let entry = self.owners.entry(*uid).or_insert((0, 0));
+      entry.0 += count;
+      entry.1 += size;
     }
   }
 
-  fn get_matched_sub(&mut self, full_path: String) -> Option<Box<&mut ResourceSet>> {
-    let matched_opt = self.directories.iter_mut().find(|rs| rs.full_path_string() == full_path);
-    if let Some(matched_box)  = matched_opt {
-      Some(Box::new(matched_box.as_mut()))
-    } else {
-      None
+  /// Newest, oldest, total, smallest and largest lines of the overview
+  pub fn show_extremes(&self) {
+    if let Some(newest) = &self.newest {
+      let newest_text = cformat!("<green>{}</green> ({})", newest.age_display(), newest.name);
+      cprintln!("{: <12} {}", "newest", newest_text);
+    }
+    if let Some(oldest) = &self.oldest {
+      let oldest_text = cformat!("<green>{}</green> ({})", oldest.age_display(), oldest.name);
+      cprintln!("{: <12} {}", "oldest", oldest_text);
+    }
+    cprintln!("{: <12} <cyan>{}</cyan>", "tot. size", smart_size(self.size));
+    if self.num_files > 1 {
+      if let Some(smallest) = &self.smallest {
+        let min_size_text = cformat!("<cyan>{}</cyan> ({})", smart_size(smallest.size), smallest.name);
+        cprintln!("{: <12} {}", "min. size", min_size_text);
+      }
+      if let Some(largest) = &self.largest {
+        let max_size_text = cformat!("<cyan>{}</cyan> ({})", smart_size(largest.size), largest.name);
+        cprintln!("{: <12} {}", "max. size", max_size_text);
+      }
     }
   }
 
-  pub fn add_to_parent(&mut self, row: &ResourceRow) {
-    if let Some(parent) = self.parent_dir() {
-      let _ = &parent.push(row);
-    }
+  pub fn build_extension_map(&self) -> Vec<GroupStats> {
+    let mut ext_stats: Vec<GroupStats> = self.extensions.iter().map(|(key, (count, size))| GroupStats::new(key.to_owned(), *count, *size)).collect();
+    ext_stats.sort_by_key(|b| std::cmp::Reverse(b.size));
+    ext_stats
   }
 
-   pub fn add_to_sub(&mut self, row: &ResourceRow) {
-    if let Some(curr_dir) = self.matched_sub_dir(row) {
-      let _ = &curr_dir.push(row);
+  pub fn build_owner_map(&self) -> Vec<GroupStats> {
+    let mut owner_stats: Vec<GroupStats> = self.owners.iter().map(|(uid, (count, size))| GroupStats::new(user_display(*uid), *count, *size)).collect();
+    owner_stats.sort_by_key(|b| std::cmp::Reverse(b.size));
+    owner_stats
+  }
+}
+
+/// Flattened Directory tree, with nested subidrectories listed after their parents.
+/// Directories are indexed by their full path, so files are added in constant time.
+/// In streaming mode matched files are shown as they are added and only the running totals are kept.
+#[derive(Debug, Clone)]
+pub struct ResourceTree {
+  parent: Option<FileEntry>,
+  pub directories: Vec<Box<ResourceSet>>,
+  pub max_depth: u8,
+  pub streaming: bool,
+  pub show_progress: bool,
+  /// Result of the action applied during the scan or by run
+  pub tally: Option<ActionTally>,
+  pub errors: Vec<FileError>,
+  /// Subfolder of the copy or move target for this root, when several roots are scanned
+  pub target_folder: Option<String>,
+  /// State file left behind by an incomplete copy or move, to resume it
+  pub job_file: Option<PathBuf>,
+  /// File system that was scanned, where actions are applied
+  pub fs: Arc<dyn FileSystem>,
+  index: HashMap<PathBuf, usize>,
+  totals: ScanTotals,
+}
+
+impl ResourceTree {
+  pub fn new(max_depth: u8, streaming: bool) -> Self {
+    ResourceTree { max_depth, streaming, show_progress: false, tally: None, errors: vec![], target_folder: None, job_file: None, fs: Arc::new(OsFileSystem), parent: None, directories: vec![], index: HashMap::new(), totals: ScanTotals::default() }
+  }
+
+  /// Add a matched file to the directory that contains it
+  pub fn add(&mut self, row: ResourceRow) {
+    if self.streaming {
+      self.totals.add_file(&row);
+      row.show(&self.parent);
+      return;
+    }
+    let dir_index = row.path_ref().parent().and_then(|dir| self.index.get(dir)).copied();
+    if let Some(directory) = dir_index.and_then(|index| self.directories.get_mut(index)) {
+      self.totals.add_file(&row);
+      directory.push(row);
     }
   }
 
-  pub fn add_root(&mut self, parent: &DirEntry) {
-    self.parent = Some(parent.to_owned());
+  pub fn root(&self) -> &Option<FileEntry> {
+    &self.parent
   }
 
-  pub fn push(&mut self, resource_set: &ResourceSet) {
-    self.directories.push(Box::new(resource_set.to_owned()));
+  pub fn add_root(&mut self, parent: &FileEntry) {
+    self.parent = Some(parent.to_owned());
   }
 
-  pub fn num_dirs(&self) -> usize { 
-    self.directories.len()
+  pub fn push(&mut self, resource_set: ResourceSet) {
+    self.totals.add_dir(&resource_set);
+    if !self.streaming {
+      self.index.insert(resource_set.parent.path().to_path_buf(), self.directories.len());
+      self.directories.push(Box::new(resource_set));
+    }
   }
 
   pub fn num_sub_dirs(&self) -> usize { 
-    if self.num_dirs() > 0 {
-      self.directories.clone().into_iter().filter(|rs| rs.depth() == 1).collect::<Vec<Box<ResourceSet>>>().len()
-    } else {
-      0
-    }
+    self.totals.num_sub_dirs
   }
 
   pub fn num_sub_dirs_display(&self) -> String {
@@ -377,74 +514,20 @@ impl ResourceTree {
   }
 
   pub fn size(&self) -> u64 {
-    let mut size = 0u64;
-    for row in &self.directories {
-      size += row.size();
-    }
-    size
-  }
-
-  pub fn get_min_max_files(&self) -> (Option<ResourceRow>, Option<ResourceRow>) {
-    let mut min_val = 0;
-    let mut max_val = 0;
-    let mut min_row: Option<ResourceRow> = None;
-    let mut max_row: Option<ResourceRow> = None;
-    for row in &self.directories {
-      for resource in row.resources.clone() {
-        let size_val = resource.size();
-        if size_val > max_val {
-          max_row = Some(resource);
-          max_val = size_val;
-        } else if size_val > 0 && (size_val < min_val || min_val < 1) {
-          min_row = Some(resource);
-          min_val = size_val;
-        }
-      }
-    }
-    (min_row, max_row)
-  }
-
-  pub fn get_oldest_newest_files(&self) -> (Option<ResourceRow>, Option<ResourceRow>) {
-    let mut min_val = 0;
-    let mut max_val = 0;
-    let mut min_row: Option<ResourceRow> = None;
-    let mut max_row: Option<ResourceRow> = None;
-    for row in &self.directories {
-      for resource in row.resources.clone() {
-        let age_val = resource.seconds_old();
-        if age_val > max_val {
-          max_row = Some(resource);
-          max_val = age_val;
-        } else if age_val > 0 && (age_val < min_val || min_val < 1) {
-          min_row = Some(resource);
-          min_val = age_val;
-        }
-      }
-    }
-    (min_row, max_row)
+    self.totals.size
   }
 
   pub fn num_files(&self) -> usize {
-    let mut num = 0;
-    for row in &self.directories {
-      num += row.count();
-    }
-    num
+    self.totals.num_files
   }
 
   pub fn max_depth_scanned(&self) -> usize {
-    let mut num = 1;
-    for row in &self.directories {
-      if row.depth > num {
-        num = row.depth + 1;
-      }
-    }
-    num
+    self.totals.max_dir_depth + 1
   }
 
   pub fn path_display(&self) -> String {
       if let Some(root) = &self.parent {
-        if let Some(root_path) = root.to_owned().into_path().to_str() {
+        if let Some(root_path) = root.path().to_str() {
           root_path.to_owned()
         } else {
           "".to_owned()  
@@ -458,136 +541,152 @@ impl ResourceTree {
       smart_size(self.size())
   }
 
-  pub fn build_extension_map(&self) -> Vec<ExtensionStats> {
-    let mut map: HashMap<String, (u32, u64)> = HashMap::new();
-    for directory in &self.directories {
-      if directory.count() > 0 {
-        for file in &directory.resources {
-          let mut ext_count: u32 = 1;
-          let mut ext_size: u64 = 1;
-          if map.contains_key(&file.extension) {
-            let (curr_count, curr_size) = map.get_mut(&file.extension).unwrap().to_owned();
-            ext_count = curr_count + 1;
-            ext_size = curr_size + file.size();
-          }
-          map.insert(file.extension.to_owned(), (ext_count, ext_size));
-        }
-      }
-    }
-    let mut ext_stats: Vec<ExtensionStats> = vec![];
-    for (key, item) in map.into_iter() {
-      ext_stats.push(ExtensionStats::new(key, item.0, item.1));
-    }
-    ext_stats.sort_by(|a, b| b.size.cmp(&a.size));
-    ext_stats
-  }
-
   pub fn show_extension_stats(&self) {
     cprintln!("<cyan,italics>BY EXTENSION</cyan,italics>");
-    for row in self.build_extension_map().into_iter() {
+    for row in self.totals.build_extension_map().into_iter() {
       let file_word = pluralize_64("file", "s", row.count as u64);
       let ext_text = if row.key.len() > 0 { row.key } else { "[none]".to_owned() };
       cprintln!("<yellow>{: >10}</yellow>\t<cyan>{: >9}</cyan> {}\t{}", ext_text, row.count, file_word, smart_size(row.size));
     }
   }
 
+  pub fn show_owner_stats(&self) {
+    cprintln!("<cyan,italics>BY OWNER</cyan,italics>");
+    for row in self.totals.build_owner_map().into_iter() {
+      let file_word = pluralize_64("file", "s", row.count as u64);
+      cprintln!("<yellow>{: >10}</yellow>\t<cyan>{: >9}</cyan> {}\t{}", row.key, row.count, file_word, smart_size(row.size));
+    }
+  }
+
+  /// The first files across the whole tree in the selected order, largest first by default
+  pub fn top_rows(&self, details: &DetailLevel) -> Vec<&ResourceRow> {
+    let mut rows: Vec<&ResourceRow> = self.action_rows().collect();
+    details.sort_rows(&mut rows, details.sort.unwrap_or(SortKey::Size));
+    rows.truncate(details.top);
+    rows
+  }
+
   pub fn show(&self, details: &DetailLevel) {
-    for directory in &self.directories {
-      if self.parent.is_some() {
-        if directory.as_ref().depth() < self.max_depth {
-          if details.show_void_directories || directory.count() > 0 {
-            directory.as_ref().show(&self.parent, details.show_files);
-          }
+    if details.top > 0 {
+      let label = details.sort.unwrap_or(SortKey::Size).to_label(details.reverse);
+      cprintln!("<cyan,italics>TOP {} ({})</cyan,italics>", details.top, label);
+      for row in self.top_rows(details) {
+        row.show(&self.parent);
+      }
+    } else {
+      for directory in &self.directories {
+        let visible = details.show_void_directories || directory.count() > 0;
+        if self.parent.is_some() && directory.as_ref().depth() < self.max_depth && visible {
+          directory.as_ref().show(&self.parent, details);
         }
       }
     }
     if details.show_extension_groups {
       self.show_extension_stats();
     }
+    if details.show_owner_groups {
+      self.show_owner_stats();
+    }
     let num_files = self.num_files();
     cprintln!("<cyan,italics>OVERVIEW</cyan,italics>");
     cprintln!("{: <12} <yellow>{}</yellow>", "path", self.path_display());
     let sub_dir_info = if self.num_sub_dirs() > 0 { format!("\t({})", self.num_sub_dirs_display()) } else { "".to_owned() };
     cprintln!("{: <12} <green>{}</green>{}", "total files", num_files, sub_dir_info);
     if num_files > 0 {
-      let (min_file, max_file) = self.get_oldest_newest_files();
-      if let Some(min_resource) = min_file {
-        let oldest_text = cformat!("<green>{}</green> ({})", min_resource.age_display(), min_resource.file_name());
-        cprintln!("{: <12} {}", "newest", oldest_text);
-      }
-      if let Some(max_resource) = max_file {
-        let newest_text = cformat!("<green>{}</green> ({})",   max_resource.age_display(), max_resource.file_name());
-        cprintln!("{: <12} {}", "oldest", newest_text);
-      }
-      cprintln!("{: <12} <cyan>{}</cyan>", "tot. size", self.smart_size());
-      if num_files > 1 {
-        let (min_file, max_file) = self.get_min_max_files();
-
-        if let Some(min_resource) = min_file {
-          let min_size_text = cformat!("<cyan>{}</cyan> ({})", min_resource.smart_size(), min_resource.file_name());
-          cprintln!("{: <12} {}", "min. size", min_size_text);
-        }
-        if let Some(max_resource) = max_file {
-          let max_size_text = cformat!("<cyan>{}</cyan> ({})",   max_resource.smart_size(), max_resource.file_name());
-          cprintln!("{: <12} {}", "max. size", max_size_text);
-        }
-      }
+      self.totals.show_extremes();
       cprintln!("{: <12} <cyan>{}</cyan> (limit: {})", "max depth", self.max_depth_scanned(), self.max_depth);
     }
   }
 
-  pub fn run(&self, action: ActionMode, target: Option<Box<PathBuf>>) {
-    let root_ref = self.parent.clone();
-    let mut num = 0u64;
-    let mut size = 0u64;
-    for directory in &self.directories {
-      if self.parent.is_some() {
-        if directory.as_ref().depth() < self.max_depth {
-          for mut resource in directory.resources.clone() {
-            let file_size = resource.size();
-            let mut success = false;
-            match action {
-               ActionMode::Copy => {
-                let (copied, target_path) = copy_file(&resource, &target, &root_ref);
-                if copied {
-                  resource.set_target(&target_path);
-                  success = true;
-                }
-              },
-               ActionMode::Move => {
-                let (moved, target_path) = move_file(&resource, &target, &root_ref);
-                if moved {
-                  resource.set_target(&target_path);
-                  success = true;
-                }
-              },
-              ActionMode::DirectDelete | ActionMode::Delete => {
-                if let Ok(_success) = remove_file(resource.path_ref()) {
-                  resource.set_deleted();
-                  success = true;
-                }                
-              },
-              _ => {}
-            }
-            if success {
-              num += 1;
-              size += file_size;
-            }
-          }
-        }
+  /// Files in the directories shown by the overview, which actions apply to
+  pub fn action_rows(&self) -> impl Iterator<Item = &ResourceRow> {
+    let max_depth = if self.parent.is_some() { self.max_depth } else { 0 };
+    self.directories.iter().filter(move |directory| directory.depth() < max_depth).flat_map(|directory| directory.resources.iter())
+  }
+
+  /// Mark the rows of deleted files, so the listing shows them as deleted
+  pub fn set_deleted(&mut self, paths: &[PathBuf]) {
+    for path in paths {
+      let dir_index = path.parent().and_then(|dir| self.index.get(dir)).copied();
+      if let Some(directory) = dir_index.and_then(|index| self.directories.get_mut(index)) {
+        directory.resources.iter_mut().filter(|row| row.path_ref() == path).for_each(|row| row.set_deleted());
       }
     }
-    match action {
-      ActionMode::Move | ActionMode::Copy  => {
-        let mut target_path = "".to_string();
-        if let Some(tg_path) = target {
-          target_path = format!(" to {}", tg_path.to_str().unwrap_or(""));
-        }
-        cprintln!("{} {} {} ({}){}", action.to_past(), num, pluralize_64("file", "s", num), smart_size(size), target_path);
-      },
-      _ => ()
-    }
-    
   }
 
+  /// Number of files the action could not be applied to
+  pub fn num_action_errors(&self) -> usize {
+    self.errors.iter().filter(|error| error.operation.is_action()).count()
+  }
+
+}
+
+/// Matched files and size of each root, then the combined totals, when several roots are scanned
+pub fn show_combined_overview(trees: &[ResourceTree]) {
+  let mut totals = ScanTotals::default();
+  cprintln!("<cyan,italics>COMBINED</cyan,italics>");
+  for tree in trees {
+    totals.merge(&tree.totals);
+    let files_word = if tree.num_files() == 1 { "file" } else { "files" };
+    cprintln!("<cyan>{: >8}</cyan> {}\t{: <10}\t<yellow>{}</yellow>", tree.num_files(), files_word, tree.smart_size(), tree.path_display());
+  }
+  cprintln!("{: <12} <green>{}</green>\t({} roots)", "total files", totals.num_files, trees.len());
+  if totals.num_files > 0 {
+    totals.show_extremes();
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::file_system::MemoryFileSystem;
+  use crate::run::Scanner;
+  use crate::utils::current_timestamp;
+
+  const DAY: u64 = 86400;
+
+  fn sample_tree() -> ResourceTree {
+    let now = current_timestamp() as u64;
+    let fs = Arc::new(MemoryFileSystem::new());
+    fs.add_file("/media/b.mp4", &[0; 500], now - 400 * DAY);
+    fs.add_file("/media/a.mkv", &[0; 100], now - 10 * DAY);
+    fs.add_file("/media/c.mp4", &[0; 300], now - 800 * DAY);
+    fs.add_file("/media/2020/d.mkv", &[0; 900], now - 1000 * DAY);
+    Scanner::with_file_system(CriteriaBuilder::new().build().unwrap(), fs).scan("/media")
+  }
+
+  fn names(rows: &[&ResourceRow]) -> Vec<String> {
+    rows.iter().map(|row| row.file_name()).collect()
+  }
+
+  #[test]
+  fn test_sorted_and_top_rows() {
+    let tree = sample_tree();
+    let mut details = DetailLevel::new(&true, &false, &false, &false);
+    details.top = 2;
+    assert_eq!(names(&tree.top_rows(&details)), vec!["d.mkv", "b.mp4"]);
+    details.sort = Some(SortKey::Age);
+    details.reverse = true;
+    assert_eq!(names(&tree.top_rows(&details)), vec!["a.mkv", "b.mp4"]);
+    details.top = 0;
+    details.sort = Some(SortKey::Ext);
+    details.reverse = false;
+    details.limit = 2;
+    assert_eq!(names(&details.listed_rows(&tree.directories[0].resources)), vec!["a.mkv", "b.mp4"]);
+    details.sort = Some(SortKey::Name);
+    details.reverse = true;
+    assert_eq!(names(&details.listed_rows(&tree.directories[0].resources)), vec!["c.mp4", "b.mp4"]);
+  }
+
+  #[test]
+  fn test_future_timestamp_is_not_old() {
+    let now = current_timestamp() as u64;
+    let rows = sample_rows(&["/media/a.mkv", "/media/b.mkv"], |path| {
+      let modified = if path.ends_with("a.mkv") { now + DAY } else { now - DAY };
+      FileMeta { modified, ..FileMeta::default() }
+    });
+    assert_eq!(rows[0].seconds_old(), 0);
+    assert!(rows[1].seconds_old() >= DAY);
+  }
 }
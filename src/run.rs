@@ -1,61 +1,442 @@
-use std::fs::remove_file;
-use walkdir::{WalkDir, DirEntry};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use rayon::prelude::*;
 use crate::resource_row::*;
 use crate::criteria::*;
-use crate::manage::{move_file, copy_file};
-
-/// Scan the directory and subdirectory to the max specified depth (5 by default)
-/// and apply filters such as age, file size, extension or file name patterns
-pub fn scan_directory(path_str: &str, details: &DetailLevel, criteria: &mut Criteria) -> ResourceTree {
-    let mut root_ref:Option<DirEntry> = None;
-    let mut resource_tree: ResourceTree = ResourceTree::new(criteria.max_depth);
-    let target_dir = WalkDir::new(path_str).min_depth(0).max_depth(criteria.max_depth as usize).follow_links(true).same_file_system(true);
-    let target_path = criteria.apply_action_permissions();
-    for file in target_dir.into_iter().filter_map(|file| file.ok()) {
-        let ft = file.file_type();
-        let mut not_excluded = true;
-        if ft.is_dir() {
-            if root_ref.is_none() {
-                root_ref = Some(file.clone());
-                resource_tree.add_root(&file);
-            }
-            let r_set = ResourceSet::new(&file  );
-            if ft.is_dir() && root_ref.is_some() {
-               not_excluded = r_set.is_not_excluded_dir(&criteria.exclude_directories, &root_ref);
-            }
-            if not_excluded {
-              resource_tree.push(&r_set);
+use crate::action::{Action, ActionTally};
+use crate::transfer::Throttle;
+use crate::progress::Progress;
+use crate::interrupt::is_interrupted;
+use crate::file_meta::FileMeta;
+use crate::file_system::{FileEntry, FileSystem, OsFileSystem};
+use crate::filters::ScanContext;
+use crate::errors::{ErrorLog, FileError, FileErrorKind, FileOperation};
+use crate::script::ScriptOutcome;
+
+/// A directory or a file that matched all filters
+enum ScanItem {
+    Directory(ResourceSet),
+    File(ResourceRow),
+}
+
+/// Scans a directory tree with the given criteria.
+///
+/// ```no_run
+/// use file_organiser::{CriteriaBuilder, Scanner};
+///
+/// let criteria = CriteriaBuilder::new().extensions("mp4,mkv").before("1y").build().unwrap();
+/// let tree = Scanner::new(criteria).scan("/srv/media");
+/// println!("{} old videos, {} bytes", tree.num_files(), tree.size());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    pub criteria: Criteria,
+    pub fs: Arc<dyn FileSystem>,
+    /// Subfolder of the copy or move target for files from this root, when several roots are scanned
+    pub target_folder: Option<String>,
+}
+
+impl Scanner {
+    pub fn new(criteria: Criteria) -> Self {
+        Scanner { criteria, fs: Arc::new(OsFileSystem), target_folder: None }
+    }
+
+    /// Scan another file system, such as a `MemoryFileSystem` in tests
+    pub fn with_file_system(criteria: Criteria, fs: Arc<dyn FileSystem>) -> Self {
+        Scanner { criteria, fs, target_folder: None }
+    }
+
+    /// Copy or move the files of this root to a subfolder of the target
+    pub fn with_target_folder(mut self, folder: &str) -> Self {
+        self.target_folder = Some(folder.to_owned());
+        self
+    }
+
+    /// Scan the directory and subdirectory to the max specified depth (5 by default)
+    /// and apply filters such as age, file size, extension or file name patterns.
+    /// Excluded and hidden directories are pruned from the walk, and name filters
+    /// are applied before reading any file metadata. Confirmed deletes, and any confirmed
+    /// action when streaming, are applied to each file as it is found.
+    pub fn scan(&mut self, path_str: &str) -> ResourceTree {
+        self.scan_with(path_str, None)
+    }
+
+    /// Check the listed files instead of walking the directory, e.g. paths from `git ls-files` or `find`.
+    /// Paths keep their location relative to the root directory for copy and move.
+    /// Listed directories are skipped, as are files below excluded or hidden directories,
+    /// but there is no depth limit.
+    pub fn scan_list(&mut self, path_str: &str, paths: &[PathBuf]) -> ResourceTree {
+        self.scan_with(path_str, Some(paths))
+    }
+
+    fn scan_with(&mut self, path_str: &str, list: Option<&[PathBuf]>) -> ResourceTree {
+        let fs = self.fs.as_ref();
+        let criteria = &mut self.criteria;
+        let root_ref: Option<FileEntry> = fs.entry(Path::new(path_str)).ok();
+        let mut resource_tree: ResourceTree = ResourceTree::new(criteria.max_depth, criteria.stream);
+        resource_tree.fs = self.fs.clone();
+        resource_tree.target_folder = self.target_folder.clone();
+        if let Some(root) = &root_ref {
+            resource_tree.add_root(root);
+        }
+        criteria.apply_action_permissions();
+        let criteria: &Criteria = criteria;
+        let mut scan_action = ScanAction::new(criteria);
+        if let Some(folder) = &self.target_folder {
+            scan_action.action = scan_action.action.map(|action| action.in_folder(folder));
+        }
+        let progress = Progress::scan(criteria.show_progress);
+        let errors = ErrorLog::default();
+        let ctx = ScanContext::new(root_ref.clone(), self.fs.clone());
+        let start = Instant::now();
+        resource_tree.show_progress = criteria.show_progress;
+        if let Some(root) = &root_ref {
+            let walk = ScanWalk { fs, criteria, ctx: &ctx, root_device: fs.device(root.path()), progress: &progress, errors: &errors };
+            let ancestors = vec![fs.canonicalize(root.path()).unwrap_or(root.path().to_path_buf())];
+            if let Some(paths) = list {
+                walk.scan_list(root, paths, &mut |item| add_scan_item(&mut resource_tree, item, &mut scan_action, criteria, fs, &root_ref));
+                // listed files may be nested deeper than the walk would go
+                resource_tree.max_depth = resource_tree.max_depth.max(resource_tree.max_depth_scanned().min(u8::MAX as usize) as u8);
+            } else if criteria.threads > 1 {
+                let scan = || walk.walk_parallel(root, &ancestors);
+                let items = match rayon::ThreadPoolBuilder::new().num_threads(criteria.threads).build() {
+                    Ok(pool) => pool.install(scan),
+                    Err(_) => scan(),
+                };
+                for item in items {
+                    add_scan_item(&mut resource_tree, item, &mut scan_action, criteria, fs, &root_ref);
+                }
+            } else {
+                walk.walk(root, &ancestors, &mut |item| add_scan_item(&mut resource_tree, item, &mut scan_action, criteria, fs, &root_ref));
             }
-        } else {
-            let mut resource = ResourceRow::new(&file);
-            if resource.matches_criteria(&criteria, &root_ref) {
-                if criteria.may_copy() {
-                  let (copied, new_path) = copy_file(&resource, &target_path, &root_ref);
-                  if copied {
-                    resource.set_target(new_path.as_str());
-                  }
-                } else if criteria.may_move() {
-                  let (moved, new_path) = move_file(&resource, &target_path, &root_ref);
-                  if moved {
-                    resource.set_target(new_path.as_str());
-                  }
+        }
+        progress.finish();
+        // scan errors are listed first, as errors from actions during the scan are already in the tree
+        let mut scan_errors = errors.into_errors();
+        scan_errors.append(&mut resource_tree.errors);
+        resource_tree.errors = scan_errors;
+        if scan_action.action.is_some() {
+            scan_action.tally.elapsed = start.elapsed();
+            resource_tree.tally = Some(scan_action.tally);
+        }
+        resource_tree
+    }
+}
+
+/// Action applied to matched files during the scan, with its running tally
+struct ScanAction {
+    action: Option<Action>,
+    throttle: Option<Throttle>,
+    tally: ActionTally,
+}
+
+impl ScanAction {
+    fn new(criteria: &Criteria) -> Self {
+        let throttle = criteria.transfer.bwlimit.map(Throttle::new);
+        ScanAction { action: criteria.scan_action(), throttle, tally: ActionTally::default() }
+    }
+}
+
+/// Apply any confirmed action and add the item to the tree.
+/// Unless streaming, only deletes are applied here and copy and move actions run once the scan is complete.
+fn add_scan_item(resource_tree: &mut ResourceTree, item: ScanItem, scan_action: &mut ScanAction, criteria: &Criteria, fs: &dyn FileSystem, root_ref: &Option<FileEntry>) {
+    match item {
+        ScanItem::Directory(resource_set) => resource_tree.push(resource_set),
+        ScanItem::File(mut resource) => {
+            if let Some(action) = &scan_action.action {
+                let result = action.apply_file(fs, &resource, root_ref, scan_action.throttle.as_ref());
+                if let Err(error) = &result {
+                    resource_tree.errors.push(FileError::from_io(resource.path_ref(), action.operation(), error));
                 } else if criteria.may_delete() {
-                  if let Ok(_ok) = remove_file(resource.path_ref()) {
                     resource.set_deleted();
-                  }
-                }
-                // Add first level files to the parent directories
-                if resource.depth() < 2 {
-                    resource_tree.add_to_parent(&resource);
-                }  else {
-                  // Add the path of nested files to their subdirectory, unless excluded
-                    if resource.is_not_in_excluded_dir(&criteria, &root_ref) { 
-                        resource_tree.add_to_sub(&resource);
+                }
+                scan_action.tally.add(result.is_ok(), resource.size());
+            }
+            resource_tree.add(resource);
+        }
+    }
+}
+
+/// Shared state of a scan
+struct ScanWalk<'a> {
+    fs: &'a dyn FileSystem,
+    criteria: &'a Criteria,
+    ctx: &'a ScanContext,
+    root_device: Option<u64>,
+    progress: &'a Progress,
+    errors: &'a ErrorLog,
+}
+
+impl ScanWalk<'_> {
+    /// Build the directory set with its number of subdirectories, and list its walkable children
+    /// unless it is at the max depth or on another file system
+    fn open_dir(&self, dir: &FileEntry) -> (ScanItem, Vec<FileEntry>) {
+        self.progress.add_dir();
+        let descend = dir.depth() < self.criteria.max_depth as usize && self.fs.device(dir.path()) == self.root_device;
+        let children = match self.fs.read_dir(dir) {
+            Ok(listing) => {
+                if descend {
+                    for (path, error) in &listing.failures {
+                        self.errors.add(FileError::from_io(path, FileOperation::Scan, error));
                     }
                 }
+                listing.entries
+            }
+            Err(error) => {
+                if descend {
+                    self.errors.add(FileError::from_io(dir.path(), FileOperation::Scan, &error));
+                }
+                vec![]
+            }
+        };
+        let num_subs = children.iter().filter(|child| child.is_dir()).count();
+        let children = if descend { children.into_iter().filter(|child| self.criteria.is_walkable(child, &self.ctx.root)).collect() } else { vec![] };
+        (ScanItem::Directory(ResourceSet::new(dir, num_subs)), children)
+    }
+
+    /// Check a file against the name filters, then read its metadata for the other filters
+    /// and finally run any rule script, which may also set the file's destination
+    fn scan_file(&self, file: &FileEntry) -> Option<ScanItem> {
+        self.progress.add_examined();
+        let mut resource = ResourceRow::new(file, FileMeta::default(), self.criteria.time_field);
+        if !self.criteria.matches_name(&resource, self.ctx) {
+            return None;
+        }
+        let meta = match self.fs.metadata(file.path()) {
+            Ok(meta) => meta,
+            Err(error) => {
+                self.errors.add(FileError::from_io(file.path(), FileOperation::Scan, &error));
+                return None;
+            }
+        };
+        resource.set_meta(meta, self.criteria.time_field);
+        if !self.criteria.matches_metadata(&resource, self.ctx) {
+            return None;
+        }
+        if let Some(script) = &self.criteria.script {
+            match script.evaluate(&resource, self.ctx) {
+                Ok(ScriptOutcome::Match(destination)) => resource.destination = destination,
+                Ok(ScriptOutcome::Skip) => return None,
+                Err(message) => {
+                    self.errors.add(FileError { path: file.path().to_path_buf(), operation: FileOperation::Scan, kind: FileErrorKind::Script, message });
+                    return None;
+                }
             }
         }
+        self.progress.add_matched(resource.size());
+        Some(ScanItem::File(resource))
+    }
+
+    /// The ancestors of a subdirectory's children, or None if it is a symlink back to an ancestor
+    fn child_ancestors(&self, child: &FileEntry, ancestors: &[PathBuf]) -> Option<Vec<PathBuf>> {
+        let parent_path = ancestors.last().cloned().unwrap_or_default();
+        let real_path = if child.path_is_symlink() {
+            self.fs.canonicalize(child.path()).unwrap_or(child.path().to_path_buf())
+        } else {
+            parent_path.join(child.file_name())
+        };
+        if ancestors.contains(&real_path) {
+            let message = format!("File system loop found: {} points to an ancestor {}", child.path().display(), real_path.display());
+            self.errors.add(FileError { path: child.path().to_path_buf(), operation: FileOperation::Scan, kind: FileErrorKind::SymlinkLoop, message });
+            return None;
+        }
+        let mut child_ancestors = ancestors.to_vec();
+        child_ancestors.push(real_path);
+        Some(child_ancestors)
+    }
+
+    /// Check each listed file, adding the directory of a matched file the first time it is needed
+    fn scan_list(&self, root: &FileEntry, paths: &[PathBuf], add: &mut dyn FnMut(ScanItem)) {
+        add(ScanItem::Directory(ResourceSet::new(root, 0)));
+        let mut dirs: HashSet<PathBuf> = HashSet::from([root.path().to_path_buf()]);
+        for listed_path in paths {
+            if is_interrupted() {
+                break;
+            }
+            let path = match self.path_below_root(listed_path, root.path()) {
+                Some(path) => path,
+                None => {
+                    self.errors.add(FileError::outside_root(listed_path, root.path()));
+                    continue;
+                }
+            };
+            let path = path.as_path();
+            let entry = match self.fs.entry(path) {
+                Ok(entry) => entry,
+                Err(error) => {
+                    self.errors.add(FileError::from_io(path, FileOperation::Scan, &error));
+                    continue;
+                }
+            };
+            if entry.is_dir() {
+                continue;
+            }
+            let dir = path.parent().unwrap_or(root.path());
+            let dir_names: Vec<&OsStr> = dir.strip_prefix(root.path()).map(|relative_dir| relative_dir.iter().collect()).unwrap_or_default();
+            let mut dir_path = root.path().to_path_buf();
+            let is_walkable = dir_names.iter().enumerate().all(|(index, name)| {
+                dir_path.push(name);
+                self.criteria.is_walkable(&FileEntry::new(dir_path.clone(), index + 1, true, false), &self.ctx.root)
+            });
+            if !is_walkable {
+                continue;
+            }
+            let file = FileEntry::new(path.to_path_buf(), dir_names.len() + 1, false, entry.path_is_symlink());
+            if let Some(item) = self.scan_file(&file) {
+                if dirs.insert(dir.to_path_buf()) {
+                    add(ScanItem::Directory(ResourceSet::new(&FileEntry::new(dir.to_path_buf(), dir_names.len(), true, false), 0)));
+                }
+                add(item);
+            }
+        }
+    }
+
+    /// The listed path if it is below the root, otherwise with its directory resolved, e.g. when the root
+    /// was given through a symlink. Paths with `..` are resolved first, so they cannot climb out of the root.
+    fn path_below_root(&self, path: &Path, root: &Path) -> Option<PathBuf> {
+        let has_parent_dirs = path.components().any(|component| component == Component::ParentDir);
+        if !has_parent_dirs && path.strip_prefix(root).map(|relative_path| relative_path.as_os_str().len() > 0).unwrap_or(false) {
+            return Some(path.to_path_buf());
+        }
+        let resolved = self.fs.canonicalize(path.parent()?).ok()?.join(path.file_name()?);
+        if resolved.strip_prefix(root).map(|relative_path| relative_path.as_os_str().len() > 0).unwrap_or(false) {
+            Some(resolved)
+        } else {
+            None
+        }
+    }
+
+    /// Walk a directory depth first, passing each directory and matched file to add as it is found
+    fn walk(&self, dir: &FileEntry, ancestors: &[PathBuf], add: &mut dyn FnMut(ScanItem)) {
+        let (item, children) = self.open_dir(dir);
+        add(item);
+        for child in children {
+            if is_interrupted() {
+                break;
+            }
+            if child.is_dir() {
+                if let Some(child_ancestors) = self.child_ancestors(&child, ancestors) {
+                    self.walk(&child, &child_ancestors, add);
+                }
+            } else if let Some(item) = self.scan_file(&child) {
+                add(item);
+            }
+        }
+    }
+
+    /// Walk subdirectories on the rayon thread pool. Results are merged in the same order
+    /// as the sequential walk, so listings do not depend on the number of threads.
+    fn walk_parallel(&self, dir: &FileEntry, ancestors: &[PathBuf]) -> Vec<ScanItem> {
+        let (item, children) = self.open_dir(dir);
+        let child_items: Vec<Vec<ScanItem>> = children.par_iter().map(|child| {
+            if is_interrupted() {
+                vec![]
+            } else if child.is_dir() {
+                self.child_ancestors(child, ancestors).map(|child_ancestors| self.walk_parallel(child, &child_ancestors)).unwrap_or_default()
+            } else {
+                self.scan_file(child).into_iter().collect()
+            }
+        }).collect();
+        std::iter::once(item).chain(child_items.into_iter().flatten()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+    use crate::file_system::{FsOperation, MemoryFileSystem};
+    use crate::utils::current_timestamp;
+
+    const DAY: u64 = 86400;
+
+    fn sample_fs() -> Arc<MemoryFileSystem> {
+        let now = current_timestamp() as u64;
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file("/data/new.log", &[0; 100], now - DAY);
+        fs.add_file("/data/old.log", &[0; 2000], now - 400 * DAY);
+        fs.add_file("/data/cache/old.tmp", &[0; 5000], now - 400 * DAY);
+        fs.add_file("/data/archive/2020/older.log", &[0; 3000], now - 1000 * DAY);
+        fs
+    }
+
+    fn scan_paths(builder: CriteriaBuilder, fs: Arc<MemoryFileSystem>) -> (Vec<String>, ResourceTree) {
+        let tree = Scanner::with_file_system(builder.build().unwrap(), fs).scan("/data");
+        let paths = tree.action_rows().map(|row| row.relative_path(tree.root())).collect();
+        (paths, tree)
+    }
+
+    #[test]
+    fn test_scan_by_age_size_and_excluded_dirs() {
+        let (paths, _) = scan_paths(CriteriaBuilder::new().before("1y"), sample_fs());
+        assert_eq!(paths, vec!["old.log", "archive/2020/older.log", "cache/old.tmp"]);
+        let (paths, _) = scan_paths(CriteriaBuilder::new().after("1y").size("1K"), sample_fs());
+        assert_eq!(paths, Vec::<String>::new());
+        let (paths, _) = scan_paths(CriteriaBuilder::new().size("1K-4K").exclude_dirs("cache").threads(4), sample_fs());
+        assert_eq!(paths, vec!["old.log", "archive/2020/older.log"]);
+    }
+
+    #[test]
+    fn test_parallel_walk_matches_sequential_walk() {
+        let fs = sample_fs();
+        let now = current_timestamp() as u64;
+        for (index, dir) in ["b", "a", "c/d", "c/e/f", "c"].iter().enumerate() {
+            for file in ["z.txt", "m.log", "a.dat"] {
+                fs.add_file(format!("/data/{}/{}", dir, file), &vec![0; 10 * (index + 1)], now - (index as u64 + 1) * DAY);
+            }
+        }
+        let layout = |tree: &ResourceTree| -> Vec<(String, usize, usize, u64)> {
+            tree.directories.iter().map(|dir| (dir.path_display(tree.root()), dir.count(), dir.num_subs, dir.size())).collect()
+        };
+        let (sequential, sequential_tree) = scan_paths(CriteriaBuilder::new().threads(1), fs.clone());
+        let (parallel, parallel_tree) = scan_paths(CriteriaBuilder::new().threads(4), fs);
+        assert_eq!(parallel, sequential);
+        assert_eq!(layout(&parallel_tree), layout(&sequential_tree));
+        assert_eq!((parallel_tree.num_files(), parallel_tree.size()), (sequential_tree.num_files(), sequential_tree.size()));
+        assert_eq!(sequential.len(), 19);
+    }
+
+    #[test]
+    fn test_scan_max_depth() {
+        let (paths, tree) = scan_paths(CriteriaBuilder::new().max_depth(1), sample_fs());
+        assert_eq!(paths, vec!["new.log", "old.log"]);
+        assert_eq!(tree.directories[0].num_subs, 2);
+    }
+
+    #[test]
+    fn test_scan_errors() {
+        let fs = sample_fs();
+        fs.fail(FsOperation::ReadDir, "/data/archive", ErrorKind::PermissionDenied);
+        fs.fail(FsOperation::Metadata, "/data/old.log", ErrorKind::NotFound);
+        let (paths, tree) = scan_paths(CriteriaBuilder::new(), fs);
+        assert_eq!(paths, vec!["new.log", "cache/old.tmp"]);
+        let kinds: Vec<(&Path, FileErrorKind)> = tree.errors.iter().map(|error| (error.path.as_path(), error.kind)).collect();
+        assert_eq!(kinds, vec![(Path::new("/data/archive"), FileErrorKind::PermissionDenied), (Path::new("/data/old.log"), FileErrorKind::NotFound)]);
+    }
+
+    #[test]
+    fn test_scan_skips_unreadable_entries() {
+        let fs = sample_fs();
+        fs.fail(FsOperation::ReadEntry, "/data/cache", ErrorKind::PermissionDenied);
+        let (paths, tree) = scan_paths(CriteriaBuilder::new(), fs);
+        assert_eq!(paths, vec!["new.log", "old.log", "archive/2020/older.log"]);
+        let kinds: Vec<(&Path, FileErrorKind)> = tree.errors.iter().map(|error| (error.path.as_path(), error.kind)).collect();
+        assert_eq!(kinds, vec![(Path::new("/data/cache"), FileErrorKind::PermissionDenied)]);
+    }
+
+    #[test]
+    fn test_scan_list() {
+        let fs = sample_fs();
+        let list: Vec<PathBuf> = ["/data/archive/2020/older.log", "/data/cache/old.tmp", "/data/archive", "/data/gone.log", "/data/new.log", "/etc/hostname", "/data/../etc/hostname"].iter().map(PathBuf::from).collect();
+        let criteria = CriteriaBuilder::new().exclude_dirs("cache").max_depth(1).build().unwrap();
+        let tree = Scanner::with_file_system(criteria, fs).scan_list("/data", &list);
+        let paths: Vec<String> = tree.action_rows().map(|row| row.relative_path(tree.root())).collect();
+        // listed files below the depth limit are kept, but not those in excluded directories
+        assert_eq!(paths, vec!["new.log", "archive/2020/older.log"]);
+        let kinds: Vec<FileErrorKind> = tree.errors.iter().map(|error| error.kind).collect();
+        assert_eq!(kinds, vec![FileErrorKind::NotFound, FileErrorKind::OutsideRoot, FileErrorKind::OutsideRoot]);
     }
-    resource_tree.show(details);
-    resource_tree
 }
@@ -0,0 +1,207 @@
+//! Match rules written in Rhai and loaded with --script.
+//!
+//! The script defines `fn rule(file)`, called for each file that passed the other filters,
+//! with a map of `name`, `stem`, `ext`, `size`, `mtime`, `age_days`, `depth`, `path` and `dir`.
+//! It returns `true` to match or `false` to skip the file, or a destination for copy and move:
+//! a path relative to the target, or a map such as `#{ name: "new.jpg" }` or `#{ target: "raw/a.raw" }`.
+//! `exists(path)` checks a path relative to the scanned directory.
+//! Scripts cannot read files, import modules or run for more than a set number of operations.
+
+use std::fs::read_to_string;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use rhai::{Dynamic, Engine, Map, NativeCallContext, Scope, AST, CallFnOptions};
+use rhai::module_resolvers::DummyModuleResolver;
+use crate::errors::ArgError;
+use crate::file_system::FileSystem;
+use crate::filters::ScanContext;
+use crate::resource_row::ResourceRow;
+
+const RULE_FN: &str = "rule";
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Result of the rule for one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptOutcome {
+  Skip,
+  /// Matched, with an optional destination relative to the copy or move target
+  Match(Option<String>),
+}
+
+/// Scanned file system and directory, available to native functions during a rule call
+#[derive(Debug, Clone)]
+struct ScriptTag {
+  fs: Arc<dyn FileSystem>,
+  root: PathBuf,
+}
+
+/// A compiled rule script with its sandboxed engine
+#[derive(Debug)]
+pub struct Script {
+  pub path: String,
+  engine: Engine,
+  ast: AST,
+}
+
+fn sandboxed_engine() -> Engine {
+  let mut engine = Engine::new();
+  engine.set_module_resolver(DummyModuleResolver::new());
+  engine.disable_symbol("eval");
+  engine.set_max_operations(MAX_OPERATIONS);
+  engine.set_max_call_levels(32);
+  engine.set_max_expr_depths(64, 32);
+  engine.set_max_string_size(64 * 1024);
+  engine.set_max_array_size(10_000);
+  engine.set_max_map_size(10_000);
+  engine.on_print(|text| eprintln!("{}", text));
+  engine.on_debug(|text, _, _| eprintln!("{}", text));
+  engine.register_fn("exists", |context: NativeCallContext, path: &str| -> bool {
+    let tag = context.tag().and_then(|tag| tag.clone().try_cast::<ScriptTag>());
+    match (tag, safe_relative_path(path)) {
+      (Some(tag), Some(relative_path)) => tag.fs.exists(&tag.root.join(relative_path)),
+      _ => false,
+    }
+  });
+  engine
+}
+
+/// A relative path without parent or root components, so it cannot leave its base directory
+fn safe_relative_path(path: &str) -> Option<PathBuf> {
+  let relative_path = PathBuf::from(path.trim_start_matches('/'));
+  let is_safe = relative_path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+  if is_safe && relative_path.as_os_str().len() > 0 { Some(relative_path) } else { None }
+}
+
+fn file_map(row: &ResourceRow, ctx: &ScanContext) -> Map {
+  let relative_path = row.relative_path(&ctx.root);
+  let dir = Path::new(&relative_path).parent().map(|dir| dir.to_string_lossy().to_string()).unwrap_or_default();
+  let stem = row.path_ref().file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+  let mut file = Map::new();
+  file.insert("name".into(), row.file_name().into());
+  file.insert("stem".into(), stem.into());
+  file.insert("ext".into(), row.extension.clone().into());
+  file.insert("size".into(), (row.size() as i64).into());
+  file.insert("mtime".into(), (row.meta.modified as i64).into());
+  file.insert("age_days".into(), ctx.days_old(row).into());
+  file.insert("depth".into(), (row.depth() as i64).into());
+  file.insert("path".into(), relative_path.into());
+  file.insert("dir".into(), dir.into());
+  file
+}
+
+/// Check a destination returned by the rule and resolve a new name within the file's directory
+fn destination(value: Dynamic, dir: &str, is_name: bool) -> Result<String, String> {
+  let text = value.into_string().map_err(|type_name| format!("expected a string destination, not {}", type_name))?;
+  let path = if is_name && dir.len() > 0 { format!("{}/{}", dir, text) } else { text };
+  match safe_relative_path(&path) {
+    Some(_) => Ok(path),
+    None => Err(format!("the destination '{}' must be a path inside the target", path)),
+  }
+}
+
+fn to_outcome(value: Dynamic, dir: &str) -> Result<ScriptOutcome, String> {
+  if value.is_unit() {
+    Ok(ScriptOutcome::Skip)
+  } else if let Ok(is_match) = value.as_bool() {
+    Ok(if is_match { ScriptOutcome::Match(None) } else { ScriptOutcome::Skip })
+  } else if value.is_string() {
+    destination(value, dir, false).map(|path| ScriptOutcome::Match(Some(path)))
+  } else if value.is_map() {
+    let mut map = value.cast::<Map>();
+    if map.get("skip").and_then(|skip| skip.as_bool().ok()).unwrap_or(false) {
+      Ok(ScriptOutcome::Skip)
+    } else if let Some(target) = map.remove("target") {
+      destination(target, dir, false).map(|path| ScriptOutcome::Match(Some(path)))
+    } else if let Some(name) = map.remove("name") {
+      destination(name, dir, true).map(|path| ScriptOutcome::Match(Some(path)))
+    } else {
+      Ok(ScriptOutcome::Match(None))
+    }
+  } else {
+    Err(format!("the rule returned {}, expected a bool, a string or a map", value.type_name()))
+  }
+}
+
+impl Script {
+  /// Read and compile a script file
+  pub fn load(path: &str) -> Result<Script, ArgError> {
+    let source = read_to_string(path).map_err(|error| ArgError::InvalidScript(path.to_owned(), error.to_string()))?;
+    Script::from_source(path, &source)
+  }
+
+  /// Compile a script, which must define a rule function with one parameter
+  pub fn from_source(path: &str, source: &str) -> Result<Script, ArgError> {
+    let engine = sandboxed_engine();
+    let ast = engine.compile(source).map_err(|error| ArgError::InvalidScript(path.to_owned(), error.to_string()))?;
+    if !ast.iter_functions().any(|function| function.name == RULE_FN && function.params.len() == 1) {
+      return Err(ArgError::InvalidScript(path.to_owned(), format!("it must define fn {}(file)", RULE_FN)));
+    }
+    Ok(Script { path: path.to_owned(), engine, ast })
+  }
+
+  /// Call the rule for a matched file. Script errors are returned as messages.
+  pub fn evaluate(&self, row: &ResourceRow, ctx: &ScanContext) -> Result<ScriptOutcome, String> {
+    let file = file_map(row, ctx);
+    let dir = file.get("dir").map(|dir| dir.to_string()).unwrap_or_default();
+    let root = ctx.root.as_ref().map(|root| root.path().to_path_buf()).unwrap_or_default();
+    let options = CallFnOptions::new().eval_ast(false).with_tag(ScriptTag { fs: ctx.fs.clone(), root });
+    let value = self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, RULE_FN, (file,))
+      .map_err(|error| error.to_string())?;
+    to_outcome(value, &dir)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::file_system::{FileEntry, MemoryFileSystem};
+  use crate::resource_row::sample_rows;
+
+  fn sample_files(fs: &Arc<MemoryFileSystem>, paths: &[&str]) -> Vec<ResourceRow> {
+    for path in paths {
+      fs.add_file(path, b"img", 100);
+    }
+    sample_rows(paths, |path| fs.metadata(path).unwrap())
+  }
+
+  fn sample_ctx(fs: &Arc<MemoryFileSystem>) -> ScanContext {
+    ScanContext::new(Some(FileEntry::new(PathBuf::from("/photos"), 0, true, false)), fs.clone())
+  }
+
+  #[test]
+  fn test_rule_outcomes() {
+    let fs = Arc::new(MemoryFileSystem::new());
+    let rows = sample_files(&fs, &["/photos/a.jpg", "/photos/a.raw", "/photos/2024/b.jpg"]);
+    let ctx = sample_ctx(&fs);
+    let script = Script::from_source("rules.rhai", r#"
+      fn rule(file) {
+        if file.ext == "jpg" && exists(file.dir + "/" + file.stem + ".raw") {
+          return false;
+        }
+        if file.ext == "raw" {
+          return "raw/" + file.name;
+        }
+        #{ name: file.stem + "_" + file.size + "." + file.ext }
+      }
+    "#).unwrap();
+    let outcomes: Vec<ScriptOutcome> = rows.iter().map(|row| script.evaluate(row, &ctx).unwrap()).collect();
+    assert_eq!(outcomes, vec![
+      ScriptOutcome::Skip,
+      ScriptOutcome::Match(Some("raw/a.raw".to_owned())),
+      ScriptOutcome::Match(Some("2024/b_3.jpg".to_owned())),
+    ]);
+  }
+
+  #[test]
+  fn test_script_errors() {
+    assert!(matches!(Script::from_source("rules.rhai", "fn keep(file) { true }"), Err(ArgError::InvalidScript(_, _))));
+    assert!(matches!(Script::from_source("rules.rhai", "fn rule(file) { true"), Err(ArgError::InvalidScript(_, _))));
+    let fs = Arc::new(MemoryFileSystem::new());
+    let rows = sample_files(&fs, &["/photos/a.jpg"]);
+    let ctx = sample_ctx(&fs);
+    let escape = Script::from_source("rules.rhai", r#"fn rule(file) { "../" + file.name }"#).unwrap();
+    assert!(escape.evaluate(&rows[0], &ctx).is_err());
+    let endless = Script::from_source("rules.rhai", "fn rule(file) { loop { } }").unwrap();
+    assert!(endless.evaluate(&rows[0], &ctx).unwrap_err().contains("Too many operations"));
+  }
+}
@@ -1,20 +1,34 @@
 use std::io::Write;
 use clap::Parser;
-use crate::args::Args;
-use color_print::cprintln;
+use crate::args::{Args, Command};
+use color_print::{cprintln, cformat};
 use crate::utils::pluralize_64;
 
-use crate::path_info::PathInfo;
 use crate::resource_row::*;
 use crate::criteria::*;
-use crate::run::*;
+use crate::run::Scanner;
+use crate::action::{Action, ActionTally};
+use crate::interrupt::{install_handler, exit_if_interrupted, set_awaiting_input};
+use crate::job::Job;
+use crate::file_system::OsFileSystem;
+use crate::transfer::{transfer_files, close_job};
+use crate::progress::Progress;
+use crate::errors::{ACTION_FAILED_EXIT_CODE, FileError, show_errors, sorted_by_path};
+use crate::report::{roots_json, tree_json, write_paths};
+use crate::hooks::{Hook, HookPhase, RunSummary};
+use crate::interrupt::is_interrupted;
+use crate::path_info::root_folder_names;
+use std::path::{Path, PathBuf};
 
 /// Called to confirm risky operations such as move or delete
 pub fn action_prompt(text: &str) -> bool {
   let mut line = String::new();
-  print!("{} (Y/n)", text);
-  std::io::stdout().flush().unwrap();
+  // on stderr, so prompts do not mix with the listing or JSON report on stdout
+  eprint!("{} (Y/n)", text);
+  std::io::stderr().flush().unwrap();
+  set_awaiting_input(true);
   std::io::stdin().read_line(&mut line).expect("Error: Could not read a line");
+  set_awaiting_input(false);
 
   match line.trim().to_lowercase().as_str() {
       "y" | "yes" => true,
@@ -22,40 +36,281 @@ pub fn action_prompt(text: &str) -> bool {
   }
 }
 
+/// Messages about the action go to stderr when stdout carries the JSON report or path list
+fn notice(criteria: &Criteria, text: &str) {
+  if criteria.machine_output() {
+    eprintln!("{}", text);
+  } else {
+    println!("{}", text);
+  }
+}
+
+/// Matched files across all scanned roots
+fn num_files(trees: &[ResourceTree]) -> usize {
+  trees.iter().map(|tree| tree.num_files()).sum()
+}
+
+/// Show how to continue a copy or move job that did not complete
+fn show_resume_hint(job_file: &Option<PathBuf>) {
+  if let Some(path) = job_file {
+    eprintln!("{}", cformat!("Resume with <yellow>fileorg resume {}</yellow>", path.to_string_lossy()));
+  }
+}
+
+/// Apply the confirmed action to the matched files of each root and show the combined summary
+fn apply_action(trees: &mut [ResourceTree], action: &Option<Action>, criteria: &Criteria) {
+  if let Some(action) = action {
+    let mut tally = ActionTally::default();
+    for resource_tree in trees.iter_mut() {
+      tally.merge(&action.apply(resource_tree, &criteria.transfer));
+    }
+    if criteria.path_output.is_some() {
+      eprintln!("{}", tally.to_text(action));
+    } else if !criteria.json {
+      tally.show(action);
+    }
+    for resource_tree in trees.iter() {
+      show_resume_hint(&resource_tree.job_file);
+    }
+  }
+}
+
+/// Show the errors, on stderr after a path list, or the JSON report, then exit with the interrupted or failed status if needed
+fn finish_run(trees: &[ResourceTree], criteria: &Criteria) {
+  let errors: Vec<FileError> = trees.iter().flat_map(|tree| tree.errors.iter().cloned()).collect();
+  if criteria.json {
+    let report = if trees.len() == 1 { tree_json(&trees[0], criteria) } else { roots_json(trees, criteria) };
+    println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+  } else if criteria.path_output.is_some() {
+    for error in sorted_by_path(&errors) {
+      eprintln!("{}", cformat!("<red>Error: {}</red>", error));
+    }
+  } else {
+    show_errors(&errors);
+  }
+  exit_if_interrupted();
+  if trees.iter().any(|tree| tree.num_action_errors() > 0) {
+    std::process::exit(ACTION_FAILED_EXIT_CODE);
+  }
+}
+
+/// Run a hook if given. If it fails, the reason is shown and false is returned.
+fn run_hook(hook: &Option<Hook>, summary: &RunSummary, criteria: &Criteria) -> bool {
+  if let Some(hook) = hook {
+    if let Err(error) = hook.run(summary, criteria.machine_output()) {
+      let skipped = if hook.phase == HookPhase::Before { ", the action was skipped" } else { "" };
+      eprintln!("{}", cformat!("<red>Error: the {} hook failed: {}{}</red>", hook.phase.as_str(), error, skipped));
+      return false;
+    }
+  }
+  true
+}
+
+/// Continue a copy or move job from its state file, skipping files already done.
+/// Files transferred before the job stopped but not yet recorded are verified and marked as done.
+fn resume_job(job_file: &str, criteria: &Criteria) {
+  let mut job = match Job::load(Path::new(job_file)) {
+    Ok(job) => job,
+    Err(error) => {
+      eprintln!("{}", cformat!("<red>Error: could not read the job file {}: {}</red>", job_file, error));
+      std::process::exit(1);
+    }
+  };
+  install_handler();
+  let mut num_verified = 0;
+  for index in job.pending() {
+    if job.entries[index].is_complete(job.move_mode) && job.mark_done(index).is_ok() {
+      job.entries[index].done = true;
+      num_verified += 1;
+    }
+  }
+  let pending = job.pending();
+  let num_done = job.entries.len() - pending.len();
+  cprintln!("Resuming job: <green>{}</green> of {} {} already done ({} verified), {} left", num_done, job.entries.len(), pluralize_64("file", "s", job.entries.len() as u64), num_verified, pending.len());
+  let total_bytes = pending.iter().map(|index| job.entries[*index].size).sum();
+  let action = if job.move_mode { Action::Move(job.target.clone()) } else { Action::Copy(job.target.clone()) };
+  let progress = Progress::action(criteria.show_progress, action.mode().past_word(), pending.len() as u64, total_bytes);
+  let mut summary = transfer_files(&OsFileSystem, &job, &criteria.transfer, &progress);
+  progress.finish();
+  summary.tally().show(&action);
+  close_job(&job, &mut summary);
+  show_resume_hint(&summary.job_file);
+  show_errors(&[summary.failures.clone(), summary.job_errors.clone()].concat());
+  exit_if_interrupted();
+  if summary.num_failed() > 0 {
+    std::process::exit(ACTION_FAILED_EXIT_CODE);
+  }
+}
+
+/// Apply the selected action once confirmed. When streaming, it is applied during the scan instead.
+fn apply_action_phase(trees: &mut [ResourceTree], criteria: &Criteria) {
+  let selected_action = Action::from_criteria(criteria);
+  if criteria.delete_with_prompt() {
+    let num_matched_files = num_files(trees);
+    if num_matched_files > 0 {
+      let file_word = pluralize_64("file", "s", num_matched_files as u64);
+      if action_prompt(format!("Are you sure you want to delete the {} above {}?", num_matched_files, file_word).as_str()) {
+        apply_action(trees, &Some(Action::Delete), criteria);
+      } else {
+        notice(criteria, &cformat!("<red>Not deleted</red>"));
+      }
+    } else {
+      notice(criteria, &cformat!("<red>No matched files to delete</red>"));
+    }
+  } else if criteria.permissions_mode() {
+    let num_matched_files = num_files(trees);
+    if num_matched_files > 0 {
+      let file_word = pluralize_64("file", "s", num_matched_files as u64);
+      if criteria.action.permissions_confirmed() || action_prompt(format!("Are you sure you want to change the permissions of the {} above {}?", num_matched_files, file_word).as_str()) {
+        apply_action(trees, &selected_action, criteria);
+      } else {
+        notice(criteria, &criteria.action.to_not_past());
+      }
+    } else {
+      notice(criteria, &cformat!("<red>No matched files to change</red>"));
+    }
+  } else if criteria.may_copy() || criteria.may_move() || criteria.exec_mode() {
+    apply_action(trees, &selected_action, criteria);
+  } else if criteria.move_or_copy_mode() && !criteria.has_target() {
+    if action_prompt(&format!("The directory {} does not exist. Do you want to create it", criteria.target_ref() )) {
+      if criteria.create_target() {
+        apply_action(trees, &selected_action, criteria);
+      } else {
+        notice(criteria, &cformat!("<red>New target directory ({}) could not be created</red>", criteria.target_ref()));
+      }
+    } else {
+      notice(criteria, &criteria.action.to_not_past());
+    }
+  }
+}
+
 /// Start the command line prompt and parse the core options
 pub fn init() {
   let args = Args::parse();
-  let path_info = PathInfo::new_from_args(&args);
-  let mut criteria = Criteria::new(&args, path_info.pattern);
-  if path_info.exists {
-      let details = DetailLevel::new(&args.list, &args.groups, &args.void);
-      let resource_tree = scan_directory(&path_info.canonical, &details, &mut criteria);
-      criteria.show();
-      if criteria.delete_with_prompt() {
-          let num_matched_files = resource_tree.num_files();
-          if num_matched_files > 0 {
-              let file_word = pluralize_64("file", "s", num_matched_files as u64);
-              if action_prompt(format!("Are you sure you want to delete the {} above {}?", num_matched_files, file_word).as_str()) {
-                  resource_tree.run(ActionMode::Delete, None);
-              } else {
-                  cprintln!("<red>Not deleted</red>");
-              }
-          } else {
-              cprintln!("<red>No matched files to delete</red>");
-          }
-      } else if criteria.move_or_copy_mode() && !criteria.has_target() {
-          if action_prompt(&format!("The directory {} does not exist. Do you want to create it", criteria.target_ref() )) {
-              if criteria.create_target() {
-                  resource_tree.run(criteria.action, Some(criteria.target_info().path));
-              } else {
-                   cprintln!("<red>New target directory ({}) could be created</red>", criteria.target_ref());
-              }
-          } else {
-              cprintln!("{}", criteria.action.to_not_past());
-          }
+  if let Some(Command::Resume { job_file }) = &args.command {
+    match args.resume_criteria() {
+      Ok(criteria) => resume_job(job_file, &criteria),
+      Err(error) => {
+        eprintln!("{}", cformat!("<red>Error: {}</red>", error));
+        std::process::exit(2);
       }
-  } else {
-     cprintln!("The target directory <red>{}</red> does not exist", path_info.input); 
+    }
+    return;
+  }
+  let roots = args.path_infos();
+  let criteria = match args.to_criteria(roots[0].pattern.clone()) {
+    Ok(criteria) => criteria,
+    Err(error) => {
+      eprintln!("{}", cformat!("<red>Error: {}</red>", error));
+      std::process::exit(2);
+    }
+  };
+  let details = match args.detail_level() {
+    Ok(details) => details,
+    Err(error) => {
+      eprintln!("{}", cformat!("<red>Error: {}</red>", error));
+      std::process::exit(2);
+    }
+  };
+  let (before_hook, after_hook) = match args.hooks() {
+    Ok(hooks) => hooks,
+    Err(error) => {
+      eprintln!("{}", cformat!("<red>Error: {}</red>", error));
+      std::process::exit(2);
+    }
+  };
+  let path_list = match args.path_list() {
+    Some(Ok(paths)) => Some(paths),
+    Some(Err(error)) => {
+      let source = args.from_file.clone().unwrap_or("stdin".to_owned());
+      eprintln!("{}", cformat!("<red>Error: could not read the path list from {}: {}</red>", source, error));
+      std::process::exit(2);
+    }
+    None => None,
+  };
+  if let Some(missing) = roots.iter().find(|root| !root.exists) {
+    cprintln!("The target directory <red>{}</red> does not exist", missing.input);
+    std::process::exit(1);
   }
-  
-}
\ No newline at end of file
+  install_handler();
+  // hooks see several roots as one colon-separated path
+  let hook_path = roots.iter().map(|root| root.canonical.clone()).collect::<Vec<String>>().join(":");
+  // files are copied or moved as they are found in streaming mode, so the target must exist first
+  if criteria.stream && criteria.move_or_copy_mode() && !criteria.has_target() {
+    if !action_prompt(&format!("The directory {} does not exist. Do you want to create it", criteria.target_ref())) {
+      cprintln!("{}", criteria.action.to_not_past());
+      return;
+    }
+    if !criteria.create_target() {
+      cprintln!("<red>New target directory ({}) could not be created</red>", criteria.target_ref());
+      return;
+    }
+  }
+  // forced deletes and streamed actions are applied as files are found, so the before hook runs ahead of the scan
+  if criteria.acts_during_scan() && !run_hook(&before_hook, &RunSummary::new(HookPhase::Before, &hook_path, &criteria, &[]), &criteria) {
+    std::process::exit(ACTION_FAILED_EXIT_CODE);
+  }
+  // each root is copied or moved to its own subfolder of the target
+  let root_paths: Vec<&Path> = roots.iter().map(|root| Path::new(root.canonical.as_str())).collect();
+  let folder_names = if roots.len() > 1 { root_folder_names(&root_paths) } else { vec![] };
+  let mut trees: Vec<ResourceTree> = vec![];
+  let mut scanned_criteria = criteria.clone();
+  for (index, root) in roots.iter().enumerate() {
+    if is_interrupted() {
+      break;
+    }
+    let mut scanner = Scanner::new(criteria.clone());
+    if let Some(folder) = folder_names.get(index) {
+      scanner = scanner.with_target_folder(folder);
+    }
+    let resource_tree = match &path_list {
+      Some(paths) if roots.len() > 1 => {
+        // paths below no root are passed to the first, which reports them as outside the root
+        let is_listed = |path: &PathBuf| path.starts_with(&root.canonical) || (index == 0 && !roots.iter().any(|other| path.starts_with(&other.canonical)));
+        let root_list: Vec<PathBuf> = paths.iter().filter(|path| is_listed(path)).cloned().collect();
+        scanner.scan_list(&root.canonical, &root_list)
+      },
+      Some(paths) => scanner.scan_list(&root.canonical, paths),
+      None => scanner.scan(&root.canonical),
+    };
+    scanned_criteria = scanner.criteria;
+    trees.push(resource_tree);
+  }
+  let criteria = scanned_criteria;
+  if let Some(path_output) = criteria.path_output {
+    for resource_tree in &trees {
+      // a closed pipe, e.g. into head, just ends the list
+      let _ = write_paths(resource_tree, path_output, &mut std::io::stdout().lock());
+      if let (Some(tally), Some(action)) = (resource_tree.tally, criteria.scan_action()) {
+        eprintln!("{}", tally.to_text(&action));
+      }
+    }
+  } else if !criteria.json {
+    for resource_tree in &trees {
+      resource_tree.show(&details);
+      if let (Some(tally), Some(action)) = (resource_tree.tally, criteria.scan_action()) {
+        tally.show(&action);
+      }
+    }
+    if trees.len() > 1 {
+      show_combined_overview(&trees);
+    }
+    criteria.show();
+  }
+  let mut hook_failed = false;
+  let mut in_action_phase = criteria.acts_during_scan();
+  if !criteria.acts_during_scan() && !is_interrupted() {
+    hook_failed = !run_hook(&before_hook, &RunSummary::new(HookPhase::Before, &hook_path, &criteria, &trees), &criteria);
+    if !hook_failed {
+      in_action_phase = true;
+      apply_action_phase(&mut trees, &criteria);
+    }
+  }
+  if in_action_phase {
+    hook_failed = !run_hook(&after_hook, &RunSummary::new(HookPhase::After, &hook_path, &criteria, &trees), &criteria);
+  }
+  finish_run(&trees, &criteria);
+  if hook_failed {
+    std::process::exit(ACTION_FAILED_EXIT_CODE);
+  }
+}
@@ -0,0 +1,241 @@
+//! Copy or move matched files on a pool of worker threads, with an optional bandwidth limit
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use rayon::prelude::*;
+use crate::action::ActionTally;
+use crate::job::{Job, default_job_path};
+use crate::manage::transfer_file;
+use crate::progress::Progress;
+use crate::interrupt::is_interrupted;
+use crate::errors::{FileError, FileOperation};
+use crate::file_system::FileSystem;
+
+/// Worker pool size, bandwidth limit and job state file for copy and move actions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferOptions {
+  pub jobs: usize,
+  /// Maximum bytes per second across all workers
+  pub bwlimit: Option<u64>,
+  /// Save the job in a state file so an interrupted copy or move can be resumed
+  pub resumable: bool,
+  /// State file for resuming the job, by default in the target directory
+  pub job_file: Option<PathBuf>,
+}
+
+impl TransferOptions {
+  /// Options for one of several scanned roots, whose job file name is suffixed with the root folder name
+  pub fn in_folder(&self, folder: &str) -> TransferOptions {
+    let job_file = self.job_file.as_ref().map(|path| job_path_in_folder(path, folder));
+    TransferOptions { job_file, ..self.clone() }
+  }
+}
+
+fn job_path_in_folder(path: &Path, folder: &str) -> PathBuf {
+  let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+  let name = match path.extension() {
+    Some(extension) => format!("{}-{}.{}", stem, folder, extension.to_string_lossy()),
+    None => format!("{}-{}", stem, folder),
+  };
+  path.with_file_name(name)
+}
+
+impl Default for TransferOptions {
+  fn default() -> Self {
+    TransferOptions { jobs: 1, bwlimit: None, resumable: false, job_file: None }
+  }
+}
+
+/// Bytes budget shared by all workers. Each worker sleeps until the bytes written so far
+/// are within the limit since the transfer started.
+#[derive(Debug)]
+pub struct Throttle {
+  limit: u64,
+  start: Instant,
+  bytes: Mutex<u64>,
+}
+
+impl Throttle {
+  pub fn new(limit: u64) -> Self {
+    Throttle { limit, start: Instant::now(), bytes: Mutex::new(0) }
+  }
+
+  pub fn consume(&self, num_bytes: u64) {
+    let total = {
+      let mut bytes = self.bytes.lock().unwrap();
+      *bytes += num_bytes;
+      *bytes
+    };
+    let due = Duration::from_secs_f64(total as f64 / self.limit as f64);
+    let elapsed = self.start.elapsed();
+    if due > elapsed {
+      sleep(due - elapsed);
+    }
+  }
+}
+
+/// Totals reported once all workers have finished
+#[derive(Debug, Clone)]
+pub struct TransferSummary {
+  pub num: u64,
+  pub size: u64,
+  pub failures: Vec<FileError>,
+  /// Errors while writing or removing the job state file, which do not fail the transfer
+  pub job_errors: Vec<FileError>,
+  /// State file left behind to resume an incomplete job
+  pub job_file: Option<PathBuf>,
+  pub elapsed: Duration,
+}
+
+impl TransferSummary {
+  pub fn num_failed(&self) -> u64 {
+    self.failures.len() as u64
+  }
+
+  pub fn tally(&self) -> ActionTally {
+    ActionTally { num: self.num, size: self.size, num_failed: self.num_failed(), elapsed: self.elapsed }
+  }
+}
+
+/// Copy or move the files of a job that are not yet done, recording each completed file.
+/// Once interrupted, files not yet started are skipped.
+pub fn transfer_files(fs: &dyn FileSystem, job: &Job, options: &TransferOptions, progress: &Progress) -> TransferSummary {
+  let start = Instant::now();
+  let throttle = options.bwlimit.map(Throttle::new);
+  let pending = job.pending();
+  let operation = if job.move_mode { FileOperation::Move } else { FileOperation::Copy };
+  let transfer = || pending.par_iter().filter(|_| !is_interrupted()).map(|index| {
+    let entry = &job.entries[*index];
+    let result = transfer_file(fs, &entry.source, &entry.destination, job.move_mode, throttle.as_ref())
+      .map(|_| entry.size)
+      .map_err(|error| FileError::from_io(&entry.source, operation, &error));
+    // the file is in place even if it could not be recorded, so a resumed job verifies it instead
+    let job_error = match &result {
+      Ok(_) => job.mark_done(*index).err().map(|error| job_file_error(job, &error)),
+      Err(_) => None,
+    };
+    progress.add_matched(entry.size);
+    (result, job_error)
+  }).collect::<Vec<(Result<u64, FileError>, Option<FileError>)>>();
+  let results = match rayon::ThreadPoolBuilder::new().num_threads(options.jobs.max(1)).build() {
+    Ok(pool) => pool.install(transfer),
+    Err(_) => transfer(),
+  };
+  let mut summary = TransferSummary { num: 0, size: 0, failures: vec![], job_errors: vec![], job_file: None, elapsed: Duration::ZERO };
+  for (result, job_error) in results {
+    match result {
+      Ok(size) => {
+        summary.num += 1;
+        summary.size += size;
+      },
+      Err(failure) => summary.failures.push(failure),
+    }
+    // the same error is usually repeated for every file once the job file cannot be written
+    if let Some(error) = job_error.filter(|error| !summary.job_errors.contains(error)) {
+      summary.job_errors.push(error);
+    }
+  }
+  summary.elapsed = start.elapsed();
+  summary
+}
+
+fn job_file_error(job: &Job, error: &std::io::Error) -> FileError {
+  FileError::from_io(&job.path.clone().unwrap_or_default(), FileOperation::JobFile, error)
+}
+
+/// Remove the state file of a completed job, or keep it in the summary to resume it
+pub fn close_job(job: &Job, summary: &mut TransferSummary) {
+  let num_done = job.entries.len() - job.pending().len() + summary.num as usize;
+  match job.remove_if_complete(num_done) {
+    Ok(true) => (),
+    Ok(false) => summary.job_file = job.path.clone(),
+    Err(error) => summary.job_errors.push(job_file_error(job, &error)),
+  }
+}
+
+/// Persist a planned job on disk if resumable, then copy or move its files
+pub fn start_job(fs: &dyn FileSystem, mut job: Job, options: &TransferOptions, progress: &Progress) -> TransferSummary {
+  let mut job_errors = vec![];
+  if options.resumable && fs.is_on_disk() {
+    let job_path = options.job_file.clone().unwrap_or(default_job_path(&job.target));
+    if let Err(error) = job.persist(&job_path) {
+      job_errors.push(FileError::from_io(&job_path, FileOperation::JobFile, &error));
+    }
+  }
+  let mut summary = transfer_files(fs, &job, options, progress);
+  progress.finish();
+  summary.job_errors.splice(0..0, job_errors);
+  close_job(&job, &mut summary);
+  summary
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::file_system::OsFileSystem;
+  use crate::job::JobEntry;
+
+  #[test]
+  fn test_throttle_waits_for_budget() {
+    let throttle = Throttle::new(1000);
+    throttle.consume(100);
+    assert!(throttle.start.elapsed() >= Duration::from_millis(100));
+  }
+
+  fn job_files(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir).unwrap().flatten().map(|entry| entry.path()).filter(|path| path.to_string_lossy().contains(".fileorg-job-")).collect()
+  }
+
+  #[test]
+  fn test_job_file_only_when_resumable() {
+    let source = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    std::fs::write(source.path().join("a.txt"), "abc").unwrap();
+    let entries = || vec![
+      JobEntry::new(source.path().join("a.txt"), target.path().join("a.txt"), 3),
+      JobEntry::new(source.path().join("missing.txt"), target.path().join("missing.txt"), 5),
+    ];
+    let progress = Progress::action(false, "copied", 2, 8);
+    let summary = start_job(&OsFileSystem, Job::new(false, source.path(), target.path(), entries()), &TransferOptions::default(), &progress);
+    assert_eq!((summary.num, summary.num_failed()), (1, 1));
+    assert_eq!(summary.job_file, None);
+    assert!(job_files(target.path()).is_empty());
+
+    let options = TransferOptions { resumable: true, ..TransferOptions::default() };
+    let summary = start_job(&OsFileSystem, Job::new(false, source.path(), target.path(), entries()), &options, &progress);
+    assert!(summary.job_errors.is_empty());
+    assert_eq!(job_files(target.path()), summary.job_file.into_iter().collect::<Vec<PathBuf>>());
+  }
+
+  #[test]
+  fn test_files_count_as_transferred_when_the_job_file_fails() {
+    let source = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    let job_path = source.path().join("job.tsv");
+    std::fs::write(&job_path, "").unwrap();
+    let entries = ["a.txt", "b.txt"].iter().map(|name| {
+      std::fs::write(source.path().join(name), "abc").unwrap();
+      JobEntry::new(source.path().join(name), target.path().join(name), 3)
+    }).collect();
+    let mut job = Job::new(false, source.path(), target.path(), entries);
+    // opened read-only, so recording each completed file fails
+    job.record_to(&job_path, std::fs::File::open(&job_path).unwrap());
+    let summary = transfer_files(&OsFileSystem, &job, &TransferOptions::default(), &Progress::action(false, "copied", 2, 6));
+    assert_eq!((summary.num, summary.size, summary.num_failed()), (2, 6, 0));
+    assert_eq!(summary.job_errors.len(), 1);
+    assert_eq!(summary.job_errors[0].operation, FileOperation::JobFile);
+    assert!(target.path().join("b.txt").exists());
+  }
+
+  #[test]
+  fn test_job_file_per_root_folder() {
+    let options = TransferOptions { job_file: Some(PathBuf::from("/tmp/state.tsv")), ..TransferOptions::default() };
+    assert_eq!(options.in_folder("logs").job_file, Some(PathBuf::from("/tmp/state-logs.tsv")));
+    assert_eq!(options.in_folder("logs_2").job_file, Some(PathBuf::from("/tmp/state-logs_2.tsv")));
+    let options = TransferOptions { job_file: Some(PathBuf::from("state")), ..TransferOptions::default() };
+    assert_eq!(options.in_folder("docs").job_file, Some(PathBuf::from("state-docs")));
+    assert_eq!(TransferOptions::default().in_folder("docs").job_file, None);
+  }
+}
@@ -1,10 +1,9 @@
-use walkdir::DirEntry;
+//! Utility functions
+
+use crate::file_system::FileEntry;
 use std::path::{Path, MAIN_SEPARATOR};
-use std::time::UNIX_EPOCH;
 use size::Size;
-use std::fs;
-
-/// Utility functions
+use crate::errors::ArgError;
 
 pub(crate) fn is_full_path(path_arg: &str) -> bool {
     path_arg.starts_with("/") || path_arg.starts_with("~/")
@@ -19,7 +18,7 @@ pub(crate) fn smart_size(byte_size: u64) -> String {
   size.to_string()
 }
 
-pub(crate) fn extract_extension(file: &DirEntry) -> String {
+pub(crate) fn extract_extension(file: &FileEntry) -> String {
     let file_ext = file.path().extension();
     if let Some(ext) = file_ext { 
         if let Some(ext_str) = ext.to_str() {
@@ -32,12 +31,12 @@ pub(crate) fn extract_extension(file: &DirEntry) -> String {
     }
 }
 
-pub(crate) fn extract_extensions<'a>(ext_list: &str) -> Vec<String> {
+pub(crate) fn extract_extensions(ext_list: &str) -> Vec<String> {
   extract_from_list(ext_list)
 }
 
-pub(crate) fn extract_from_list<'a>(str_list: &str) -> Vec<String> {
-  if str_list.len() > 0 { str_list.split(",").into_iter().map(|s| s.to_owned()).collect() } else { vec![] }
+pub(crate) fn extract_from_list(str_list: &str) -> Vec<String> {
+  if str_list.len() > 0 { str_list.split(",").map(|s| s.to_owned()).collect() } else { vec![] }
 }
 
 pub(crate) fn extract_move_target(move_opt: Option<String>) -> (String, bool) {
@@ -45,19 +44,7 @@ pub(crate) fn extract_move_target(move_opt: Option<String>) -> (String, bool) {
   (move_target.clone(), move_target.len() > 0)
 }
 
-pub(crate) fn extract_timestamp(file: &DirEntry) -> u64 {
-    let mut ts = 0u64;
-    if let Ok(meta) = file.metadata() {
-        if let Ok(mod_time) = meta.modified() {
-            if let Ok(ts_val) = mod_time.duration_since(UNIX_EPOCH) {
-                ts = ts_val.as_secs();
-            }
-        }
-    }
-    ts
-}
-
-pub(crate) fn is_in_extensions(ext: &str, extensions: &Vec<String>) -> bool {
+pub(crate) fn is_in_extensions(ext: &str, extensions: &[String]) -> bool {
     if extensions.len() > 0 {
         extensions.iter().any(|e| matches_empty_extension_ref(e, ext))
     } else {
@@ -65,169 +52,107 @@ pub(crate) fn is_in_extensions(ext: &str, extensions: &Vec<String>) -> bool {
     }
 }
 
-pub(crate) fn is_not_in_extensions(ext: &str, extensions: &Vec<String>) -> bool {
-    extensions.iter().any(|e| matches_empty_extension_ref(e, ext)) == false
+pub(crate) fn is_not_in_extensions(ext: &str, extensions: &[String]) -> bool {
+    !extensions.iter().any(|e| matches_empty_extension_ref(e, ext))
 }
 
 fn matches_empty_extension_ref(match_ext: &str, file_ext: &str) -> bool {
-  match_ext == file_ext || (match_ext == "_" && file_ext == "")
+  match_ext == file_ext || (match_ext == "_" && file_ext.is_empty())
 }
 
-pub(crate) fn numeric_string_to_f64(num_chars: &Vec<char>) -> f64 {
-    let min_str = num_chars.iter().collect::<String>();
-    if let Ok(min_val) = min_str.parse::<f64>() {
-        min_val
-    } else {
-        0f64
+pub(crate) fn size_unit_multiplier(unit: &str) -> Option<u64> {
+    match unit {
+        "" | "b" => Some(1),
+        "k" | "kb" | "kib" => Some(1024),
+        "m" | "mb" | "mib" => Some(1024 * 1024),
+        "g" | "gb" | "gib" => Some(1024 * 1024 * 1024),
+        "t" | "tb" | "tib" => Some(1024 * 1024 * 1024 * 1024),
+        _ => None
     }
 }
 
-pub(crate) fn num_unit_to_bytes_u64(num: f64, unit: char) -> u64 {
-    let unit_multiplier = match unit {
-        'k' => 1024f64,
-        'm' => 1024f64 * 1024f64,
-        'g' => 1024f64 * 1024f64 * 1024f64,
-        _ => 1f64
-    };
-    (num * unit_multiplier) as u64
-}
-
-pub(crate) fn extract_size_val(num_chars: &Vec<char>, unit: char) -> u64 {
-    let mut int_val = 0u64;
-    if num_chars.len() > 0 {
-        let num_val = numeric_string_to_f64(&num_chars);
-        if num_val > 0f64 {
-            int_val = num_unit_to_bytes_u64(num_val, unit);
-        }
+/// Parse a transfer rate in bytes per second such as `50M` or `500k/s`
+pub(crate) fn extract_rate(rate_str: &str) -> Result<u64, ArgError> {
+    let ref_str = rate_str.trim().to_lowercase();
+    let size_str = ref_str.strip_suffix("/s").unwrap_or(&ref_str);
+    let (num, unit) = split_size_unit(size_str, rate_str)?;
+    let rate = to_bytes(num, &unit);
+    if rate > 0 {
+        Ok(rate)
+    } else {
+        Err(ArgError::InvalidSize(rate_str.to_owned(), "the rate must be greater than zero".to_owned()))
     }
-    int_val
 }
 
-pub(crate) fn extract_string_parts(str_val: &str) -> Vec<String> {
-  str_val.to_owned().split("-").into_iter().map(|s| s.to_owned()).collect::<Vec<String>>()
+/// Split a size such as `2.5m` into its number and unit suffix
+fn split_size_unit(size_str: &str, input: &str) -> Result<(f64, String), ArgError> {
+    let num_str: String = size_str.chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+    let unit = size_str[num_str.len()..].trim().to_owned();
+    match num_str.parse::<f64>() {
+        Ok(num) => {
+            if size_unit_multiplier(&unit).is_some() {
+                Ok((num, unit))
+            } else {
+                Err(ArgError::InvalidSize(input.to_owned(), format!("unknown unit '{}', use k, m, g or t", unit)))
+            }
+        },
+        Err(_) => Err(ArgError::InvalidSize(input.to_owned(), "expected a number with an optional k, m, g or t unit".to_owned()))
+    }
 }
 
-pub(crate) fn extract_first_suffix_letter(str_val: &str) -> char {
-  let first_char = str_val.to_owned().to_lowercase().chars().into_iter().find(|c| c.is_ascii_alphabetic());
-  if let Some(char) = first_char {
-    char
-  } else {
-    '-'
-  }
+fn to_bytes(num: f64, unit: &str) -> u64 {
+    (num * size_unit_multiplier(unit).unwrap_or(1) as f64) as u64
 }
 
-/// Extract the age as a 64-bi float day values from text paraneter with a unit suffix
-pub(crate) fn extract_age(size_str: &str) -> f64 {
-  let chars: Vec<char> = size_str.to_lowercase().chars().into_iter().collect();
-  let mut num = 0f64;
-  let mut num_chars: Vec<char> = vec![];
-  let mut has_number = false;
-  let mut div = 1f64;
-  let mut has_unit = false;
-  for (index, char) in chars.into_iter().enumerate() {
-    if char.is_numeric() {
-      has_number = true;
+/// Parse a size range such as `5M`, `1-2M`, `500k-1.5g` or `,5M`.
+/// One size alone is the minimum. A leading comma or dash sets only the maximum.
+/// If only one bound has a unit, the other bound inherits it.
+pub(crate) fn extract_sizes(size_str: &str) -> Result<(u64, u64), ArgError> {
+    let ref_str = size_str.trim().to_lowercase();
+    if ref_str.len() < 1 {
+        return Ok((0, 0));
     }
-    if (char.is_numeric() || (char == '.' && has_number)) && !has_unit {
-      num_chars.push(char);
-    } else if index > 0 && has_number && char != ',' && !char.is_numeric() && div == 1f64 {
-      div = match char {
-        's' => 86400f64,
-        'm' => 1440f64,
-        'h' => 24f64,
-        'w' => 1f64/7f64,
-        'y' => 1f64/365.25f64,
-        _ => 1f64
-      };
-      has_unit = true;
+    let parts: Vec<&str> = ref_str.split(['-', ',']).map(|s| s.trim()).collect();
+    if parts.len() > 2 {
+        return Err(ArgError::InvalidSize(size_str.to_owned(), "expected a single size or a min-max range".to_owned()));
     }
-  }
-  let num_str = num_chars.iter().collect::<String>();
-  if let Ok(num_fl) = num_str.parse::<f64>() {
-    num = num_fl;
-  }
-  num / div
-}
-
-pub(crate) fn extract_sizes(size_str: &str) -> (u64, u64) {
-    let mut min = 0u64;
-    let mut max = 0u64;
-    let ref_str = size_str.trim().to_lowercase();
-    if ref_str.len() > 0 {
-        let mut is_in_num = false;
-        let mut first_char = '#';
-        let mut has_min = false;
-        let mut min_chars: Vec<char> = vec![];
-        let mut min_unit = 'b';
-        let mut max_unit = 'b';
-        let mut max_chars: Vec<char> = vec![];
-        for (ci, char) in  ref_str.chars().into_iter().enumerate() {
-            if ci == 0 {
-                first_char = char;
-            }
-            let is_max = has_min || first_char == ',';
-            let mut capture = false;
-            if char.is_numeric() {
-                is_in_num = true;
-                capture = true;
-            } else if is_in_num {
-                if char == '.' {
-                    capture = true;
-                } else if char != ',' {
-                    is_in_num = false;
-                    let unit = match char {
-                        'k' | 'm' | 'g' => char,
-                        _ => '#'
-                    };
-                    if unit != '#' {
-                        if is_max {
-                            max_unit = unit;
-                        } else {
-                            min_unit = unit;
-                        }
-                    }
-                }
-            } else {
-                is_in_num = false;
-            }
-            if ci > 0 && min_chars.len() > 0 && !is_in_num {
-                has_min = true;
-            }
-            if capture {
-                if is_max {
-                    max_chars.push(char);
-                } else {
-                    min_chars.push(char);
-                }
-            }
-        }
-        let min_ref_unit = if min_unit == 'b' && max_unit != 'b' { max_unit } else { min_unit };
-        min = extract_size_val(&min_chars, min_ref_unit);
-        if max_chars.len() > 0 {
-            let max_ref_unit = if min_ref_unit != 'b' && max_unit == 'b' { min_ref_unit } else { max_unit };
-            let max_ref_val = extract_size_val(&max_chars, max_ref_unit);
-            if max_ref_val > min {
-                max = max_ref_val;
-            }
-        }
+    let min_part = parts[0];
+    let max_part = parts.get(1).copied();
+    if max_part == Some("") {
+        return Err(ArgError::InvalidSize(size_str.to_owned(), "the range has no upper bound".to_owned()));
     }
-    (min, max)
+    let min_pair = if min_part.len() > 0 { Some(split_size_unit(min_part, size_str)?) } else { None };
+    let max_pair = if let Some(max_str) = max_part { Some(split_size_unit(max_str, size_str)?) } else { None };
+    let (min, max) = match (min_pair, max_pair) {
+        (Some((min_num, min_unit)), Some((max_num, max_unit))) => {
+            let min_ref_unit = if min_unit.len() < 1 { max_unit.clone() } else { min_unit };
+            let max_ref_unit = if max_unit.len() < 1 { min_ref_unit.clone() } else { max_unit };
+            (to_bytes(min_num, &min_ref_unit), to_bytes(max_num, &max_ref_unit))
+        },
+        (Some((min_num, min_unit)), None) => (to_bytes(min_num, &min_unit), 0),
+        (None, Some((max_num, max_unit))) => (0, to_bytes(max_num, &max_unit)),
+        (None, None) => (0, 0)
+    };
+    if max > 0 && max < min {
+        return Err(ArgError::InvalidSize(size_str.to_owned(), "the maximum is smaller than the minimum".to_owned()));
+    }
+    Ok((min, max))
 }
 
 pub(crate) fn size_display(size: u64, prefix: &str) -> String {
     if size > 0 { format!("{} {}", prefix, smart_size(size)) } else { "".to_string() }
 }
 
-pub(crate) fn to_relative_parts(current: &DirEntry, root: &Option<DirEntry>) -> Vec<String> {
+pub(crate) fn to_relative_parts(current: &FileEntry, root: &Option<FileEntry>) -> Vec<String> {
   path_to_relative_parts(current.path(), root)
 }
 
-pub(crate) fn path_to_relative_parts(current_path: &Path, root: &Option<DirEntry>) -> Vec<String> {
+pub(crate) fn path_to_relative_parts(current_path: &Path, root: &Option<FileEntry>) -> Vec<String> {
     if let Some(root_ref) = root {
-        let root_comps = root_ref.path().components().into_iter().collect::<Vec<_>>();
+        let root_comps = root_ref.path().components().collect::<Vec<_>>();
         let num_root_parts = root_comps.len();
         let mut parts: Vec<String> = vec![];
-        for (ci, item) in current_path.components().into_iter().enumerate() {
+        for (ci, item) in current_path.components().enumerate() {
             let item_str = item.as_os_str().to_str().unwrap_or("");
             let par_ref = if ci < num_root_parts { root_comps.get(ci) } else { None };
             let is_root_part = par_ref.is_some() && par_ref.unwrap().as_os_str().to_str().unwrap_or("") == item_str;
@@ -241,31 +166,11 @@ pub(crate) fn path_to_relative_parts(current_path: &Path, root: &Option<DirEntry
     }
 }
 
-pub(crate) fn get_num_subdirectories(parent: &DirEntry) -> usize {
-  let mut num_subs:usize = 0;
-  let result: Result<fs::ReadDir, std::io::Error>  = fs::read_dir(parent.clone().into_path());
-  if let Ok(rd) = result {
-    for entry in  rd {
-      if let Ok(sub_entry) = entry {
-        if sub_entry.path().is_dir() {
-          num_subs += 1;
-        }
-      }
-    }
-  }
-  num_subs
-}
-
-pub(crate) fn path_to_string(ref_path: &Path) -> String {
-  let parts = ref_path.components().into_iter().map(|c| c.as_os_str().to_str().unwrap_or("")).collect::<Vec<_>>();
-  parts.join("/")
-}
-
-pub(crate) fn to_relative_path(current: &DirEntry, root: &Option<DirEntry>) -> String {
+pub(crate) fn to_relative_path(current: &FileEntry, root: &Option<FileEntry>) -> String {
   path_to_relative_path(current.path(), root)
 }
 
-pub(crate) fn path_to_relative_path(current_path: &Path, root: &Option<DirEntry>) -> String {
+pub(crate) fn path_to_relative_path(current_path: &Path, root: &Option<FileEntry>) -> String {
   let parts = path_to_relative_parts(current_path, root);
     if parts.len() > 0 {
         parts.join("/").to_owned()
@@ -278,117 +183,31 @@ pub(crate) fn directory_ref_starts_with_separator(path_ref: &str) -> bool {
   path_ref.starts_with(MAIN_SEPARATOR)
 }
 
-pub(crate) fn to_os_directory_string(dirs: &Vec<String>) -> String {
+pub(crate) fn to_os_directory_string(dirs: &[String]) -> String {
   format!("{}{}",MAIN_SEPARATOR, dirs.join(MAIN_SEPARATOR.to_string().as_str()))
 }
 
-pub(crate) fn strings_contain_str(dirs: &Vec<String>, segment: &str) -> bool {
-  dirs.into_iter().any(|d2| segment.to_owned() == d2.to_owned())
+pub(crate) fn strings_contain_str(dirs: &[String], segment: &str) -> bool {
+  dirs.iter().any(|d2| segment == *d2)
 }
 
-pub(crate) fn is_not_excluded_dir(resource: &DirEntry, e_dirs: &Vec<String>, root_ref: &Option<DirEntry>) -> bool {
+pub(crate) fn is_not_excluded_dir(resource: &FileEntry, e_dirs: &[String], root_ref: &Option<FileEntry>) -> bool {
   if e_dirs.len() > 0 {
     let dirs = to_relative_parts(resource, root_ref);
-    e_dirs.into_iter().any(|d| {
+    !e_dirs.iter().any(|d| {
       if directory_ref_starts_with_separator(d) {
         to_os_directory_string(&dirs).starts_with(d)
       } else {
         strings_contain_str(&dirs, d)
       }
-    }) == false
+    })
   } else {
     true
   }
 }
 
-pub(crate) fn is_not_in_hidden_dir(resource: &DirEntry, root_ref: &Option<DirEntry>) -> bool {
-  let dirs = to_relative_parts(resource, root_ref);
-  dirs.into_iter().any(|d| d.starts_with(".")) == false
-}
-
-pub(crate) fn extract_day_ref_pairs(days: f64) -> (f64, String) {
-  let mut unit = "day";
-  let mut num = days;
-  if days < 0.5 {
-    if days >= 1f64/24f64 {
-      num *= 24f64;
-      unit = "hour";
-    } else if days >= 1f64/1440f64 {
-      num *= 1440f64;
-      unit = "min";
-    } else {
-      num *= 86400f64;
-      unit = "sec";
-    }
-  } else if days > 730.5 {
-    unit = "year";
-    num /= 362.25;
-  }
-  (num, unit.to_owned())
-}
-
-pub(crate) fn smart_dec_format(num: f64) -> String {
-  let num_fmt = format!("{:.3}", num);
-  let num_parts = num_fmt.split(".").into_iter().collect::<Vec<&str>>();
-  let base_num = num_parts.get(0).unwrap().to_owned();
-  let mut dec_chars:Vec<char> = vec![];
-  if num_parts.len() > 1 {
-    let second = num_parts.get(1).unwrap().chars().rev().into_iter();
-    let mut is_zero = true;
-    for (_index, digit) in second.enumerate() {
-      if digit != '0' && is_zero {
-        dec_chars.push(digit);
-        is_zero = false;
-      }
-    }
-  }
-  let has_decimals = dec_chars.len() > 0;
-  if has_decimals { 
-    format!("{}.{}", base_num, dec_chars.into_iter().collect::<String>())
-  } else { 
-    base_num.to_owned()
-  }
-}
-
-pub(crate) fn days_age_display(min: f64, max: f64) -> String {
-  let has_min = min > 0f64;
-  let has_max = max > min;
-  if has_min || has_max {
-    if has_min && has_max {
-      days_between_display(min, max)
-    } else if has_min {
-      days_part_display(min, false)
-    } else {
-      days_part_display(max, true)
-    }
-  } else {
-    "All ages".to_owned()
-  }
-}
-
-pub(crate) fn days_between_display(min: f64, max: f64) -> String {
-  let (start_num, start_unit, _spl) = to_time_unit_pairs(min);
-  let (_end_num, end_unit) = extract_day_ref_pairs(max);
-  let start_unit_text = if start_unit != end_unit { days_to_day_hours_min_secs(min) } else { start_num };
-  let end_unit_text = days_to_day_hours_min_secs(max);
-  format!("between {} and {} old", start_unit_text, end_unit_text)
-}
-
-pub(crate) fn days_part_display(days: f64, is_after: bool) -> String {
-  let start = if is_after { "newer"} else { "older" };
-  format!("{} than {}", start, days_unit_display(days))
-}
-
-pub(crate) fn days_unit_display(days: f64) -> String {
-  let unit_text = days_to_day_hours_min_secs(days);
-  format!("{} old", unit_text)
-}
-
-pub(crate) fn to_time_unit_pairs(days: f64) -> (String, String, String) {
-  let pl = if days == 1f64 { "" } else { "s"};
-  let (num, unit) = extract_day_ref_pairs(days);
-  let num_display = smart_dec_format(num);
-  (num_display, unit, pl.to_owned())
+pub(crate) fn is_hidden_entry(resource: &FileEntry) -> bool {
+  resource.file_name().to_str().unwrap_or("").starts_with(".")
 }
 
 pub(crate) fn pluralize_64(single_form: &str, plural_form: &str, count: u64) -> String {
@@ -403,53 +222,8 @@ pub(crate) fn pluralize_64(single_form: &str, plural_form: &str, count: u64) ->
   }
 }
 
-/// Convert seconds an unsigned 64-bit integer to a display string
-pub(crate) fn seconds_to_day_hours_min_secs(seconds: u64) -> String {
-  let secs_per_hour = 3600;
-  let secs_per_day = secs_per_hour * 24;
-  let has_days = seconds >= secs_per_day;
-  let show_hours = seconds < secs_per_day * 3;
-  let has_hours = seconds >= secs_per_hour;
-  let show_minutes = seconds < (secs_per_hour * 6);
-  let has_minutes = seconds >= 60;
-  let show_seconds = seconds < 60 * 5;
-  if has_days {
-    let days = seconds as f64 / secs_per_day as f64;
-    let hours = (days % 1f64) * 24f64;
-    if show_hours && hours != 0f64 {
-      format!("{:.0} {} {:.0}{}", days, pluralize_64("day", "s", days as u64), hours, "h")
-    } else {
-      format!("{:.0} {}", days, pluralize_64("day", "s", days as u64))
-    }
-  } else if has_hours {
-    let hours = seconds as f64 / secs_per_hour as f64;
-    let minutes = (hours % 1f64) * 60f64;
-    if show_minutes && minutes != 0f64 {
-      format!("{:.0}{} {:.0}{}", hours, "h", minutes, "m")
-    } else {
-      format!("{:.0}{}", hours, "h")
-    }
-  } else if has_minutes {
-    let minutes = seconds / 60;
-    let secs = seconds % 60;
-    if show_seconds && secs != 0u64 {
-      format!("{:.0}{} {:.0}{}", minutes, "m", secs, "s")
-    } else {
-      format!("{:.0}{}", minutes, "m")
-    }
-  } else {
-    format!("{:.0}{}", seconds, "s")
-  }
-}
-
-/// Convert days a 64-bit float to a display string
-pub(crate) fn days_to_day_hours_min_secs(days: f64) -> String {
-  let secs = days * 86400f64;
-  seconds_to_day_hours_min_secs(secs as u64)
-}
-
 pub(crate) fn path_string_to_file_name(path_ref: &str) -> String {
-  if let Some(last_part) = path_ref.split(MAIN_SEPARATOR).last() {
+  if let Some(last_part) = path_ref.split(MAIN_SEPARATOR).next_back() {
     last_part.to_owned()
   } else {
     path_ref.to_owned()
@@ -457,10 +231,10 @@ pub(crate) fn path_string_to_file_name(path_ref: &str) -> String {
 }
 
 pub(crate) fn path_string_to_head(path_ref: &str) -> String {
-  let parts:Vec<&str> = path_ref.split(MAIN_SEPARATOR).into_iter().collect();
+  let parts:Vec<&str> = path_ref.split(MAIN_SEPARATOR).collect();
   let num = parts.len();
   if num > 1 {
-    let head = parts.into_iter().take(num - 1).map(|s| s).collect::<Vec<&str>>().join(MAIN_SEPARATOR.to_string().as_str());
+    let head = parts.into_iter().take(num - 1).collect::<Vec<&str>>().join(MAIN_SEPARATOR.to_string().as_str());
     format!("{}{}", head, MAIN_SEPARATOR)
   } else {
     "".to_owned()
@@ -469,7 +243,7 @@ pub(crate) fn path_string_to_head(path_ref: &str) -> String {
 
 pub(crate) fn to_short_pattern(pattern: &str) -> String {
   if pattern.len() > 32 && pattern.contains("|") && pattern.contains("(") { 
-    let head = pattern.split("|").into_iter().take(2).collect::<Vec<&str>>().join("|");
+    let head = pattern.split("|").take(2).collect::<Vec<&str>>().join("|");
     format!("{}|...)", head)
   } else {
     pattern.to_owned()
@@ -481,21 +255,31 @@ mod tests {
   use super::*;
 
   #[test]
-  fn test_time_unit_display() {
-    let one_day = 1.0;
-    let unit_3hres = one_day / 8.0;
-    let text_1 = days_to_day_hours_min_secs(unit_3hres);
-    let expected_1 = "3h".to_string();
-    assert_eq!(text_1, expected_1);
-
-    let minute = one_day / 1440.0;
-    let minutes_2m_15s = minute * 2.25;
-    let text_2 = days_to_day_hours_min_secs(minutes_2m_15s);
-    let expected_2 = "2m 15s".to_string();
-    assert_eq!(text_2, expected_2);
-    let minutes_1m_45s = minute * 1.75;
-    let text_3 = days_to_day_hours_min_secs(minutes_1m_45s);
-    let expected_3 = "1m 45s".to_string();
-    assert_eq!(text_3, expected_3);
+  fn test_extract_sizes() {
+    let mb = 1024 * 1024;
+    assert_eq!(extract_sizes(""), Ok((0, 0)));
+    assert_eq!(extract_sizes("5M"), Ok((5 * mb, 0)));
+    assert_eq!(extract_sizes("1-2M"), Ok((mb, 2 * mb)));
+    assert_eq!(extract_sizes("500k-1.5mb"), Ok((500 * 1024, mb + mb / 2)));
+    assert_eq!(extract_sizes(",5M"), Ok((0, 5 * mb)));
+    assert_eq!(extract_sizes("0-5M"), Ok((0, 5 * mb)));
+    assert_eq!(extract_sizes("2048"), Ok((2048, 0)));
+  }
+
+  #[test]
+  fn test_reject_invalid_sizes() {
+    assert!(extract_sizes("5X").is_err());
+    assert!(extract_sizes("abc").is_err());
+    assert!(extract_sizes("10-").is_err());
+    assert!(extract_sizes("5M-1M").is_err());
+    assert!(extract_sizes("1-2-3").is_err());
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn test_extract_rate() {
+    assert_eq!(extract_rate("50M"), Ok(50 * 1024 * 1024));
+    assert_eq!(extract_rate("500k/s"), Ok(500 * 1024));
+    assert!(extract_rate("0").is_err());
+    assert!(extract_rate("fast").is_err());
+  }
+}
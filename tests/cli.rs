@@ -0,0 +1,360 @@
+//! Run the fileorg binary over generated fixture trees
+
+mod common;
+
+use common::{Fixture, report_paths, stdout};
+
+fn paths(list: &[&str]) -> Vec<String> {
+  let mut paths: Vec<String> = list.iter().map(|path| path.to_string()).collect();
+  paths.sort();
+  paths
+}
+
+#[test]
+fn test_listing_skips_hidden_files_and_directories() {
+  let fixture = Fixture::sample();
+  let report = fixture.json(&[fixture.root_str()]);
+  assert_eq!(report_paths(&report), paths(&[
+    "README.md", "docs/report.pdf", "docs/notes.txt", "docs/drafts/old_notes.txt",
+    "photos/IMG_001.jpg", "photos/IMG_002.jpg", "photos/holiday.png",
+    "node_modules/pkg/index.js", "src/main.rs", "src/node_modules/lib/mod.js",
+  ]));
+  assert_eq!(report["totals"]["files"], 10);
+  assert_eq!(report["totals"]["size"], 22800);
+  let report = fixture.json(&[fixture.root_str(), "--hidden", "-e", "jpg,_"]);
+  assert_eq!(report_paths(&report), paths(&["photos/IMG_001.jpg", "photos/IMG_002.jpg", "photos/.thumbs/IMG_001.jpg", ".env"]));
+}
+
+#[test]
+fn test_age_size_and_extension_filters() {
+  let fixture = Fixture::sample();
+  let root = fixture.root_str();
+  assert_eq!(report_paths(&fixture.json(&[root, "-b", "1y"])), paths(&["docs/report.pdf", "docs/drafts/old_notes.txt", "photos/IMG_002.jpg"]));
+  assert_eq!(report_paths(&fixture.json(&[root, "-a", "1w"])), paths(&["README.md", "src/main.rs"]));
+  assert_eq!(report_paths(&fixture.json(&[root, "-s", "4K"])), paths(&["photos/IMG_001.jpg", "photos/IMG_002.jpg", "photos/holiday.png"]));
+  assert_eq!(report_paths(&fixture.json(&[root, "-s", "1K-5K"])), paths(&["docs/report.pdf", "photos/IMG_001.jpg"]));
+  assert_eq!(report_paths(&fixture.json(&[root, "-n", "jpg,png,js,md"])), paths(&["docs/report.pdf", "docs/notes.txt", "docs/drafts/old_notes.txt", "src/main.rs"]));
+  assert_eq!(report_paths(&fixture.json(&[root, "-d", "1"])), paths(&["README.md"]));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_time_field() {
+  use std::time::{Duration, SystemTime};
+  let fixture = Fixture::new();
+  fixture.file("cache/old.bin", 10, 40).file("cache/fresh.bin", 10, 0);
+  let now = SystemTime::now();
+  let month_ago = now - Duration::from_secs(40 * 86400);
+  // old.bin was modified long ago but read today, fresh.bin the other way round
+  set_accessed(&fixture.path("cache/old.bin"), now);
+  set_accessed(&fixture.path("cache/fresh.bin"), month_ago);
+  let root = fixture.root_str();
+  assert_eq!(report_paths(&fixture.json(&[root, "-b", "30"])), paths(&["cache/old.bin"]));
+  assert_eq!(report_paths(&fixture.json(&[root, "-b", "30", "--time-field", "accessed"])), paths(&["cache/fresh.bin"]));
+  // both were created and changed just now
+  assert_eq!(report_paths(&fixture.json(&[root, "-b", "30", "--time-field", "changed"])), paths(&[]));
+  assert!(stdout(&fixture.run(&[root, "--time-field", "accessed"])).contains("time field   accessed"));
+  assert_eq!(fixture.run(&[root, "--time-field", "mtime"]).status.code(), Some(2));
+}
+
+#[cfg(unix)]
+fn set_accessed(path: &std::path::Path, time: std::time::SystemTime) {
+  use std::fs::{File, FileTimes};
+  let file = File::options().write(true).open(path).unwrap();
+  file.set_times(FileTimes::new().set_accessed(time)).unwrap();
+}
+
+#[test]
+fn test_excluded_directories() {
+  let fixture = Fixture::sample();
+  let root = fixture.root_str();
+  let report = fixture.json(&[root, "-e", "js", "-q", "node_modules"]);
+  assert_eq!(report_paths(&report), Vec::<String>::new());
+  // a leading separator only excludes the directory below the root
+  let report = fixture.json(&[root, "-e", "js", "-q", "/node_modules"]);
+  assert_eq!(report_paths(&report), paths(&["src/node_modules/lib/mod.js"]));
+  let report = fixture.json(&[root, "-q", "photos,docs,src,node_modules"]);
+  assert_eq!(report_paths(&report), paths(&["README.md"]));
+}
+
+#[test]
+fn test_name_patterns() {
+  let fixture = Fixture::sample();
+  let root = fixture.root_str();
+  assert_eq!(report_paths(&fixture.json(&[root, "--starts-with", "IMG"])), paths(&["photos/IMG_001.jpg", "photos/IMG_002.jpg"]));
+  assert_eq!(report_paths(&fixture.json(&[root, "--ends-with", "notes"])), paths(&["docs/notes.txt", "docs/drafts/old_notes.txt"]));
+  assert_eq!(report_paths(&fixture.json(&[root, "-p", "notes", "-o", "old"])), paths(&["docs/notes.txt"]));
+  assert_eq!(report_paths(&fixture.json(&[root, "-x", "-p", r"^IMG_\d{3}\.jpg$"])), paths(&["photos/IMG_001.jpg", "photos/IMG_002.jpg"]));
+  // in simple mode, the same pattern is matched literally
+  assert_eq!(report_paths(&fixture.json(&[root, "-p", r"^IMG_\d{3}\.jpg$"])), Vec::<String>::new());
+}
+
+#[test]
+fn test_text_overview_and_groups() {
+  let fixture = Fixture::sample();
+  let output = fixture.run(&[fixture.root_str(), "-l", "-g"]);
+  assert!(output.status.success());
+  let text = stdout(&output);
+  assert!(text.contains("BY EXTENSION"));
+  assert!(text.lines().any(|line| line.split_whitespace().collect::<Vec<&str>>().starts_with(&["jpg", "2", "files"])));
+  assert!(text.lines().any(|line| line.trim_end().ends_with("photos/IMG_002.jpg")));
+  assert!(text.lines().any(|line| line.starts_with("total files") && line.contains("10")));
+}
+
+#[test]
+fn test_copy_keeps_relative_paths() {
+  let fixture = Fixture::sample();
+  let target = Fixture::new();
+  let report = fixture.json(&[fixture.root_str(), "-e", "jpg,txt", "--copy", target.root_str()]);
+  assert_eq!(target.files(), paths(&["photos/IMG_001.jpg", "photos/IMG_002.jpg", "docs/notes.txt", "docs/drafts/old_notes.txt"]));
+  assert!(fixture.exists("photos/IMG_001.jpg"));
+  assert_eq!(report["action"]["name"], "copy");
+  assert_eq!(report["action"]["result"]["files"], 4);
+  assert_eq!(report["action"]["result"]["failed"], 0);
+}
+
+#[test]
+fn test_move_creates_the_target_when_confirmed() {
+  let fixture = Fixture::sample();
+  let target = fixture.path_str("archive/2024");
+  let output = fixture.run_with_input(&[fixture.root_str(), "-b", "1y", "--move", &target], "n\n");
+  assert!(output.status.success());
+  assert!(!fixture.exists("archive"));
+  assert!(fixture.exists("docs/report.pdf"));
+
+  let output = fixture.run_with_input(&[fixture.root_str(), "-b", "1y", "--move", &target], "y\n");
+  assert!(output.status.success());
+  assert_eq!(fixture.files_in("archive/2024"), paths(&["docs/report.pdf", "docs/drafts/old_notes.txt", "photos/IMG_002.jpg"]));
+  assert!(!fixture.exists("docs/report.pdf"));
+  assert!(!fixture.exists("photos/IMG_002.jpg"));
+  assert!(fixture.exists("photos/IMG_001.jpg"));
+}
+
+#[test]
+fn test_delete() {
+  let fixture = Fixture::sample();
+  let root = fixture.root_str();
+  let output = fixture.run_with_input(&[root, "-e", "js", "--delete"], "n\n");
+  assert!(output.status.success());
+  assert!(fixture.exists("node_modules/pkg/index.js"));
+
+  let report = fixture.json(&[root, "-e", "js", "-q", "/node_modules", "--delete", "--force"]);
+  assert_eq!(report["files"][0]["deleted"], true);
+  assert_eq!(report["action"]["result"]["files"], 1);
+  assert!(!fixture.exists("src/node_modules/lib/mod.js"));
+  assert!(fixture.exists("node_modules/pkg/index.js"));
+  assert_eq!(fixture.files().len(), 11);
+}
+
+#[test]
+fn test_exit_codes() {
+  let fixture = Fixture::sample();
+  assert_eq!(fixture.run(&[&fixture.path_str("missing/dir")]).status.code(), Some(1));
+  assert_eq!(fixture.run(&[fixture.root_str(), "--copy", "a", "--move", "b"]).status.code(), Some(2));
+  assert_eq!(fixture.run(&[fixture.root_str(), "-s", "lots"]).status.code(), Some(2));
+  assert_eq!(fixture.run(&[fixture.root_str(), "-b", "10-"]).status.code(), Some(2));
+  assert_eq!(fixture.run(&[fixture.root_str(), "-b", "14-7"]).status.code(), Some(2));
+  assert_eq!(fixture.run(&[fixture.root_str(), "--json", "--delete"]).status.code(), Some(2));
+}
+
+#[test]
+fn test_script_rules() {
+  let fixture = Fixture::sample();
+  fixture.file("photos/IMG_001.raw", 8000, 30);
+  let scripts = Fixture::new();
+  let script = scripts.path_str("rules.rhai");
+  std::fs::write(&script, r#"
+    // keep the jpg only when there is no raw file with the same name
+    fn rule(file) {
+      if file.ext == "jpg" && exists(file.dir + "/" + file.stem + ".raw") {
+        return false;
+      }
+      if file.ext == "raw" {
+        return "raw/" + file.name;
+      }
+      #{ name: "small_" + file.name }
+    }
+  "#).unwrap();
+  let root = fixture.root_str();
+  let report = fixture.json(&[root, "-e", "jpg,raw", "--script", &script]);
+  assert_eq!(report_paths(&report), paths(&["photos/IMG_001.raw", "photos/IMG_002.jpg"]));
+
+  let target = Fixture::new();
+  let report = fixture.json(&[root, "-e", "jpg,raw", "--script", &script, "--copy", target.root_str()]);
+  assert_eq!(report["action"]["result"]["files"], 2);
+  assert_eq!(target.files(), paths(&["raw/IMG_001.raw", "photos/small_IMG_002.jpg"]));
+
+  std::fs::write(&script, "fn keep(file) { true }").unwrap();
+  assert_eq!(fixture.run(&[root, "--script", &script]).status.code(), Some(2));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exec_commands() {
+  let fixture = Fixture::sample();
+  fixture.file("photos/summer trip.jpg", 100, 5);
+  let root = fixture.root_str();
+  let output = fixture.run(&[root, "-e", "jpg", "--exec", "cp {path} {dir}/{stem}_small.{ext}"]);
+  assert!(output.status.success());
+  assert!(fixture.exists("photos/summer trip_small.jpg"));
+  assert!(fixture.exists("photos/IMG_002_small.jpg"));
+
+  let output = fixture.run(&[root, "-e", "jpg", "-o", "small", "--exec-batch", "echo {relpath}", "-j", "4"]);
+  let text = stdout(&output);
+  let lines: Vec<&str> = text.lines().filter(|line| line.contains("IMG_001.jpg")).collect();
+  assert_eq!(lines.len(), 1);
+  assert!(lines[0].contains("summer trip.jpg") && lines[0].contains("IMG_002.jpg"));
+
+  let output = fixture.run(&[root, "-e", "jpg", "-o", "small", "--exec", "test {size} -gt 5500", "--json"]);
+  assert_eq!(output.status.code(), Some(1));
+  let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+  assert_eq!(report["action"]["name"], "exec");
+  assert_eq!(report["action"]["result"]["files"], 1);
+  assert_eq!(report["action"]["result"]["failed"], 2);
+  assert_eq!(report["errors"][0]["kind"], "command failed");
+
+  let output = fixture.run(&[root, "-e", "md", "--exec", "echo {{literal}} {name}"]);
+  assert!(stdout(&output).contains("{literal} README.md"));
+  assert_eq!(fixture.run(&[root, "--exec", "echo {base}"]).status.code(), Some(2));
+  assert_eq!(fixture.run(&[root, "--exec", "echo", "--delete"]).status.code(), Some(2));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_before_and_after_hooks() {
+  let fixture = Fixture::sample();
+  let logs = Fixture::new();
+  let root = fixture.root_str();
+  let target = fixture.path_str("archive");
+  let before_log = logs.path_str("before.txt");
+  let after_log = logs.path_str("after.json");
+  let before_hook = format!("sh -c 'echo $FILEORG_HOOK $FILEORG_ACTION $FILEORG_FILES $FILEORG_SIZE > \"$0\"' {}", before_log);
+  let after_hook = format!("sh -c 'cat > \"$0\"' {}", after_log);
+  let output = fixture.run_with_input(&[root, "-b", "1y", "--move", &target, "--before-hook", &before_hook, "--after-hook", &after_hook], "y\n");
+  assert!(output.status.success());
+  assert_eq!(std::fs::read_to_string(&before_log).unwrap().trim(), "before move 3 9200");
+  let summary: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&after_log).unwrap()).unwrap();
+  assert_eq!(summary["hook"], "after");
+  assert_eq!(summary["result"]["files"], 3);
+  assert_eq!(summary["result"]["size"], 9200);
+  assert_eq!(fixture.files_in("archive").len(), 3);
+
+  // forced deletes run during the scan, so a failing before hook stops them before any file is found
+  let output = fixture.run(&[root, "-e", "js", "--delete", "--force", "--before-hook", "false", "--after-hook", &after_hook]);
+  assert_eq!(output.status.code(), Some(1));
+  assert!(fixture.exists("node_modules/pkg/index.js"));
+  assert_eq!(fixture.run(&[root, "--after-hook", "sh -c 'exit 3'"]).status.code(), Some(1));
+}
+
+#[test]
+fn test_path_lists() {
+  let fixture = Fixture::sample();
+  let root = fixture.root_str();
+  let output = fixture.run(&[root, "-e", "jpg,png", "--print0", "--relative"]);
+  let mut listed: Vec<String> = stdout(&output).split('\0').filter(|path| !path.is_empty()).map(|path| path.to_owned()).collect();
+  listed.sort();
+  assert_eq!(listed, paths(&["photos/IMG_001.jpg", "photos/IMG_002.jpg", "photos/holiday.png"]));
+  let output = fixture.run(&[root, "-n", "jpg,png,js,md,txt,rs", "--paths-only"]);
+  assert_eq!(stdout(&output).trim(), fixture.path_str("docs/report.pdf"));
+
+  // listed files are filtered instead of walking, with relative paths from the working directory
+  let list = "docs/notes.txt\nphotos/IMG_002.jpg\nphotos/.thumbs/IMG_001.jpg\nnode_modules/pkg/index.js\nmissing.txt\n";
+  let output = fixture.run_with_input(&["--from-stdin", "-q", "node_modules", "--json"], list);
+  let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+  assert_eq!(report_paths(&report), paths(&["docs/notes.txt", "photos/IMG_002.jpg"]));
+  assert_eq!(report["errors"][0]["kind"], "not found");
+  // stdin holds the list, so actions that would prompt are rejected
+  let target = fixture.path_str("backup");
+  assert_eq!(fixture.run_with_input(&["--from-stdin", "--copy", &target], "src/main.rs\n").status.code(), Some(2));
+  assert_eq!(fixture.run_with_input(&["--from-stdin", "--delete"], "src/main.rs\n").status.code(), Some(2));
+  assert!(fixture.exists("src/main.rs"));
+  std::fs::create_dir(&target).unwrap();
+  let output = fixture.run_with_input(&["--from-stdin", "-0", "--copy", &target], "docs/drafts/old_notes.txt\0src/main.rs\0");
+  assert!(output.status.success());
+  assert_eq!(fixture.files_in("backup"), paths(&["docs/drafts/old_notes.txt", "src/main.rs"]));
+}
+
+#[test]
+fn test_listed_paths_outside_the_root_are_skipped() {
+  let fixture = Fixture::sample();
+  let outside = Fixture::new();
+  outside.file("secret.txt", 10, 1);
+  let target = fixture.path_str("backup");
+  std::fs::create_dir(&target).unwrap();
+  let list = format!("docs/notes.txt\n{}\ndocs/../../{}/secret.txt\n", outside.path_str("secret.txt"), outside.root().file_name().unwrap().to_string_lossy());
+  for roots in [vec!["docs"], vec!["docs", "photos"]] {
+    let mut args = roots.clone();
+    args.extend(["--from-stdin", "--copy", &target, "--json"]);
+    let output = fixture.run_with_input(&args, &list);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let root_report = if roots.len() > 1 { &report["roots"][0] } else { &report };
+    assert_eq!(root_report["errors"][0]["kind"], "outside root");
+    assert_eq!(root_report["errors"].as_array().unwrap().len(), 2);
+  }
+  assert!(!fixture.files().iter().any(|file| file.contains("secret")));
+  assert!(outside.exists("secret.txt"));
+}
+
+#[test]
+fn test_multiple_roots() {
+  let fixture = Fixture::sample();
+  let report = fixture.json(&["docs", "photos", "src", "-b", "1y"]);
+  let roots: Vec<&str> = report["roots"].as_array().unwrap().iter().map(|root| root["path"].as_str().unwrap()).collect();
+  assert_eq!(roots.len(), 3);
+  assert!(roots[0].ends_with("docs") && roots[2].ends_with("src"));
+  assert_eq!(report["totals"]["files"], 3);
+  assert_eq!(report["totals"]["size"], 9200);
+
+  // each root keeps its structure in a subfolder of the target named after it
+  let target = fixture.path_str("archive");
+  std::fs::create_dir(&target).unwrap();
+  let output = fixture.run(&["docs", "photos", "src", "-b", "1y", "--move", &target]);
+  assert!(output.status.success());
+  assert_eq!(fixture.files_in("archive"), paths(&["docs/drafts/old_notes.txt", "docs/report.pdf", "photos/IMG_002.jpg"]));
+  assert!(stdout(&output).contains("COMBINED"));
+  // shell-expanded file names are still combined into one name pattern
+  let output = fixture.run(&[&fixture.path_str("photos/IMG_001.jpg"), &fixture.path_str("photos/holiday.png"), "--paths-only"]);
+  assert_eq!(stdout(&output).lines().count(), 2);
+  assert_eq!(fixture.run(&["docs", "missing"]).status.code(), Some(1));
+}
+
+#[test]
+fn test_sorted_and_top_listings() {
+  let fixture = Fixture::sample();
+  let root = fixture.root_str();
+  let text = stdout(&fixture.run(&[root, "--top", "3"]));
+  let listed: Vec<&str> = text.lines().skip(1).take(3).map(|line| line.rsplit('\t').next().unwrap()).collect();
+  assert_eq!(listed, vec!["photos/holiday.png", "photos/IMG_002.jpg", "photos/IMG_001.jpg"]);
+  let text = stdout(&fixture.run(&[root, "--top", "2", "--sort", "age"]));
+  assert!(text.starts_with("TOP 2 (oldest)"));
+  assert!(text.contains("docs/drafts/old_notes.txt") && text.contains("photos/IMG_002.jpg"));
+  let text = stdout(&fixture.run(&[root, "-l", "--sort", "size", "--reverse", "--limit", "1"]));
+  assert!(text.contains("photos/IMG_001.jpg") && !text.contains("photos/holiday.png"));
+  assert!(text.contains("and 2 more"));
+  assert_eq!(fixture.run(&[root, "--top", "3", "--stream"]).status.code(), Some(2));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_interrupt_at_a_prompt_exits_at_once() {
+  use std::io::Read;
+  use std::process::{Command, Stdio};
+  let fixture = Fixture::sample();
+  let mut child = Command::new(env!("CARGO_BIN_EXE_file-organiser"))
+    .args([fixture.root_str(), "-e", "md", "--delete", "--quiet"])
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+  let mut stderr = child.stderr.take().unwrap();
+  let mut text = String::new();
+  let mut byte = [0u8; 1];
+  while !text.ends_with("(Y/n)") && stderr.read(&mut byte).unwrap() > 0 {
+    text.push(byte[0] as char);
+  }
+  Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+  // stdin stays open, so only the signal can end the prompt
+  assert_eq!(child.wait().unwrap().code(), Some(130));
+  assert!(fixture.exists("README.md"));
+}
@@ -0,0 +1,159 @@
+//! Fixture trees and helpers to run the fileorg binary in integration tests
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, SystemTime};
+use serde_json::Value;
+use tempfile::TempDir;
+
+const DAY_SECS: u64 = 86400;
+
+/// A temporary directory tree, removed when dropped
+pub struct Fixture {
+  dir: TempDir,
+}
+
+impl Fixture {
+  pub fn new() -> Self {
+    Fixture { dir: tempfile::tempdir().expect("could not create a temporary directory") }
+  }
+
+  /// A tree with nested, hidden and excluded directories and files of known sizes and ages
+  pub fn sample() -> Self {
+    let fixture = Fixture::new();
+    fixture
+      .file("README.md", 500, 2)
+      .file(".env", 10, 2)
+      .file("docs/report.pdf", 3000, 400)
+      .file("docs/notes.txt", 100, 10)
+      .file("docs/drafts/old_notes.txt", 200, 800)
+      .file("photos/IMG_001.jpg", 5000, 30)
+      .file("photos/IMG_002.jpg", 6000, 500)
+      .file("photos/holiday.png", 7000, 60)
+      .file("photos/.thumbs/IMG_001.jpg", 50, 30)
+      .file("node_modules/pkg/index.js", 300, 100)
+      .file("src/main.rs", 400, 1)
+      .file("src/node_modules/lib/mod.js", 300, 100);
+    fixture
+  }
+
+  pub fn root(&self) -> &Path {
+    self.dir.path()
+  }
+
+  pub fn path(&self, relative_path: &str) -> PathBuf {
+    self.root().join(relative_path)
+  }
+
+  pub fn root_str(&self) -> &str {
+    self.root().to_str().unwrap()
+  }
+
+  pub fn path_str(&self, relative_path: &str) -> String {
+    self.path(relative_path).to_string_lossy().to_string()
+  }
+
+  /// Add a file of the given size, last modified the given number of days ago
+  pub fn file(&self, relative_path: &str, size: usize, days_old: u64) -> &Self {
+    let path = self.path(relative_path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let mut file = File::create(&path).unwrap();
+    file.write_all(&vec![b'x'; size]).unwrap();
+    file.set_modified(SystemTime::now() - Duration::from_secs(days_old * DAY_SECS)).unwrap();
+    self
+  }
+
+  pub fn exists(&self, relative_path: &str) -> bool {
+    self.path(relative_path).exists()
+  }
+
+  /// Relative paths of all files in the tree, including hidden ones, sorted
+  pub fn files(&self) -> Vec<String> {
+    let mut files = vec![];
+    collect_files(self.root(), self.root(), &mut files);
+    files.sort();
+    files
+  }
+
+  /// Files below a subdirectory, relative to it
+  pub fn files_in(&self, relative_dir: &str) -> Vec<String> {
+    let prefix = format!("{}/", relative_dir);
+    self.files().into_iter().filter_map(|file| file.strip_prefix(&prefix).map(|rest| rest.to_owned())).collect()
+  }
+
+  /// Run fileorg with the tree as the working directory and no input for prompts
+  pub fn run(&self, args: &[&str]) -> Output {
+    self.run_with_input(args, "")
+  }
+
+  /// Run fileorg and answer its prompts with the given input
+  pub fn run_with_input(&self, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_file-organiser"))
+      .args(args)
+      .arg("--quiet")
+      .current_dir(self.root())
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .expect("could not run fileorg");
+    // fileorg may exit before reading its input, e.g. when rejecting the arguments
+    if let Err(error) = child.stdin.take().unwrap().write_all(input.as_bytes()) {
+      assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+    }
+    child.wait_with_output().unwrap()
+  }
+
+  /// Run fileorg with --json and parse the report
+  pub fn json(&self, args: &[&str]) -> Value {
+    let mut json_args = args.to_vec();
+    json_args.push("--json");
+    let output = self.run(&json_args);
+    serde_json::from_slice(&output.stdout).unwrap_or_else(|error| panic!("invalid JSON report ({}): {}", error, stdout(&output)))
+  }
+}
+
+fn collect_files(dir: &Path, root: &Path, files: &mut Vec<String>) {
+  for entry in fs::read_dir(dir).unwrap().flatten() {
+    let path = entry.path();
+    if path.is_dir() {
+      collect_files(&path, root, files);
+    } else {
+      files.push(path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"));
+    }
+  }
+}
+
+/// Relative paths of the files in a JSON report, sorted
+pub fn report_paths(report: &Value) -> Vec<String> {
+  let mut paths: Vec<String> = report["files"].as_array().unwrap().iter()
+    .map(|file| file["relative_path"].as_str().unwrap().to_owned())
+    .collect();
+  paths.sort();
+  paths
+}
+
+/// Standard output without colour codes
+pub fn stdout(output: &Output) -> String {
+  strip_colours(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn strip_colours(text: &str) -> String {
+  let mut plain = String::with_capacity(text.len());
+  let mut chars = text.chars();
+  while let Some(c) = chars.next() {
+    if c == '\u{1b}' {
+      // skip the control sequence up to its final letter
+      for next in chars.by_ref() {
+        if next.is_ascii_alphabetic() {
+          break;
+        }
+      }
+    } else {
+      plain.push(c);
+    }
+  }
+  plain
+}